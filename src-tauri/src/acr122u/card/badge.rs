@@ -0,0 +1,322 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use keyring::Entry;
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::acr122u::card::format::{read_record_data, BadgeRecord};
+use crate::acr122u::card::utils::keys::KeyStore;
+use crate::acr122u::card::utils::layout::CardType;
+use crate::acr122u::card::write::write_data;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::settings::card_deadline;
+use crate::acr122u::utils::errors::ReaderError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The first block (Mifare Classic) or page (Ultralight and NTAG21x) of the badge.
+pub(crate) const BADGE_BLOCK: u16 = 4;
+
+/// The badge takes blocks 4 to 6 and 8 of Mifare Classic cards, skipping the trailer of sector 1,
+/// and pages 4 to 19 of NTAG21x cards. Plain Ultralight cards only have 48 bytes of user memory,
+/// they can hold a short record but not a signed badge.
+pub(crate) const BADGE_LENGTH: usize = 64;
+
+const ID_LENGTH: usize = 16;
+const UID_LENGTH: usize = 7;
+const SIGNATURE_LENGTH: usize = 16;
+
+/// The sled tree keeping the last counter issued to each employee.
+const COUNTERS_TREE: &str = "badge_counters";
+
+/// The data stored on an employee badge.
+///
+/// Stored as a `BadgeRecord` with the employee id, the UID, the issue counter
+/// and the first 16 bytes of an HMAC-SHA256 of those three fields.
+/// The UID binds the badge to the card it was written to, so copying the data to another card is useless,
+/// and the counter makes the previous badges of the employee stop working once a new one is issued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Badge {
+    pub(crate) employee_id: String,
+    pub(crate) uid: Vec<u8>,
+    pub(crate) counter: u32,
+}
+
+fn badge_error(message: &str) -> ReaderError {
+    ReaderError::BadgeError(message.to_string())
+}
+
+impl Badge {
+    /// The record of the badge, without the signature.
+    fn record(&self) -> Result<BadgeRecord, ReaderError> {
+        if self.employee_id.is_empty() || self.employee_id.len() > ID_LENGTH {
+            return Err(badge_error("Invalid employee id"));
+        }
+        if self.uid.len() != 4 && self.uid.len() != UID_LENGTH {
+            return Err(badge_error("Invalid card UID"));
+        }
+
+        Ok(BadgeRecord {
+            uid: Some(self.uid.clone()),
+            counter: Some(self.counter),
+            ..BadgeRecord::new(&self.employee_id)
+        })
+    }
+
+    fn mac(record: &BadgeRecord, key: &[u8]) -> Result<HmacSha256, ReaderError> {
+        let mut mac = HmacSha256::new_from_slice(key).map_err(|e| badge_error(&e.to_string()))?;
+        mac.update(&record.signed_bytes()?);
+        Ok(mac)
+    }
+
+    /// Encodes and signs the badge.
+    pub(crate) fn sign(&self, key: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        let mut record = self.record()?;
+        let signature = Badge::mac(&record, key)?.finalize().into_bytes();
+        record.signature = Some(signature[..SIGNATURE_LENGTH].to_vec());
+
+        record.encode()
+    }
+
+    /// Checks if the data is a badge record with a signature, without checking the signature.
+    ///
+    /// Cards written before badges were signed hold a plain id or a record without a signature, which aren't.
+    pub(crate) fn is_badge(data: &[u8]) -> bool {
+        BadgeRecord::decode(data).is_ok_and(|record| record.signature.is_some())
+    }
+
+    /// Decodes a badge read from a card and checks its signature and UID binding.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The badge as read from the card.
+    /// * `uid` - The UID of the card it was read from.
+    /// * `key` - The badge key of this install.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Badge)` - If the badge was signed with `key` for this card.
+    /// * `Err(ReaderError)` - If the data is not a badge, the signature is wrong or the badge was copied from another card.
+    pub(crate) fn verify(data: &[u8], uid: &[u8], key: &[u8]) -> Result<Badge, ReaderError> {
+        let record = BadgeRecord::decode(data)?;
+        let (Some(badge_uid), Some(counter), Some(signature)) =
+            (&record.uid, record.counter, &record.signature)
+        else {
+            return Err(badge_error("Not a badge"));
+        };
+
+        if signature.len() != SIGNATURE_LENGTH {
+            return Err(badge_error("Invalid signature"));
+        }
+        Badge::mac(&record, key)?
+            .verify_truncated_left(signature)
+            .map_err(|_| badge_error("Invalid signature"))?;
+
+        // Only checked once the signature is known to be good, a forged badge never gets this far
+        if badge_uid != uid {
+            return Err(badge_error("The badge belongs to another card"));
+        }
+
+        Ok(Badge {
+            employee_id: record.employee_id.clone(),
+            uid: badge_uid.clone(),
+            counter,
+        })
+    }
+}
+
+/// Gets the badge key of this install from the keyring, generating it on first use.
+pub(crate) fn load_badge_key() -> Result<Vec<u8>, ReaderError> {
+    let entry = Entry::new("PontuAll", "badge_key")
+        .map_err(|e| ReaderError::KeyStoreError(e.to_string()))?;
+
+    match entry.get_password() {
+        Ok(key) => hex::decode(key).map_err(|e| ReaderError::KeyStoreError(e.to_string())),
+        Err(keyring::Error::NoEntry) => {
+            let mut key = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+
+            entry
+                .set_password(&hex::encode(&key))
+                .map_err(|e| ReaderError::KeyStoreError(e.to_string()))?;
+            Ok(key)
+        }
+        Err(e) => Err(ReaderError::KeyStoreError(e.to_string())),
+    }
+}
+
+/// Gets the last counter issued to the employee, 0 if they never got a badge.
+pub(crate) fn current_counter(db: &sled::Db, employee_id: &str) -> Result<u32, ReaderError> {
+    let tree = db
+        .open_tree(COUNTERS_TREE)
+        .map_err(|e| badge_error(&e.to_string()))?;
+
+    match tree.get(employee_id.as_bytes()) {
+        Ok(Some(counter)) => {
+            bincode::deserialize(&counter).map_err(|e| badge_error(&e.to_string()))
+        }
+        Ok(None) => Ok(0),
+        Err(e) => Err(badge_error(&e.to_string())),
+    }
+}
+
+/// Saves the counter of the badge just issued to the employee.
+pub(crate) fn save_counter(
+    db: &sled::Db,
+    employee_id: &str,
+    counter: u32,
+) -> Result<(), ReaderError> {
+    let tree = db
+        .open_tree(COUNTERS_TREE)
+        .map_err(|e| badge_error(&e.to_string()))?;
+    let counter = bincode::serialize(&counter).map_err(|e| badge_error(&e.to_string()))?;
+
+    tree.insert(employee_id.as_bytes(), counter)
+        .map_err(|e| badge_error(&e.to_string()))?;
+    tree.flush().map_err(|e| badge_error(&e.to_string()))?;
+    Ok(())
+}
+
+/// Checks that the badge is the last one issued to the employee.
+pub(crate) fn check_counter(db: &sled::Db, badge: &Badge) -> Result<(), ReaderError> {
+    if badge.counter < current_counter(db, &badge.employee_id)? {
+        return Err(badge_error("The badge was replaced by a newer one"));
+    }
+
+    Ok(())
+}
+
+/// Reads the raw badge bytes from a connected card, authenticating Mifare Classic sectors with the key store.
+///
+/// Only as much as the header of the record says is read, see `read_record_data`.
+pub(crate) fn read_badge_data(
+    reader: &mut dyn CardReader,
+    card_type: CardType,
+    keys: &KeyStore,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u8>, ReaderError> {
+    read_record_data(reader, keys, card_type, BADGE_BLOCK, cancel_flag)
+}
+
+/// Reads the badge on the card and checks its signature and UID binding.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to read with.
+/// * `keys` - The key store with the key of each sector.
+/// * `badge_key` - The badge key of this install.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(Badge)` - The badge, if it was issued by this install for this card.
+/// * `Err(ReaderError)` - If the card has no valid badge or the read fails.
+pub(crate) async fn read_badge(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    badge_key: &[u8],
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<Badge, ReaderError> {
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+    let card_type = CardType::detect(reader, &atr)?;
+
+    let uid = reader.get_uid()?;
+    let data = read_badge_data(reader, card_type, keys, cancel_flag)?;
+
+    Badge::verify(&data, &uid, badge_key)
+}
+
+/// Writes a signed badge for the employee to the card on the reader.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to write with.
+/// * `keys` - The key store with the key of each sector.
+/// * `badge_key` - The badge key of this install.
+/// * `employee_id` - The id of the employee.
+/// * `counter` - The issue counter of the new badge.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(Badge)` - The badge written, bound to the UID of the card.
+/// * `Err(ReaderError)` - If the card is not supported or the write fails.
+pub(crate) async fn write_badge(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    badge_key: &[u8],
+    employee_id: &str,
+    counter: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<Badge, ReaderError> {
+    if cancel_flag.load(Ordering::Relaxed) {
+        return Err(ReaderError::OperationCancelled("Write Badge".to_string()));
+    }
+
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+    let card_type = CardType::detect(reader, &atr)?;
+
+    let badge = Badge {
+        employee_id: employee_id.to_string(),
+        uid: reader.get_uid()?,
+        counter,
+    };
+    let data = badge.sign(badge_key)?;
+
+    write_data(reader, keys, card_type, BADGE_BLOCK, &data, cancel_flag)?;
+
+    Ok(badge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_badge_signature() {
+        let key = [0x42u8; 32];
+        let uid = vec![0x04, 0xa2, 0x3b, 0x1c];
+        let badge = Badge {
+            employee_id: "hOtB6pOxiL2IQPYs".to_string(),
+            uid: uid.clone(),
+            counter: 3,
+        };
+
+        let data = badge.sign(&key).unwrap();
+        assert!(data.len() <= BADGE_LENGTH);
+        assert!(Badge::is_badge(&data));
+        assert_eq!(Badge::verify(&data, &uid, &key).unwrap(), badge);
+
+        // Copied to another card
+        assert!(Badge::verify(&data, &[0x04, 0xa2, 0x3b, 0x1d], &key).is_err());
+
+        // Signed by another install
+        assert!(Badge::verify(&data, &uid, &[0x24u8; 32]).is_err());
+
+        // Someone else's id written over the original, with a CRC that checks out
+        let mut forged = BadgeRecord::decode(&data).unwrap();
+        forged.employee_id = "aaaaaaaaaaaaaaaa".to_string();
+        let forged = forged.encode().unwrap();
+        assert!(Badge::is_badge(&forged));
+        assert!(Badge::verify(&forged, &uid, &key).is_err());
+
+        // Plain ids and records written by write_card are not badges
+        let mut legacy = b"hOtB6pOxiL2IQPYs".to_vec();
+        legacy.resize(BADGE_LENGTH, 0);
+        assert!(!Badge::is_badge(&legacy));
+        assert!(Badge::verify(&legacy, &uid, &key).is_err());
+
+        let record = BadgeRecord::new("hOtB6pOxiL2IQPYs").encode().unwrap();
+        assert!(!Badge::is_badge(&record));
+        assert!(Badge::verify(&record, &uid, &key).is_err());
+    }
+}
@@ -0,0 +1,590 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use pcsc::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::card::utils::authenticate::KeyType;
+use crate::acr122u::card::utils::keys::{authenticate_block, KeyStore, SectorKey, KEY_SLOT};
+use crate::acr122u::card::utils::layout::{sector_blocks, trailer_block, CardType};
+use crate::acr122u::card::utils::trailer::{AccessConditions, SectorTrailer};
+use crate::acr122u::card::write::write_data;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::settings::card_deadline;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// How long to wait for the card to answer again after a failed authentication.
+const RESELECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A sector of the dump.
+///
+/// * `sector` - The sector number.
+/// * `key` - The key that opened the sector, `None` if none of the keys tried did.
+/// * `blocks` - Every block of the sector as hex, the trailer last. `None` for the blocks that couldn't be read.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SectorDump {
+    pub(crate) sector: u8,
+    pub(crate) key: Option<SectorKey>,
+    pub(crate) blocks: Vec<Option<String>>,
+}
+
+/// Everything that could be read from a Mifare Classic card.
+///
+/// * `uid` - The card UID as an uppercase hex string.
+/// * `atr` - The ATR of the card as hex, empty for dumps loaded from a binary image.
+/// * `card_type` - The type of the card.
+/// * `dumped_at` - When the card was dumped, in RFC 3339. Empty for dumps loaded from a binary image.
+/// * `sectors` - Every sector of the card, in order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CardDump {
+    pub(crate) uid: String,
+    pub(crate) atr: String,
+    pub(crate) card_type: CardType,
+    pub(crate) dumped_at: String,
+    pub(crate) sectors: Vec<SectorDump>,
+}
+
+/// How a dump is saved to a file.
+///
+/// `Json` keeps which blocks couldn't be read and which key opened each sector.
+/// `Binary` is the raw 1K or 4K image used by other Mifare tools, with the known keys put back in the trailers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DumpFormat {
+    Json,
+    Binary,
+}
+
+/// A single write of a restore, data blocks come before the trailer of their sector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RestoreStep {
+    Data { block: u16, data: [u8; 16] },
+    Trailer { sector: u8, trailer: SectorTrailer },
+}
+
+fn dump_error(message: String) -> ReaderError {
+    ReaderError::DumpError(message)
+}
+
+/// The keys tried on a sector, in order: the one from the key store, then the transport keys A and B.
+fn candidate_keys(keys: &KeyStore, sector: u8) -> Vec<SectorKey> {
+    let transport_b = SectorKey {
+        key_type: KeyType::B,
+        ..SectorKey::default()
+    };
+
+    let mut candidates = vec![keys.key_for_sector(sector)];
+    for key in [SectorKey::default(), transport_b] {
+        if !candidates.contains(&key) {
+            candidates.push(key);
+        }
+    }
+
+    candidates
+}
+
+/// Connects to the card again, a failed authentication or read leaves it halted until it's selected again.
+fn reselect(reader: &mut dyn CardReader, cancel_flag: &AtomicBool) -> Result<(), ReaderError> {
+    reader.disconnect();
+    reader.connect(cancel_flag, Some(RESELECT_TIMEOUT))?;
+    Ok(())
+}
+
+fn authenticate_with(
+    reader: &mut dyn CardReader,
+    sector: u8,
+    key: &SectorKey,
+) -> Result<(), ReaderError> {
+    reader.load_key(KEY_SLOT, &key.key)?;
+    reader.authenticate(trailer_block(sector), key.key_type, KEY_SLOT)
+}
+
+/// Tries every candidate key on the sector until one of them opens it.
+///
+/// # Returns
+///
+/// * `Ok(Some(SectorKey))` - The key that opened the sector, which is left authenticated.
+/// * `Ok(None)` - If none of the keys did.
+/// * `Err(ReaderError)` - If the card or the reader went away.
+fn authenticate_sector(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    sector: u8,
+    cancel_flag: &AtomicBool,
+) -> Result<Option<SectorKey>, ReaderError> {
+    for key in candidate_keys(keys, sector) {
+        match authenticate_with(reader, sector, &key) {
+            Ok(()) => return Ok(Some(key)),
+            Err(e) if e.is_reader_lost() => return Err(e),
+            Err(_) => reselect(reader, cancel_flag)?,
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads every sector of the Mifare Classic card on the reader.
+///
+/// Each sector is opened with the key from the key store, or the transport keys if that one fails.
+/// Sectors no key opens and blocks the access conditions don't let through are kept as `None`,
+/// so a partial dump is still returned. Key A always reads as zeros in the trailers, the key that
+/// opened the sector is kept alongside instead.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to read with.
+/// * `keys` - The key store with the key of each sector.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(CardDump)` - The UID, ATR and blocks of the card.
+/// * `Err(ReaderError)` - If the card isn't a Mifare Classic or it's pulled away mid-dump.
+pub(crate) fn read_card_dump(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    cancel_flag: &AtomicBool,
+    timeout: Option<Duration>,
+) -> Result<CardDump, ReaderError> {
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+
+    let card_type = CardType::from_atr(&atr)?;
+    if !card_type.is_classic() {
+        return Err(ReaderError::CardError(
+            "Only Mifare Classic cards can be dumped.".to_string(),
+            Error::CardUnsupported,
+        ));
+    }
+
+    let uid = reader.get_uid()?;
+    let mut sectors = Vec::with_capacity(card_type.sector_count() as usize);
+
+    for sector in 0..card_type.sector_count() {
+        if cancel_flag.load(Ordering::Relaxed) {
+            return Err(ReaderError::OperationCancelled("Dump Card".to_string()));
+        }
+
+        let key = authenticate_sector(reader, keys, sector, cancel_flag)?;
+        let mut blocks = Vec::new();
+
+        for block in sector_blocks(sector) {
+            let Some(sector_key) = &key else {
+                blocks.push(None);
+                continue;
+            };
+
+            match reader.read_block(block, 16) {
+                Ok(data) => blocks.push(Some(hex::encode_upper(data))),
+                Err(e) if e.is_reader_lost() => return Err(e),
+                Err(_) => {
+                    blocks.push(None);
+
+                    // The card halts on a denied read, open the sector again for the next blocks
+                    reselect(reader, cancel_flag)?;
+                    authenticate_with(reader, sector, sector_key)?;
+                }
+            }
+        }
+
+        sectors.push(SectorDump {
+            sector,
+            key,
+            blocks,
+        });
+    }
+
+    Ok(CardDump {
+        uid: hex::encode_upper(uid),
+        atr: hex::encode_upper(atr),
+        card_type,
+        dumped_at: chrono::Utc::now().to_rfc3339(),
+        sectors,
+    })
+}
+
+/// Decodes a block of the dump.
+fn block_bytes(block: &str) -> Result<[u8; 16], ReaderError> {
+    hex::decode(block)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| dump_error(format!("Invalid block in the dump: {}", block)))
+}
+
+impl CardDump {
+    /// Builds the raw image of the card, unread blocks are zeros.
+    ///
+    /// The key that opened each sector is put back in its trailer, key A reads as zeros otherwise.
+    pub(crate) fn to_image(&self) -> Result<Vec<u8>, ReaderError> {
+        let size = (*self.card_type.user_blocks().end() as usize + 1) * 16;
+        let mut image = vec![0u8; size];
+
+        for sector in &self.sectors {
+            for (block, data) in sector_blocks(sector.sector).zip(&sector.blocks) {
+                let start = block as usize * 16;
+                let image_block = image
+                    .get_mut(start..start + 16)
+                    .ok_or_else(|| dump_error(format!("Block {} is out of the card", block)))?;
+
+                if let Some(data) = data {
+                    image_block.copy_from_slice(&block_bytes(data)?);
+                }
+            }
+
+            if let Some(key) = &sector.key {
+                let start = trailer_block(sector.sector) as usize * 16;
+                let key_range = match key.key_type {
+                    KeyType::A => start..start + 6,
+                    KeyType::B => start + 10..start + 16,
+                };
+                image[key_range].copy_from_slice(&key.key);
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Loads a dump from a raw 1K or 4K image.
+    ///
+    /// The UID is taken from the manufacturer block, and key A from each trailer.
+    pub(crate) fn from_image(image: &[u8]) -> Result<CardDump, ReaderError> {
+        let card_type = match image.len() {
+            1024 => CardType::MifareClassic1K,
+            4096 => CardType::MifareClassic4K,
+            size => {
+                return Err(dump_error(format!(
+                    "A {} bytes image is not a Mifare Classic 1K or 4K card",
+                    size
+                )))
+            }
+        };
+
+        let sectors = (0..card_type.sector_count())
+            .map(|sector| {
+                let trailer = trailer_block(sector) as usize * 16;
+
+                SectorDump {
+                    sector,
+                    key: Some(SectorKey {
+                        key_type: KeyType::A,
+                        key: image[trailer..trailer + 6].try_into().unwrap(),
+                    }),
+                    blocks: sector_blocks(sector)
+                        .map(|block| {
+                            let start = block as usize * 16;
+                            Some(hex::encode_upper(&image[start..start + 16]))
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Ok(CardDump {
+            uid: hex::encode_upper(&image[..4]),
+            atr: String::new(),
+            card_type,
+            dumped_at: String::new(),
+            sectors,
+        })
+    }
+
+    /// Serializes the dump to be saved to a file.
+    pub(crate) fn to_bytes(&self, format: DumpFormat) -> Result<Vec<u8>, ReaderError> {
+        match format {
+            DumpFormat::Json => {
+                serde_json::to_vec_pretty(self).map_err(|e| dump_error(e.to_string()))
+            }
+            DumpFormat::Binary => self.to_image(),
+        }
+    }
+
+    /// Loads a dump saved with `to_bytes`, in either format.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<CardDump, ReaderError> {
+        // A binary image may start with a brace too, so only trust JSON that parses
+        if bytes.first() == Some(&b'{') {
+            if let Ok(dump) = serde_json::from_slice(bytes) {
+                return Ok(dump);
+            }
+        }
+
+        CardDump::from_image(bytes)
+    }
+}
+
+/// Rebuilds the trailer of a sector from the dump, with both keys known.
+///
+/// Key A comes from the key that opened the sector. Key B too if it opened it,
+/// or from the trailer itself when the access conditions leave it readable.
+fn restore_trailer(sector: &SectorDump, data: &[u8; 16]) -> Result<SectorTrailer, ReaderError> {
+    let access = AccessConditions::from_bytes(&[data[6], data[7], data[8]])?;
+    let unknown_key = |key: &str| {
+        dump_error(format!(
+            "Key {} of sector {} is unknown, restore without the trailers",
+            key, sector.sector
+        ))
+    };
+
+    let (key_a, key_b) = match &sector.key {
+        Some(SectorKey {
+            key_type: KeyType::A,
+            key,
+        }) if access.trailer.key_b_readable() => (*key, data[10..].try_into().unwrap()),
+        Some(SectorKey {
+            key_type: KeyType::A,
+            ..
+        }) => return Err(unknown_key("B")),
+        _ => return Err(unknown_key("A")),
+    };
+
+    Ok(SectorTrailer {
+        key_a,
+        access,
+        general_purpose: data[9],
+        key_b,
+    })
+}
+
+/// Works out the writes needed to restore the dump onto a blank card.
+///
+/// The manufacturer block is skipped, it can't be written. Sectors no key opened are skipped too.
+/// With `write_trailers`, the trailer of each sector is written last, once its data blocks are in.
+///
+/// # Returns
+///
+/// * `Ok(Vec<RestoreStep>)` - The writes, in order.
+/// * `Err(ReaderError)` - If a block is invalid, or a trailer can't be rebuilt because a key is unknown.
+pub(crate) fn restore_plan(
+    dump: &CardDump,
+    write_trailers: bool,
+) -> Result<Vec<RestoreStep>, ReaderError> {
+    let mut steps = Vec::new();
+
+    for sector in dump.sectors.iter().filter(|sector| sector.key.is_some()) {
+        let trailer = trailer_block(sector.sector);
+
+        for (block, data) in sector_blocks(sector.sector).zip(&sector.blocks) {
+            match data {
+                Some(data) if block == trailer && write_trailers => {
+                    steps.push(RestoreStep::Trailer {
+                        sector: sector.sector,
+                        trailer: restore_trailer(sector, &block_bytes(data)?)?,
+                    });
+                }
+                None if block == trailer && write_trailers => {
+                    return Err(dump_error(format!(
+                        "The trailer of sector {} wasn't dumped, restore without the trailers",
+                        sector.sector
+                    )));
+                }
+                Some(data) if block != 0 && block != trailer => {
+                    steps.push(RestoreStep::Data {
+                        block,
+                        data: block_bytes(data)?,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Writes a dump onto a blank card, every sector still using the transport keys.
+///
+/// The whole plan is checked before anything is written. Data blocks are read back after being written.
+/// Every trailer written changes the keys of its sector, so the key store is updated right after each one,
+/// the same way `write_sector_trailer` does.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to write with.
+/// * `keys` - The key store, updated with the keys of the restored trailers.
+/// * `dump` - The dump to restore.
+/// * `write_trailers` - Whether to restore the keys and access conditions too, the card keeps the transport ones otherwise.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u16>)` - The blocks written, in order.
+/// * `Err(ReaderError)` - If the card is not of the same type as the dump, or a write fails.
+pub(crate) fn write_card_dump(
+    reader: &mut dyn CardReader,
+    keys: &mut KeyStore,
+    dump: &CardDump,
+    write_trailers: bool,
+    cancel_flag: &AtomicBool,
+    timeout: Option<Duration>,
+) -> Result<Vec<u16>, ReaderError> {
+    let steps = restore_plan(dump, write_trailers)?;
+
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+    let card_type = CardType::from_atr(&atr)?;
+    if card_type != dump.card_type {
+        return Err(ReaderError::CardError(
+            format!(
+                "The dump is of a {:?} card, not a {:?}.",
+                dump.card_type, card_type
+            ),
+            Error::InvalidParameter,
+        ));
+    }
+
+    let blank = KeyStore::default();
+    let mut written = Vec::with_capacity(steps.len());
+
+    for step in steps {
+        if cancel_flag.load(Ordering::Relaxed) {
+            return Err(ReaderError::OperationCancelled("Restore Card".to_string()));
+        }
+
+        match step {
+            RestoreStep::Data { block, data } => {
+                written.extend(write_data(
+                    reader,
+                    &blank,
+                    card_type,
+                    block,
+                    &data,
+                    cancel_flag,
+                )?);
+            }
+            RestoreStep::Trailer { sector, trailer } => {
+                let block = trailer_block(sector);
+                let trailer_bytes = trailer.to_bytes()?;
+
+                authenticate_block(reader, block, &blank)
+                    .and_then(|_| reader.write_block(block, &trailer_bytes))
+                    .map_err(|e| ReaderError::BlockWriteFailed(block, e.to_string()))?;
+                written.push(block);
+
+                keys.sectors.insert(sector, trailer.sector_key());
+                keys.save()?;
+            }
+        }
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::card::utils::trailer::{DataBlockAccess, TrailerAccess};
+    use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
+
+    #[test]
+    fn test_dump_and_image() {
+        let mut reader = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+        let cancel_flag = AtomicBool::new(false);
+        let keys = KeyStore::default();
+
+        reader.connect(&cancel_flag, None).unwrap();
+        write_data(
+            &mut reader,
+            &keys,
+            CardType::MifareClassic1K,
+            4,
+            b"hOtB6pOxiL2IQPYs",
+            &cancel_flag,
+        )
+        .unwrap();
+
+        let dump = read_card_dump(&mut reader, &keys, &cancel_flag, None).unwrap();
+        assert_eq!(dump.uid, "04A23B1C");
+        assert_eq!(dump.sectors.len(), 16);
+        assert_eq!(dump.sectors[1].key, Some(SectorKey::default()));
+        assert_eq!(
+            dump.sectors[1].blocks[0],
+            Some(hex::encode_upper(b"hOtB6pOxiL2IQPYs"))
+        );
+
+        // The binary image puts key A back in the trailers
+        let image = dump.to_bytes(DumpFormat::Binary).unwrap();
+        assert_eq!(image.len(), 1024);
+        assert_eq!(&image[7 * 16..7 * 16 + 6], &[0xff; 6]);
+
+        let loaded = CardDump::from_bytes(&image).unwrap();
+        assert_eq!(loaded.uid, dump.uid);
+        assert_eq!(loaded.to_image().unwrap(), image);
+
+        let json = dump.to_bytes(DumpFormat::Json).unwrap();
+        assert_eq!(CardDump::from_bytes(&json).unwrap(), dump);
+
+        // Restored onto a blank card, the manufacturer block keeps the UID of the new card
+        let mut blank = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xff, 0xff, 0xff]));
+        let mut keys = KeyStore::default();
+        let written =
+            write_card_dump(&mut blank, &mut keys, &dump, false, &cancel_flag, None).unwrap();
+        assert_eq!(written.len(), 16 * 3 - 1);
+
+        let restored = read_card_dump(&mut blank, &keys, &cancel_flag, None).unwrap();
+        assert_eq!(restored.uid, "04FFFFFF");
+        assert_eq!(restored.sectors[1..], dump.sectors[1..]);
+    }
+
+    #[test]
+    fn test_restore_plan() {
+        let issued = SectorTrailer {
+            key_a: [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5],
+            access: AccessConditions::default(),
+            general_purpose: 0x69,
+            key_b: [0xb0, 0xb1, 0xb2, 0xb3, 0xb4, 0xb5],
+        };
+        let mut trailer = issued.to_bytes().unwrap();
+        // Key A never reads back
+        trailer[..6].fill(0);
+
+        let mut dump = CardDump {
+            uid: "04A23B1C".to_string(),
+            atr: String::new(),
+            card_type: CardType::MifareClassic1K,
+            dumped_at: String::new(),
+            sectors: (0..2)
+                .map(|sector| SectorDump {
+                    sector,
+                    key: Some(SectorKey {
+                        key_type: KeyType::A,
+                        key: issued.key_a,
+                    }),
+                    blocks: vec![
+                        Some("00".repeat(16)),
+                        None,
+                        Some("11".repeat(16)),
+                        Some(hex::encode(trailer)),
+                    ],
+                })
+                .collect(),
+        };
+        dump.sectors[1].key = None;
+
+        // The manufacturer block, unread blocks and sectors no key opened are skipped
+        let steps = restore_plan(&dump, false).unwrap();
+        assert_eq!(
+            steps,
+            vec![RestoreStep::Data {
+                block: 2,
+                data: [0x11; 16]
+            }]
+        );
+
+        let steps = restore_plan(&dump, true).unwrap();
+        assert_eq!(
+            steps.last(),
+            Some(&RestoreStep::Trailer {
+                sector: 0,
+                trailer: issued.clone()
+            })
+        );
+
+        // Key B can't be read back once it's used for authentication
+        let mut locked = issued.clone();
+        locked.access = AccessConditions {
+            blocks: [DataBlockAccess::ReadABWriteB; 3],
+            trailer: TrailerAccess::KeyBManaged,
+        };
+        dump.sectors[0].blocks[3] = Some(hex::encode(locked.to_bytes().unwrap()));
+        assert!(restore_plan(&dump, true).is_err());
+        assert!(restore_plan(&dump, false).is_ok());
+    }
+}
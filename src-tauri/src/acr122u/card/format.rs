@@ -0,0 +1,471 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::acr122u::card::badge::BADGE_LENGTH;
+use crate::acr122u::card::read::read_data;
+use crate::acr122u::card::utils::encoding::decode_text;
+use crate::acr122u::card::utils::keys::KeyStore;
+use crate::acr122u::card::utils::layout::CardType;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::settings::card_deadline;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// The first bytes of every record, 0xFA is never valid UTF-8, so a record can't be mistaken
+/// for the plain-text ids of legacy badges or the employee id at the start of a signed badge.
+pub(crate) const MAGIC: [u8; 4] = [0xfa, 0xce, b'P', b'A'];
+
+/// The version written by this build. It's only bumped when the layout changes in a way older builds can't read,
+/// new fields don't need it, older builds skip the fields they don't know.
+pub(crate) const FORMAT_VERSION: u8 = 1;
+
+/// Magic bytes, version and length of the fields.
+const HEADER_LENGTH: usize = MAGIC.len() + 1 + 2;
+const CRC_LENGTH: usize = 4;
+
+/// A record fits in the badge area, see `BADGE_LENGTH`.
+pub(crate) const MAX_RECORD_LENGTH: usize = BADGE_LENGTH;
+
+/// The field types, each field is laid out as its type, its length and its value.
+const EMPLOYEE_ID: u8 = 0x01;
+const CARD_UID: u8 = 0x02;
+const COUNTER: u8 = 0x03;
+const SIGNATURE: u8 = 0x04;
+
+/// The badge record written by `write_card` and read back by `read_card` and the card watcher.
+///
+/// Signed badges (see `Badge`) are records too, with the UID of the card, the issue counter and the signature
+/// as fields of their own. Records written by `write_card` only have the employee id.
+///
+/// Laid out as:
+///
+/// | Offset | Length | Content                                            |
+/// |--------|--------|----------------------------------------------------|
+/// | 0      | 4      | Magic bytes, `FA CE 50 41`                         |
+/// | 4      | 1      | Format version, see `FORMAT_VERSION`               |
+/// | 5      | 2      | Length of the fields, big endian                   |
+/// | 7      | n      | Fields, one byte of type, one of length, the value |
+/// | 7 + n  | 4      | CRC-32 of everything before it, big endian         |
+///
+/// The fields are:
+///
+/// | Type | Length | Content                                                    |
+/// |------|--------|------------------------------------------------------------|
+/// | 0x01 | n      | Employee id, UTF-8                                         |
+/// | 0x02 | 4 or 7 | UID of the card the badge was issued for                   |
+/// | 0x03 | 4      | Issue counter, big endian                                  |
+/// | 0x04 | 16     | HMAC-SHA256 of the fields 0x01 to 0x03, first 16 bytes     |
+///
+/// The rest of the last block is padded with zeros.
+/// The CRC catches writes cut short by a card pulled away, it doesn't prove who wrote the record, signed badges do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BadgeRecord {
+    pub(crate) employee_id: String,
+    pub(crate) uid: Option<Vec<u8>>,
+    pub(crate) counter: Option<u32>,
+    pub(crate) signature: Option<Vec<u8>>,
+    /// Fields added by newer versions, kept so rewriting the record doesn't lose them.
+    pub(crate) unknown_fields: Vec<(u8, Vec<u8>)>,
+}
+
+/// What was found on the card where a record is expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StoredData {
+    Record(BadgeRecord),
+    /// A bare id written before records, upgraded to a record on its next tap.
+    Legacy(String),
+    Blank,
+}
+
+fn format_error(message: &str) -> ReaderError {
+    ReaderError::FormatError(message.to_string())
+}
+
+/// CRC-32 (IEEE 802.3), the one of zip and Ethernet.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Lays out the fields as their type, their length and their value.
+fn encode_fields(fields: Vec<(u8, Vec<u8>)>) -> Result<Vec<u8>, ReaderError> {
+    let mut encoded = Vec::new();
+
+    for (field, value) in fields {
+        let length = u8::try_from(value.len()).map_err(|_| format_error("A field is too long"))?;
+        encoded.push(field);
+        encoded.push(length);
+        encoded.extend(value);
+    }
+
+    Ok(encoded)
+}
+
+/// Checks if the data starts with the magic bytes of a record, without decoding it.
+pub(crate) fn is_record(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// The length of the whole record, CRC included, from its header.
+fn record_length(header: &[u8]) -> Option<usize> {
+    let length = u16::from_be_bytes([*header.get(5)?, *header.get(6)?]) as usize;
+    Some(HEADER_LENGTH + length + CRC_LENGTH)
+}
+
+impl BadgeRecord {
+    pub(crate) fn new(employee_id: &str) -> BadgeRecord {
+        BadgeRecord {
+            employee_id: employee_id.to_string(),
+            uid: None,
+            counter: None,
+            signature: None,
+            unknown_fields: Vec::new(),
+        }
+    }
+
+    /// The fields that identify the badge, in the order they're written, the ones covered by the signature.
+    fn known_fields(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut fields = vec![(EMPLOYEE_ID, self.employee_id.as_bytes().to_vec())];
+        if let Some(uid) = &self.uid {
+            fields.push((CARD_UID, uid.clone()));
+        }
+        if let Some(counter) = self.counter {
+            fields.push((COUNTER, counter.to_be_bytes().to_vec()));
+        }
+        fields
+    }
+
+    /// The encoded employee id, UID and counter fields, this is what the signature of a badge is computed over.
+    pub(crate) fn signed_bytes(&self) -> Result<Vec<u8>, ReaderError> {
+        encode_fields(self.known_fields())
+    }
+
+    /// Encodes the record, without the padding.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The record, ready to be written with `write_data`.
+    /// * `Err(ReaderError)` - If the employee id is empty or the record doesn't fit in `MAX_RECORD_LENGTH`.
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, ReaderError> {
+        if self.employee_id.is_empty() {
+            return Err(format_error("The employee id is empty"));
+        }
+
+        let mut fields = self.known_fields();
+        if let Some(signature) = &self.signature {
+            fields.push((SIGNATURE, signature.clone()));
+        }
+        fields.extend(self.unknown_fields.iter().cloned());
+        let fields = encode_fields(fields)?;
+
+        if HEADER_LENGTH + fields.len() + CRC_LENGTH > MAX_RECORD_LENGTH {
+            return Err(format_error(&format!(
+                "The record takes {} bytes, only {} fit on a badge",
+                HEADER_LENGTH + fields.len() + CRC_LENGTH,
+                MAX_RECORD_LENGTH
+            )));
+        }
+
+        let mut record = Vec::with_capacity(HEADER_LENGTH + fields.len() + CRC_LENGTH);
+        record.extend_from_slice(&MAGIC);
+        record.push(FORMAT_VERSION);
+        record.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        record.extend(fields);
+        record.extend_from_slice(&crc32(&record).to_be_bytes());
+
+        Ok(record)
+    }
+
+    /// Decodes a record read from a card, the padding after it is ignored.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(BadgeRecord)` - The record.
+    /// * `Err(ReaderError)` - `FormatError` if the data isn't a record, was written by a newer format version,
+    ///   is cut short or doesn't match its CRC.
+    pub(crate) fn decode(data: &[u8]) -> Result<BadgeRecord, ReaderError> {
+        if !is_record(data) {
+            return Err(format_error("Not a badge record"));
+        }
+
+        let version = data.get(4).copied().unwrap_or_default();
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(format_error(&format!(
+                "Unsupported format version {}, the badge may have been written by a newer version of PontuAll",
+                version
+            )));
+        }
+
+        let length = record_length(data).ok_or_else(|| format_error("The record is cut short"))?;
+        if data.len() < length {
+            return Err(format_error("The record is cut short"));
+        }
+
+        let (record, crc) = data[..length].split_at(length - CRC_LENGTH);
+        if crc32(record).to_be_bytes() != crc {
+            return Err(format_error(
+                "The record is corrupted, its CRC doesn't match",
+            ));
+        }
+
+        let mut employee_id = None;
+        let mut uid = None;
+        let mut counter = None;
+        let mut signature = None;
+        let mut unknown_fields = Vec::new();
+        let mut fields = &record[HEADER_LENGTH..];
+
+        while let [field, length, rest @ ..] = fields {
+            let length = *length as usize;
+            if rest.len() < length {
+                return Err(format_error("A field runs past the end of the record"));
+            }
+            let (value, rest) = rest.split_at(length);
+
+            match *field {
+                EMPLOYEE_ID => {
+                    employee_id = Some(
+                        String::from_utf8(value.to_vec())
+                            .map_err(|_| format_error("Invalid employee id"))?,
+                    )
+                }
+                CARD_UID => uid = Some(value.to_vec()),
+                COUNTER => {
+                    let value: [u8; 4] = value
+                        .try_into()
+                        .map_err(|_| format_error("Invalid issue counter"))?;
+                    counter = Some(u32::from_be_bytes(value));
+                }
+                SIGNATURE => signature = Some(value.to_vec()),
+                field => unknown_fields.push((field, value.to_vec())),
+            }
+            fields = rest;
+        }
+
+        if !fields.is_empty() {
+            return Err(format_error("A field runs past the end of the record"));
+        }
+
+        Ok(BadgeRecord {
+            employee_id: employee_id
+                .ok_or_else(|| format_error("The record has no employee id"))?,
+            uid,
+            counter,
+            signature,
+            unknown_fields,
+        })
+    }
+}
+
+impl StoredData {
+    /// Works out what the data read at the start of a badge is.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StoredData)` - The record, the legacy id or `Blank` if there's nothing but zeros.
+    /// * `Err(ReaderError)` - If the data has the magic bytes of a record but can't be decoded,
+    ///   or it's neither a record nor text.
+    pub(crate) fn decode(data: &[u8]) -> Result<StoredData, ReaderError> {
+        if is_record(data) {
+            return BadgeRecord::decode(data).map(StoredData::Record);
+        }
+
+        let text = decode_text(data)?;
+        if text.is_empty() {
+            Ok(StoredData::Blank)
+        } else {
+            Ok(StoredData::Legacy(text))
+        }
+    }
+}
+
+/// Reads the record starting at `first_block` of a connected card, or the first block if there's no record there.
+///
+/// The header is read first, the rest of the record only when it doesn't fit in the first block,
+/// so a legacy id on the last data block of a card can still be read.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader, already connected to the card.
+/// * `keys` - The key store with the key of each sector, only used by Mifare Classic cards.
+/// * `card_type` - The type of the connected card.
+/// * `first_block` - The block or page the record starts at.
+/// * `cancel_flag` - A flag to cancel the operation.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The raw data, decode it with `StoredData::decode`.
+/// * `Err(ReaderError)` - If a block can't be read.
+pub(crate) fn read_record_data(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    card_type: CardType,
+    first_block: u16,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u8>, ReaderError> {
+    let data = read_data(reader, keys, card_type, first_block, 16, cancel_flag)?;
+
+    match record_length(&data) {
+        Some(length) if is_record(&data) && length > data.len() => read_data(
+            reader,
+            keys,
+            card_type,
+            first_block,
+            length.min(MAX_RECORD_LENGTH),
+            cancel_flag,
+        ),
+        _ => Ok(data),
+    }
+}
+
+/// Reads the data stored at `first_block` and works out whether it's a record, a legacy id or nothing.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to read with.
+/// * `keys` - The key store with the key of each sector.
+/// * `first_block` - The block or page the record starts at.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(StoredData)` - What's stored on the card.
+/// * `Err(ReaderError)` - `FormatError` if the record is corrupted, or the read fails.
+pub(crate) async fn read_record(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    first_block: u16,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<StoredData, ReaderError> {
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+
+    let card_type = CardType::detect(reader, &atr)?;
+    let data = read_record_data(reader, keys, card_type, first_block, cancel_flag)?;
+    StoredData::decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::card::write::write_data;
+    use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_record() {
+        let record = BadgeRecord::new("hOtB6pOxiL2IQPYs");
+        let mut data = record.encode().unwrap();
+        assert_eq!(data.len(), 29);
+        assert!(data.starts_with(&MAGIC));
+
+        data.resize(32, 0);
+        assert_eq!(BadgeRecord::decode(&data).unwrap(), record);
+        assert_eq!(
+            StoredData::decode(&data).unwrap(),
+            StoredData::Record(record.clone())
+        );
+
+        // A write cut short, or a bit flipped
+        assert!(BadgeRecord::decode(&data[..20]).is_err());
+        let mut corrupted = data.clone();
+        corrupted[10] ^= 0x01;
+        assert!(BadgeRecord::decode(&corrupted).is_err());
+
+        // Written by a newer version
+        let mut newer = data.clone();
+        newer[4] = FORMAT_VERSION + 1;
+        assert!(BadgeRecord::decode(&newer).is_err());
+
+        // Fields from newer versions are kept
+        let extended = BadgeRecord {
+            unknown_fields: vec![(0x7f, vec![0x01, 0x02])],
+            ..BadgeRecord::new("hOtB6pOx")
+        };
+        assert_eq!(
+            BadgeRecord::decode(&extended.encode().unwrap()).unwrap(),
+            extended
+        );
+
+        // The fields of a signed badge
+        let signed = BadgeRecord {
+            uid: Some(vec![0x04, 0xa2, 0x3b, 0x1c, 0x2d, 0x3e, 0x4f]),
+            counter: Some(3),
+            signature: Some(vec![0xaa; 16]),
+            ..BadgeRecord::new("hOtB6pOxiL2IQPYs")
+        };
+        let data = signed.encode().unwrap();
+        assert_eq!(data.len(), 62);
+        assert_eq!(BadgeRecord::decode(&data).unwrap(), signed);
+
+        assert!(BadgeRecord::new("").encode().is_err());
+        assert!(BadgeRecord::new(&"a".repeat(52)).encode().is_err());
+        assert!(BadgeRecord::new(&"a".repeat(51)).encode().is_ok());
+    }
+
+    #[test]
+    fn test_stored_data() {
+        let mut legacy = b"hOtB6pOx".to_vec();
+        legacy.resize(16, 0);
+
+        assert_eq!(
+            StoredData::decode(&legacy).unwrap(),
+            StoredData::Legacy("hOtB6pOx".to_string())
+        );
+        assert_eq!(StoredData::decode(&[0; 16]).unwrap(), StoredData::Blank);
+        assert!(StoredData::decode(&[0xff, 0xfe, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_read_record_data() {
+        let mut reader = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+        let cancel_flag = AtomicBool::new(false);
+        let keys = KeyStore::default();
+        let atr = reader.connect(&cancel_flag, None).unwrap();
+        let card_type = CardType::detect(&mut reader, &atr).unwrap();
+
+        // The last data block before the trailer of sector 15, there's no room for more than a block
+        write_data(&mut reader, &keys, card_type, 62, b"hOtB6pOx", &cancel_flag).unwrap();
+        let data = read_record_data(&mut reader, &keys, card_type, 62, &cancel_flag).unwrap();
+        assert_eq!(
+            StoredData::decode(&data).unwrap(),
+            StoredData::Legacy("hOtB6pOx".to_string())
+        );
+
+        let record = BadgeRecord::new("hOtB6pOxiL2IQPYs");
+        write_data(
+            &mut reader,
+            &keys,
+            card_type,
+            4,
+            &record.encode().unwrap(),
+            &cancel_flag,
+        )
+        .unwrap();
+        let data = read_record_data(&mut reader, &keys, card_type, 4, &cancel_flag).unwrap();
+        assert_eq!(data.len(), 29);
+        assert_eq!(
+            StoredData::decode(&data).unwrap(),
+            StoredData::Record(record)
+        );
+    }
+}
@@ -1,3 +1,8 @@
+pub mod badge;
+pub mod dump;
+pub mod format;
+pub mod ndef;
 pub mod read;
+pub mod revocation;
 pub mod utils;
 pub mod write;
@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use pcsc::Error;
+
+use crate::acr122u::card::ndef::record::{decode_message, encode_message, NdefRecord};
+use crate::acr122u::card::ndef::tlv::{build_mad, find_message, parse_mad, wrap_message};
+use crate::acr122u::card::read::read_pages;
+use crate::acr122u::card::utils::keys::{authenticate_block, KeyStore};
+use crate::acr122u::card::utils::layout::CardType;
+use crate::acr122u::card::write::write_data;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::settings::card_deadline;
+use crate::acr122u::utils::errors::ReaderError;
+
+pub(crate) mod record;
+pub(crate) mod tlv;
+
+/// The first page of the data area of Type 2 tags (Ultralight and NTAG21x).
+const TYPE2_DATA_PAGE: u16 = 4;
+
+/// Gets the first block of a Mifare Classic sector covered by the MAD v1.
+fn first_block(sector: u8) -> u16 {
+    sector as u16 * 4
+}
+
+/// Writes an NDEF message to the card, so phones and other NFC tools can read it.
+///
+/// Ultralight and NTAG21x cards get the message in the Type 2 TLV layout, starting at page 4.
+/// Mifare Classic cards get it in the MAD layout: the message fills sectors 1 to 15 and the MAD in sector 0 lists them.
+/// The sectors are written with the keys from the key store,
+/// phones only read them once the trailers use the public NFC Forum keys (see `write_sector_trailer`).
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to write with.
+/// * `keys` - The key store with the key of each sector.
+/// * `records` - The records of the message.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the message was written.
+/// * `Err(ReaderError)` - If the message doesn't fit in the card or the write fails.
+pub(crate) async fn write_message(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    records: &[NdefRecord],
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<(), ReaderError> {
+    let tlv = wrap_message(&encode_message(records)?)?;
+
+    if cancel_flag.load(Ordering::Relaxed) {
+        return Err(ReaderError::OperationCancelled("Write NDEF".to_string()));
+    }
+
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+    let card_type = CardType::detect(reader, &atr)?;
+
+    if card_type.is_classic() {
+        write_classic(reader, keys, card_type, &tlv, cancel_flag)
+    } else {
+        write_data(reader, keys, card_type, TYPE2_DATA_PAGE, &tlv, cancel_flag)?;
+        Ok(())
+    }
+}
+
+fn write_classic(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    card_type: CardType,
+    tlv: &[u8],
+    cancel_flag: &AtomicBool,
+) -> Result<(), ReaderError> {
+    // Each sector holds 3 data blocks of 16 bytes
+    let sectors = tlv.len().div_ceil(48);
+    let mad = build_mad(u8::try_from(sectors).unwrap_or(u8::MAX))?;
+
+    // The rest of the last sector is cleared, so no old data is left after the terminator
+    let mut data = tlv.to_vec();
+    data.resize(sectors * 48, 0);
+
+    write_data(reader, keys, card_type, first_block(1), &data, cancel_flag)?;
+
+    // The MAD goes last, so a write that fails halfway doesn't point to a broken message
+    write_data(reader, keys, card_type, 1, &mad, cancel_flag)?;
+
+    Ok(())
+}
+
+/// Reads the NDEF message of the card.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to read with.
+/// * `keys` - The key store with the key of each sector, only used by Mifare Classic cards.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(Vec<NdefRecord>)` - The Text and URI records of the message, other records are skipped.
+/// * `Err(ReaderError)` - If the card has no NDEF message or the read fails.
+pub(crate) async fn read_message(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<Vec<NdefRecord>, ReaderError> {
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+    let card_type = CardType::detect(reader, &atr)?;
+
+    let data = if card_type.is_classic() {
+        read_classic(reader, keys, cancel_flag)?
+    } else {
+        read_type2(reader, card_type, cancel_flag)?
+    };
+
+    let (offset, length) = find_message(&data)?;
+    let message = data.get(offset..offset + length).ok_or_else(|| {
+        ReaderError::CardError("NDEF: Truncated message".to_string(), Error::InvalidValue)
+    })?;
+
+    decode_message(message)
+}
+
+fn read_type2(
+    reader: &mut dyn CardReader,
+    card_type: CardType,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u8>, ReaderError> {
+    // The TLV header is always in the first 4 pages, it tells how much more to read
+    let mut data = read_pages(reader, TYPE2_DATA_PAGE, 16, cancel_flag)?;
+    let (offset, length) = find_message(&data)?;
+
+    let user_pages = card_type.user_blocks();
+    let user_memory = (user_pages.end() - user_pages.start() + 1) as usize * 4;
+    if offset + length > user_memory {
+        return Err(ReaderError::CardError(
+            "NDEF: The message is longer than the card".to_string(),
+            Error::InvalidValue,
+        ));
+    }
+
+    if offset + length > data.len() {
+        let remaining = (offset + length - data.len()) as u16;
+        data.extend(read_pages(
+            reader,
+            TYPE2_DATA_PAGE + 4,
+            remaining,
+            cancel_flag,
+        )?);
+    }
+
+    Ok(data)
+}
+
+fn read_classic(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u8>, ReaderError> {
+    authenticate_block(reader, 1, keys)?;
+    let mut mad = reader.read_block(1, 16)?;
+    mad.extend(reader.read_block(2, 16)?);
+
+    let mut data = Vec::new();
+
+    for sector in parse_mad(&mad)? {
+        if cancel_flag.load(Ordering::SeqCst) {
+            return Err(ReaderError::OperationCancelled("Read NDEF".to_string()));
+        }
+
+        for block in first_block(sector)..first_block(sector) + 3 {
+            authenticate_block(reader, block, keys)?;
+            data.extend(reader.read_block(block, 16)?);
+        }
+
+        // Stop as soon as the whole message was read
+        if let Ok((offset, length)) = find_message(&data) {
+            if data.len() >= offset + length {
+                break;
+            }
+        }
+    }
+
+    Ok(data)
+}
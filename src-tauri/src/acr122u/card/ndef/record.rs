@@ -0,0 +1,276 @@
+use pcsc::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Record header flags (NFC Forum NDEF 1.0, section 3.2).
+const MESSAGE_BEGIN: u8 = 0x80;
+const MESSAGE_END: u8 = 0x40;
+const CHUNK: u8 = 0x20;
+const SHORT_RECORD: u8 = 0x10;
+const ID_LENGTH: u8 = 0x08;
+const TNF_MASK: u8 = 0x07;
+
+/// Type Name Format of the NFC Forum well-known types (RTD).
+const TNF_WELL_KNOWN: u8 = 0x01;
+
+/// The abbreviations of the URI record, the index is the identifier code (NFC Forum URI RTD, section 3.2.2).
+const URI_PREFIXES: [&str; 36] = [
+    "",
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+/// The NDEF records the app reads and writes.
+///
+/// Records of any other type are skipped when reading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub(crate) enum NdefRecord {
+    Text { language: String, text: String },
+    Uri { uri: String },
+}
+
+impl NdefRecord {
+    fn record_type(&self) -> &'static [u8] {
+        match self {
+            NdefRecord::Text { .. } => b"T",
+            NdefRecord::Uri { .. } => b"U",
+        }
+    }
+
+    fn payload(&self) -> Result<Vec<u8>, ReaderError> {
+        match self {
+            NdefRecord::Text { language, text } => {
+                // Bit 7 clear means UTF-8, the lower 6 bits are the length of the language code
+                if language.is_empty() || language.len() > 0x3f {
+                    return Err(ndef_error("Invalid language code"));
+                }
+
+                let mut payload = vec![language.len() as u8];
+                payload.extend_from_slice(language.as_bytes());
+                payload.extend_from_slice(text.as_bytes());
+                Ok(payload)
+            }
+            NdefRecord::Uri { uri } => {
+                // Use the longest abbreviation that matches, the first one is the empty prefix
+                let (code, prefix) = URI_PREFIXES
+                    .iter()
+                    .enumerate()
+                    .skip(1)
+                    .filter(|(_, prefix)| uri.starts_with(*prefix))
+                    .max_by_key(|(_, prefix)| prefix.len())
+                    .unwrap_or((0, &""));
+
+                let mut payload = vec![code as u8];
+                payload.extend_from_slice(uri[prefix.len()..].as_bytes());
+                Ok(payload)
+            }
+        }
+    }
+
+    fn from_payload(record_type: &[u8], payload: &[u8]) -> Result<Option<NdefRecord>, ReaderError> {
+        match record_type {
+            b"T" => {
+                let status = *payload
+                    .first()
+                    .ok_or_else(|| ndef_error("Empty text record"))?;
+                if status & 0x80 != 0 {
+                    return Err(ndef_error("UTF-16 text records are not supported"));
+                }
+
+                let language_end = 1 + (status & 0x3f) as usize;
+                if payload.len() < language_end {
+                    return Err(ndef_error("Truncated text record"));
+                }
+
+                Ok(Some(NdefRecord::Text {
+                    language: utf8(&payload[1..language_end])?,
+                    text: utf8(&payload[language_end..])?,
+                }))
+            }
+            b"U" => {
+                let code = *payload
+                    .first()
+                    .ok_or_else(|| ndef_error("Empty URI record"))?;
+                let prefix = URI_PREFIXES.get(code as usize).unwrap_or(&"");
+
+                Ok(Some(NdefRecord::Uri {
+                    uri: format!("{}{}", prefix, utf8(&payload[1..])?),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn ndef_error(message: &str) -> ReaderError {
+    ReaderError::CardError(format!("NDEF: {}", message), Error::InvalidValue)
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, ReaderError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| ndef_error("Invalid UTF-8"))
+}
+
+/// Encodes the records into an NDEF message.
+///
+/// Records with less than 256 bytes of payload use the short record format.
+pub(crate) fn encode_message(records: &[NdefRecord]) -> Result<Vec<u8>, ReaderError> {
+    if records.is_empty() {
+        return Err(ndef_error("The message needs at least one record"));
+    }
+
+    let mut message = Vec::new();
+
+    for (i, record) in records.iter().enumerate() {
+        let record_type = record.record_type();
+        let payload = record.payload()?;
+
+        let mut header = TNF_WELL_KNOWN;
+        if i == 0 {
+            header |= MESSAGE_BEGIN;
+        }
+        if i == records.len() - 1 {
+            header |= MESSAGE_END;
+        }
+        if payload.len() < 256 {
+            header |= SHORT_RECORD;
+        }
+
+        message.push(header);
+        message.push(record_type.len() as u8);
+        if payload.len() < 256 {
+            message.push(payload.len() as u8);
+        } else {
+            message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        }
+        message.extend_from_slice(record_type);
+        message.extend_from_slice(&payload);
+    }
+
+    Ok(message)
+}
+
+/// Reads the next `n` bytes of the message.
+fn take_bytes<'a>(
+    message: &'a [u8],
+    offset: &mut usize,
+    n: usize,
+) -> Result<&'a [u8], ReaderError> {
+    let bytes = message
+        .get(*offset..*offset + n)
+        .ok_or_else(|| ndef_error("Truncated message"))?;
+    *offset += n;
+    Ok(bytes)
+}
+
+/// Decodes an NDEF message, skipping the records the app doesn't know.
+pub(crate) fn decode_message(message: &[u8]) -> Result<Vec<NdefRecord>, ReaderError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = take_bytes(message, &mut offset, 1)?[0];
+        if header & CHUNK != 0 {
+            return Err(ndef_error("Chunked records are not supported"));
+        }
+
+        let type_length = take_bytes(message, &mut offset, 1)?[0] as usize;
+        let payload_length = if header & SHORT_RECORD != 0 {
+            take_bytes(message, &mut offset, 1)?[0] as usize
+        } else {
+            let length = take_bytes(message, &mut offset, 4)?;
+            u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize
+        };
+        let id_length = if header & ID_LENGTH != 0 {
+            take_bytes(message, &mut offset, 1)?[0] as usize
+        } else {
+            0
+        };
+
+        let record_type = take_bytes(message, &mut offset, type_length)?;
+        take_bytes(message, &mut offset, id_length)?;
+        let payload = take_bytes(message, &mut offset, payload_length)?;
+
+        if header & TNF_MASK == TNF_WELL_KNOWN {
+            if let Some(record) = NdefRecord::from_payload(record_type, payload)? {
+                records.push(record);
+            }
+        }
+
+        if header & MESSAGE_END != 0 {
+            break;
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let records = vec![
+            NdefRecord::Text {
+                language: "pt".to_string(),
+                text: "hOtB6pOxiL2IQPYs".to_string(),
+            },
+            NdefRecord::Uri {
+                uri: "https://www.example.com/badge".to_string(),
+            },
+        ];
+
+        let message = encode_message(&records).unwrap();
+
+        // First record: MB + SR + well-known, type "T", status byte with the language length
+        assert_eq!(&message[..6], &[0x91, 0x01, 0x13, b'T', 0x02, b'p']);
+        assert_eq!(decode_message(&message).unwrap(), records);
+    }
+
+    #[test]
+    fn test_uri_abbreviation() {
+        let message = encode_message(&[NdefRecord::Uri {
+            uri: "https://example.com".to_string(),
+        }])
+        .unwrap();
+
+        // "https://" is code 0x04
+        assert_eq!(&message[..5], &[0xd1, 0x01, 0x0c, b'U', 0x04]);
+        assert!(decode_message(&message[..8]).is_err());
+    }
+}
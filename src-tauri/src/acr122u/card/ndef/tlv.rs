@@ -0,0 +1,163 @@
+use pcsc::Error;
+
+use crate::acr122u::utils::errors::ReaderError;
+
+/// TLV blocks found in the data area of NFC Forum tags (Type 2 Tag 1.0, section 2.3).
+const NULL_TLV: u8 = 0x00;
+const NDEF_TLV: u8 = 0x03;
+const TERMINATOR_TLV: u8 = 0xfe;
+
+/// The application id of NDEF sectors in the MAD, stored as application code and function cluster code.
+pub(crate) const NDEF_AID: [u8; 2] = [0x03, 0xe1];
+
+/// The Mifare Application Directory v1, kept in blocks 1 and 2 of sector 0.
+///
+/// It covers sectors 1 to 15, the MAD v2 of 4K cards is not used.
+pub(crate) const MAD_SECTORS: u8 = 15;
+
+fn tlv_error(message: &str) -> ReaderError {
+    ReaderError::CardError(format!("NDEF: {}", message), Error::InvalidValue)
+}
+
+/// Wraps an NDEF message in an NDEF TLV followed by a terminator TLV.
+pub(crate) fn wrap_message(message: &[u8]) -> Result<Vec<u8>, ReaderError> {
+    let mut tlv = vec![NDEF_TLV];
+
+    // Lengths up to 254 fit in one byte, above that 0xFF is followed by two bytes
+    match message.len() {
+        0..=0xfe => tlv.push(message.len() as u8),
+        0xff..=0xfffe => {
+            tlv.push(0xff);
+            tlv.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        }
+        _ => return Err(tlv_error("Message too long")),
+    }
+
+    tlv.extend_from_slice(message);
+    tlv.push(TERMINATOR_TLV);
+
+    Ok(tlv)
+}
+
+/// Finds the NDEF TLV in the data area.
+///
+/// # Returns
+///
+/// * `Ok((usize, usize))` - The offset and length of the NDEF message.
+///   The message may go past the end of `data` if only the start of the data area was read.
+/// * `Err(ReaderError)` - If there's no NDEF TLV before the terminator.
+pub(crate) fn find_message(data: &[u8]) -> Result<(usize, usize), ReaderError> {
+    let mut offset = 0;
+
+    while let Some(&tag) = data.get(offset) {
+        match tag {
+            NULL_TLV => {
+                offset += 1;
+                continue;
+            }
+            TERMINATOR_TLV => break,
+            _ => {}
+        }
+
+        let (length, header) = match data.get(offset + 1) {
+            Some(0xff) => {
+                let length = data
+                    .get(offset + 2..offset + 4)
+                    .ok_or_else(|| tlv_error("Truncated TLV"))?;
+                (u16::from_be_bytes([length[0], length[1]]) as usize, 4)
+            }
+            Some(&length) => (length as usize, 2),
+            None => return Err(tlv_error("Truncated TLV")),
+        };
+
+        if tag == NDEF_TLV {
+            return Ok((offset + header, length));
+        }
+
+        // Lock control, memory control and proprietary TLVs are skipped
+        offset += header + length;
+    }
+
+    Err(tlv_error("No NDEF message found"))
+}
+
+/// Calculates the CRC of the MAD, CRC-8 with polynomial 0x1D and preset 0xC7.
+fn mad_crc(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xc7;
+
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x1d
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Builds the MAD v1 (blocks 1 and 2) marking the first `sectors` sectors as NDEF.
+pub(crate) fn build_mad(sectors: u8) -> Result<[u8; 32], ReaderError> {
+    if sectors == 0 || sectors > MAD_SECTORS {
+        return Err(tlv_error("The message doesn't fit in the card"));
+    }
+
+    let mut mad = [0u8; 32];
+    // Info byte, points to the card publisher sector
+    mad[1] = 0x01;
+    for sector in 0..sectors as usize {
+        mad[2 + sector * 2..4 + sector * 2].copy_from_slice(&NDEF_AID);
+    }
+    mad[0] = mad_crc(&mad[1..]);
+
+    Ok(mad)
+}
+
+/// Parses the MAD v1 and returns the NDEF sectors, in order.
+pub(crate) fn parse_mad(mad: &[u8]) -> Result<Vec<u8>, ReaderError> {
+    if mad.len() != 32 || mad[0] != mad_crc(&mad[1..]) {
+        return Err(tlv_error("Invalid MAD"));
+    }
+
+    Ok((1..=MAD_SECTORS)
+        .filter(|sector| {
+            let offset = *sector as usize * 2;
+            mad[offset..offset + 2] == NDEF_AID
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tlv() {
+        let tlv = wrap_message(&[0xd1, 0x01, 0x00, b'T']).unwrap();
+        assert_eq!(tlv, vec![0x03, 0x04, 0xd1, 0x01, 0x00, b'T', 0xfe]);
+
+        // Preceded by a NULL and a lock control TLV
+        let data = [&[0x00, 0x01, 0x03, 0xa0, 0x0c, 0x34][..], &tlv].concat();
+        assert_eq!(find_message(&data).unwrap(), (8, 4));
+        assert_eq!(
+            find_message(&wrap_message(&[0; 300]).unwrap()).unwrap(),
+            (4, 300)
+        );
+        assert!(find_message(&[0x00, 0xfe, 0x03, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_mad() {
+        // Every sector of a 1K card formatted as NDEF
+        let mad = build_mad(15).unwrap();
+        assert_eq!(&mad[..4], &[0x14, 0x01, 0x03, 0xe1]);
+        assert_eq!(parse_mad(&mad).unwrap(), (1..=15).collect::<Vec<u8>>());
+
+        let mad = build_mad(2).unwrap();
+        assert_eq!(parse_mad(&mad).unwrap(), vec![1, 2]);
+        assert!(parse_mad(&[0; 32]).is_err());
+    }
+}
@@ -1,9 +1,13 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use pcsc::*;
+use pcsc::Error;
 
-use crate::acr122u::card::utils::authenticate::{authenticate_14443_3, KeyType};
+use crate::acr122u::card::utils::keys::{authenticate_block, KeyStore};
+use crate::acr122u::card::utils::layout::CardType;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::settings::card_deadline;
 use crate::acr122u::utils::errors::ReaderError;
 
 /// When accessing a Mifare Classic 1K card blocks with this library, blocks are numbered as follows:
@@ -28,17 +32,21 @@ use crate::acr122u::utils::errors::ReaderError;
 /// as the sector trailer contains the access bits,
 /// and the key A and key B. Overwriting the sector trailer can make the card unreadable.
 ///
+/// On Mifare Classic 4K cards, sectors 32 to 39 have 16 blocks each, starting at block 128.
+/// Ultralight and NTAG21x cards have no sectors, `block_number` is the first 4 bytes page to read.
+///
 /// Reads data from a specified block on a card.
 ///
 /// # Arguments
 ///
-/// * `ctx` - The PC/SC context.
-/// * `reader` - The name of the reader.
+/// * `reader` - The driver of the reader to read with.
+/// * `keys` - The key store with the key of each sector.
 /// * `block_number` - The block number to read from.
 /// * `cancel_flag` - A flag to cancel the operation.
 /// * `length` - The length of data to read (optional).
 /// * `block_size` - The size of each block (optional).
 /// * `packet_size` - The size of each packet (optional).
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
 ///
 /// # Returns
 ///
@@ -48,185 +56,235 @@ use crate::acr122u::utils::errors::ReaderError;
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The reader name is invalid.
 /// * The read operation fails.
 /// * The card type is unsupported.
 /// * The operation is canceled.
+/// * No card is presented before the timeout, with `ReaderError::Timeout`.
 ///
 /// # Examples
 ///
 /// ```
-/// let (ctx, reader) = reader().unwrap();
+/// let mut driver = open_reader(None).unwrap();
+/// let keys = KeyStore::load().unwrap();
 /// let cancel_flag = Arc::new(AtomicBool::new(false));
-/// let result = read_block(ctx, reader, 4, &cancel_flag, None, None, None).await;
+/// let timeout = Some(Duration::from_secs(10));
+/// let result = read_block(driver.as_mut(), &keys, 4, &cancel_flag, None, None, None, timeout).await;
 /// match result {
 ///     Ok(data) => println!("Data: {:?}", data),
 ///     Err(e) => println!("Error: {:?}", e),
 /// }
 /// ```
+#[allow(clippy::too_many_arguments)]
 pub async fn read_block(
-    ctx: Context,
-    reader: String,
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
     block_number: u16,
     cancel_flag: &Arc<AtomicBool>,
     length: Option<u16>,
     block_size: Option<u16>,
     packet_size: Option<u16>,
+    timeout: Option<Duration>,
 ) -> Result<Vec<u8>, ReaderError> {
-    let reader = std::ffi::CString::new(reader)
-        .map_err(|_| ReaderError::UnsupportedReader("Invalid reader name".to_string()))?;
-
     let length = length.unwrap_or(16);
     let packet_size = packet_size.unwrap_or(16);
     let block_size = block_size.unwrap_or(4);
 
-    if length > packet_size {
-        // Math.ceil(length / packet_size)
-        let p = (length as f32 / packet_size as f32).ceil() as u16;
-        let mut commands = Vec::new();
-
-        //for (let i = 0; i < p; i++)
-        for i in 0..p {
-            let block = block_number + (i * packet_size) / block_size;
-
-            let size = if (i + 1) * packet_size < length {
-                packet_size
-            } else {
-                length - i * packet_size
-            };
-
-            commands.push(read_block(
-                ctx.clone(),
-                reader.clone().into_string().unwrap(),
-                block,
-                cancel_flag,
-                Some(size),
-                Some(block_size),
-                Some(packet_size),
-            ));
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+
+    // Get the card type from the ATR, anything that isn't Mifare Classic, Ultralight or NTAG21x is rejected
+    let card_type = CardType::detect(reader, &atr)?;
+    if !card_type.is_classic() {
+        return read_pages(reader, block_number, length, cancel_flag);
+    }
+
+    // Math.ceil(length / packet_size)
+    let p = (length as f32 / packet_size as f32).ceil() as u16;
+    let mut data = Vec::new();
+
+    //for (let i = 0; i < p; i++)
+    for i in 0..p {
+        if cancel_flag.load(Ordering::SeqCst) {
+            return Err(ReaderError::OperationCancelled("Read Card".to_string()));
         }
 
-        let responses = futures::future::join_all(commands)
-            .await
-            .into_iter()
-            .map(|c| c.map_err(|e| e))
-            .collect::<Result<Vec<_>, _>>()?;
+        let block = block_number + (i * packet_size) / block_size;
+
+        let size = if (i + 1) * packet_size < length {
+            packet_size
+        } else {
+            length - i * packet_size
+        };
+
+        //First let's authenticate the card with the key of the block's sector
+        authenticate_block(reader, block, keys)?;
+
+        let response = reader.read_block(block, size as u8)?;
+        data.extend_from_slice(&response);
+    }
+
+    Ok(data)
+}
+
+/// Reads `length` bytes from consecutive pages of an Ultralight or NTAG21x card, no authentication needed.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader, already connected to the card.
+/// * `first_page` - The page to start reading from.
+/// * `length` - The number of bytes to read.
+/// * `cancel_flag` - A flag to cancel the operation.
+pub(crate) fn read_pages(
+    reader: &mut dyn CardReader,
+    first_page: u16,
+    length: u16,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u8>, ReaderError> {
+    let pages = length.div_ceil(4);
+    let mut data = Vec::new();
 
-        let mut data = Vec::new();
-        for response in responses {
-            data.extend_from_slice(&response);
+    for page in first_page..first_page + pages {
+        if cancel_flag.load(Ordering::SeqCst) {
+            return Err(ReaderError::OperationCancelled("Read Card".to_string()));
         }
 
-        Ok(data)
-    } else {
-        let mut reader_states = vec![ReaderState::new(reader, State::UNAWARE)];
-
-        let mut last_event_count = reader_states[0].event_count();
-
-        loop {
-            ctx.get_status_change(None, &mut reader_states)
-                .map_err(ReaderError::PcscError)?;
-
-            loop {
-                ctx.get_status_change(None, &mut reader_states)
-                    .map_err(ReaderError::PcscError)?;
-
-                for reader_state in &mut reader_states {
-                    if reader_state.event_count() != last_event_count {
-                        if reader_state.event_state().contains(State::CHANGED) {
-                            if reader_state.event_state().contains(State::PRESENT) {
-                                let mut card = ctx
-                                    .connect(reader_state.name(), ShareMode::Shared, Protocols::ANY)
-                                    .map_err(ReaderError::PcscError)?;
-
-                                {
-                                    if cancel_flag.load(Ordering::SeqCst) {
-                                        println!("Operation cancelled at card connection.");
-                                        return Err(ReaderError::OperationCancelled(
-                                            "Read Card".to_string(),
-                                        ));
-                                    }
-
-                                    let tx = card.transaction().map_err(ReaderError::PcscError)?;
-
-                                    // Get the card tag type: TAG_ISO_14443_3 is Mifare, TAG_ISO_14443_4 is FeliCa
-                                    let status =
-                                        tx.status2_owned().map_err(ReaderError::PcscError)?;
-                                    let atr = status.atr();
-                                    return if atr.starts_with(&[0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F])
-                                    {
-                                        let packet = [
-                                            0xff,
-                                            0xb0,
-                                            ((block_number >> 8) & 0xff) as u8, // High byte of block_number
-                                            (block_number & 0xff) as u8, // Low byte of block_number
-                                            length as u8,
-                                        ];
-
-                                        //First let's authenticate the card
-                                        authenticate_14443_3(&tx, block_number as u8, KeyType::A)
-                                            .map_err(|e| e)?;
-
-                                        let mut response_buf = [0; 128]; // Adjust the size as needed
-                                        let response = tx
-                                            .transmit(&packet, &mut response_buf)
-                                            .map_err(ReaderError::PcscError)?;
-
-                                        if response.len() < 2 {
-                                            return Err(ReaderError::CardError(
-                                                "Invalid response.".to_string(),
-                                                Error::InvalidParameter,
-                                            ));
-                                        }
-
-                                        //Status code is of UINT16BE type
-                                        let status_code = ((response[response.len() - 2] as u16)
-                                            << 8)
-                                            | response[response.len() - 1] as u16;
-
-                                        if status_code != 0x9000 {
-                                            return Err(ReaderError::CardError(
-                                                "Read failed.".to_string(),
-                                                Error::InvalidParameter,
-                                            ));
-                                        }
-
-                                        Ok(response[0..response.len() - 2].to_vec())
-                                    } else {
-                                        Err(ReaderError::CardError(
-                                            "Unsupported card type.".to_string(),
-                                            Error::CardUnsupported,
-                                        ))
-                                    };
-                                }
-                            }
-                            // Sync the current state to the event state after handling the event
-                            reader_state.sync_current_state();
-                        }
+        let response = reader.read_block(page, 4)?;
+        data.extend_from_slice(&response);
+    }
 
-                        last_event_count = reader_state.event_count();
-                    }
-                }
+    data.truncate(length as usize);
+    Ok(data)
+}
 
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-            }
+/// Reads `length` bytes from consecutive data blocks, or pages on Ultralight and NTAG21x cards, starting at `first_block`.
+///
+/// Sector trailers are skipped the same way `write_data` skips them, so it reads back what `write_data` wrote.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader, already connected to the card.
+/// * `keys` - The key store with the key of each sector, only used by Mifare Classic cards.
+/// * `card_type` - The type of the connected card.
+/// * `first_block` - The block or page to start reading at.
+/// * `length` - The number of bytes to read.
+/// * `cancel_flag` - A flag to cancel the operation.
+pub(crate) fn read_data(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    card_type: CardType,
+    first_block: u16,
+    length: usize,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u8>, ReaderError> {
+    let block_size = card_type.block_size() as usize;
+    let blocks: Vec<u16> = card_type
+        .data_blocks(first_block)
+        .take(length.div_ceil(block_size))
+        .collect();
+
+    if blocks.first() != Some(&first_block) || blocks.len() * block_size < length {
+        return Err(ReaderError::CardError(
+            format!(
+                "{} bytes from block {} go past the user memory of the card.",
+                length, first_block
+            ),
+            Error::InvalidParameter,
+        ));
+    }
+
+    let mut data = Vec::with_capacity(blocks.len() * block_size);
+
+    for block in blocks {
+        if cancel_flag.load(Ordering::SeqCst) {
+            return Err(ReaderError::OperationCancelled("Read Card".to_string()));
+        }
+
+        if card_type.is_classic() {
+            authenticate_block(reader, block, keys)?;
         }
+
+        data.extend(reader.read_block(block, block_size as u8)?);
     }
+
+    data.truncate(length);
+    Ok(data)
+}
+
+/// Reads `length` bytes of raw data starting at `first_block`, across as many blocks or pages as needed.
+///
+/// Unlike `read_block`, it follows the same layout as `write_data`, skipping the sector trailers,
+/// so it reads back exactly what `write_card_raw` wrote.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to read with.
+/// * `keys` - The key store with the key of each sector.
+/// * `first_block` - The block or page to start reading at.
+/// * `length` - The number of bytes to read.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The data, padding included.
+/// * `Err(ReaderError)` - If the range goes past the user memory of the card or a block can't be read.
+pub(crate) async fn read_range(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    first_block: u16,
+    length: usize,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>, ReaderError> {
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+
+    let card_type = CardType::detect(reader, &atr)?;
+    read_data(reader, keys, card_type, first_block, length, cancel_flag)
+}
+
+/// Reads the hardware UID of the card with the GET DATA command (FF CA 00 00 00).
+///
+/// Unlike the data blocks, the UID doesn't need authentication,
+/// so it works with cards that were never written to.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to read with.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The UID of the card, 4 or 7 bytes long.
+/// * `Err(ReaderError)` - If an error occurs while reading the UID.
+pub async fn read_uid(
+    reader: &mut dyn CardReader,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>, ReaderError> {
+    // Waits for the card to be present on the reader
+    reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+
+    reader.get_uid()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::acr122u::reader::connect::reader;
+    use super::*;
+    use crate::acr122u::card::write::write_data;
+    use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
 
     /// Tests the `read_block` function.
     ///
-    /// This test connects to a reader, reads data from a specified block, and prints the data.
+    /// This test connects to a simulated reader, reads data from a specified block, and prints the data.
     /// It verifies that the data is read successfully and converts it to a readable format.
     #[tokio::test]
     async fn test_read_block() {
-        let (ctx, reader) = reader().unwrap();
+        let mut driver = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+        let keys = KeyStore::default();
         let cancel_flag = Arc::new(AtomicBool::new(false));
-        let result = read_block(ctx, reader, 4, &cancel_flag, None, None, None);
+        let result = read_block(&mut driver, &keys, 4, &cancel_flag, None, None, None, None);
 
         match result.await {
             Ok(data) => {
@@ -255,4 +313,40 @@ mod tests {
             }
         }
     }
+
+    /// Tests the `read_range` function.
+    ///
+    /// This test writes data across the trailer of sector 1 of a simulated card and reads it back,
+    /// so it checks that the trailer is skipped the same way `write_data` skips it.
+    #[tokio::test]
+    async fn test_read_range() {
+        let mut driver = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+        let keys = KeyStore::default();
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+
+        let atr = driver.connect(&cancel_flag, None).unwrap();
+        let card_type = CardType::detect(&mut driver, &atr).unwrap();
+        let payload: Vec<u8> = (0..40).collect();
+        write_data(&mut driver, &keys, card_type, 6, &payload, &cancel_flag).unwrap();
+
+        let data = read_range(&mut driver, &keys, 6, 40, &cancel_flag, None)
+            .await
+            .unwrap();
+        assert_eq!(data, payload);
+
+        // A range can't start at a trailer or run past the end of the card
+        for first_block in [7, 62] {
+            let result = read_range(&mut driver, &keys, first_block, 40, &cancel_flag, None);
+            assert!(result.await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_uid() {
+        let mut driver = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+
+        let uid = read_uid(&mut driver, &cancel_flag, None).await.unwrap();
+        assert_eq!(hex::encode_upper(uid), "04A23B1C");
+    }
 }
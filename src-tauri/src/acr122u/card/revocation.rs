@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::utils::errors::ReaderError;
+
+/// The sled tree keeping the local copy of the `revoked_cards` collection, so taps are checked offline too.
+const REVOKED_TREE: &str = "revoked_cards";
+
+/// A lost or stolen card that must not punch anyone in anymore.
+///
+/// * `uid` - The card UID as an uppercase hex string.
+/// * `reason` - Why the card was revoked, shown on the kiosk when it's tapped.
+/// * `revoked_by` - The id of the admin who revoked it.
+/// * `revoked_at` - When it was revoked, in RFC 3339.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RevokedCard {
+    pub(crate) uid: String,
+    pub(crate) reason: String,
+    pub(crate) revoked_by: String,
+    pub(crate) revoked_at: String,
+}
+
+fn revocation_error(message: &str) -> ReaderError {
+    ReaderError::BadgeError(format!("Revocation list: {}", message))
+}
+
+fn revoked_tree(db: &sled::Db) -> Result<sled::Tree, ReaderError> {
+    db.open_tree(REVOKED_TREE)
+        .map_err(|e| revocation_error(&e.to_string()))
+}
+
+/// Finds the card in the local revocation list.
+pub(crate) fn find_revoked(db: &sled::Db, uid: &str) -> Result<Option<RevokedCard>, ReaderError> {
+    match revoked_tree(db)?.get(uid.as_bytes()) {
+        Ok(Some(card)) => bincode::deserialize(&card)
+            .map(Some)
+            .map_err(|e| revocation_error(&e.to_string())),
+        Ok(None) => Ok(None),
+        Err(e) => Err(revocation_error(&e.to_string())),
+    }
+}
+
+/// Fails with `CardRevoked` if the card is in the local revocation list.
+pub(crate) fn check_revoked(db: &sled::Db, uid: &str) -> Result<(), ReaderError> {
+    match find_revoked(db, uid)? {
+        Some(card) => Err(ReaderError::CardRevoked(card.reason)),
+        None => Ok(()),
+    }
+}
+
+/// Lists every card in the local revocation list.
+pub(crate) fn list_revoked(db: &sled::Db) -> Result<Vec<RevokedCard>, ReaderError> {
+    revoked_tree(db)?
+        .iter()
+        .values()
+        .map(|card| {
+            let card = card.map_err(|e| revocation_error(&e.to_string()))?;
+            bincode::deserialize(&card).map_err(|e| revocation_error(&e.to_string()))
+        })
+        .collect()
+}
+
+/// Adds the card to the local revocation list.
+pub(crate) fn save_revoked(db: &sled::Db, card: &RevokedCard) -> Result<(), ReaderError> {
+    let tree = revoked_tree(db)?;
+    let data = bincode::serialize(card).map_err(|e| revocation_error(&e.to_string()))?;
+
+    tree.insert(card.uid.as_bytes(), data)
+        .map_err(|e| revocation_error(&e.to_string()))?;
+    tree.flush().map_err(|e| revocation_error(&e.to_string()))?;
+    Ok(())
+}
+
+/// Takes the card out of the local revocation list.
+pub(crate) fn remove_revoked(db: &sled::Db, uid: &str) -> Result<(), ReaderError> {
+    let tree = revoked_tree(db)?;
+
+    tree.remove(uid.as_bytes())
+        .map_err(|e| revocation_error(&e.to_string()))?;
+    tree.flush().map_err(|e| revocation_error(&e.to_string()))?;
+    Ok(())
+}
+
+/// Replaces the local revocation list with the one from the database.
+pub(crate) fn replace_revoked(db: &sled::Db, cards: &[RevokedCard]) -> Result<(), ReaderError> {
+    let tree = revoked_tree(db)?;
+    tree.clear().map_err(|e| revocation_error(&e.to_string()))?;
+
+    for card in cards {
+        save_revoked(db, card)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revocation_list() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let card = |uid: &str| RevokedCard {
+            uid: uid.to_string(),
+            reason: "Lost".to_string(),
+            revoked_by: "hOtB6pOxiL2IQPYs".to_string(),
+            revoked_at: "2024-10-01T08:00:00Z".to_string(),
+        };
+
+        save_revoked(&db, &card("04A23B1C")).unwrap();
+        assert_eq!(
+            find_revoked(&db, "04A23B1C").unwrap(),
+            Some(card("04A23B1C"))
+        );
+        assert!(matches!(
+            check_revoked(&db, "04A23B1C"),
+            Err(ReaderError::CardRevoked(reason)) if reason == "Lost"
+        ));
+        assert!(check_revoked(&db, "04FFFFFF").is_ok());
+
+        replace_revoked(&db, &[card("04FFFFFF")]).unwrap();
+        assert_eq!(list_revoked(&db).unwrap(), vec![card("04FFFFFF")]);
+
+        remove_revoked(&db, "04FFFFFF").unwrap();
+        assert!(list_revoked(&db).unwrap().is_empty());
+    }
+}
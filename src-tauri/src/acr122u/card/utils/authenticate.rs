@@ -1,6 +1,8 @@
+use crate::acr122u::driver::{check_status, CardReader};
 use crate::acr122u::utils::errors::ReaderError;
-use pcsc::{Error, Transaction};
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum KeyType {
     A = 0x60,
     B = 0x61,
@@ -8,11 +10,14 @@ pub(crate) enum KeyType {
 
 /// This function authenticates the card for reading.
 ///
+/// The key must already be loaded in `key_slot` (see `CardReader::load_key`).
+///
 /// Returns a Result with an empty tuple if successful, or a ReaderError if not.
-pub(crate) fn authenticate_14443_3(
-    tx: &Transaction,
+pub(crate) fn authenticate_14443_3<R: CardReader + ?Sized>(
+    reader: &mut R,
     block_number: u8,
     key_type: KeyType,
+    key_slot: u8,
 ) -> Result<(), ReaderError> {
     let key_type = key_type as u8;
 
@@ -26,33 +31,10 @@ pub(crate) fn authenticate_14443_3(
         0x00,
         block_number,
         key_type,
-        0x00,
+        key_slot,
     ];
 
-    let mut response_buf = [0; 512]; // Adjust the size as needed
-
-    let response = tx
-        .transmit(&command, &mut response_buf)
-        .map_err(ReaderError::PcscError)?;
-
-    if response.len() >= 2 {
-        let sw1 = response[response.len() - 2];
-        let sw2 = response[response.len() - 1];
+    let response = reader.transmit(&command)?;
 
-        if sw1 == 0x90 && sw2 == 0x00 {
-            Ok(())
-        } else {
-            //Return error
-            return Err(ReaderError::CardError(
-                "Authentication Failed.".to_string(),
-                Error::CardNotAuthenticated,
-            ));
-        }
-    } else {
-        //Return error
-        return Err(ReaderError::CardError(
-            "Invalid response.".to_string(),
-            Error::CardNotAuthenticated,
-        ));
-    }
+    check_status(&command, &response)
 }
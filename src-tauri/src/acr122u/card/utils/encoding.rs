@@ -0,0 +1,120 @@
+use base64::{engine::general_purpose, Engine as _};
+use pcsc::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::utils::errors::ReaderError;
+
+/// How raw card data is passed to and from the frontend.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum DataEncoding {
+    /// Uppercase hex, separators like "04:A2" or "04 A2" are accepted when decoding.
+    #[default]
+    Hex,
+    /// Standard base64 with padding.
+    Base64,
+}
+
+impl DataEncoding {
+    pub(crate) fn encode(&self, data: &[u8]) -> String {
+        match self {
+            DataEncoding::Hex => hex::encode_upper(data),
+            DataEncoding::Base64 => general_purpose::STANDARD.encode(data),
+        }
+    }
+
+    pub(crate) fn decode(&self, data: &str) -> Result<Vec<u8>, ReaderError> {
+        let decoded = match self {
+            DataEncoding::Hex => {
+                hex::decode(data.replace([':', ' '], "")).map_err(|e| e.to_string())
+            }
+            DataEncoding::Base64 => general_purpose::STANDARD
+                .decode(data.trim())
+                .map_err(|e| e.to_string()),
+        };
+
+        decoded.map_err(|e| {
+            ReaderError::CardError(
+                format!("The data is not valid {:?}: {}", self, e),
+                Error::InvalidParameter,
+            )
+        })
+    }
+}
+
+/// Drops the NUL padding the writes add to fill the last block.
+pub(crate) fn trim_padding(data: &[u8]) -> &[u8] {
+    let end = data
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+/// Decodes text read from the card, without its padding.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The text.
+/// * `Err(ReaderError)` - If the data isn't UTF-8, `read_card_raw` is the way to read binary data.
+pub(crate) fn decode_text(data: &[u8]) -> Result<String, ReaderError> {
+    String::from_utf8(trim_padding(data).to_vec()).map_err(|e| {
+        ReaderError::CardError(
+            format!(
+                "The data is not UTF-8 text ({}), read it with read_card_raw instead.",
+                e.utf8_error()
+            ),
+            Error::InvalidValue,
+        )
+    })
+}
+
+/// Checks that the text can be written and read back as is.
+///
+/// NUL is the padding of the blocks, so text containing it would come back cut short.
+pub(crate) fn validate_text(text: &str) -> Result<(), ReaderError> {
+    if text.contains('\0') {
+        return Err(ReaderError::CardError(
+            "Text can't contain NUL characters, write it with write_card_raw instead.".to_string(),
+            Error::InvalidParameter,
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let data = [0x04, 0xa2, 0x3b, 0x00, 0xff];
+
+        for encoding in [DataEncoding::Hex, DataEncoding::Base64] {
+            let encoded = encoding.encode(&data);
+            assert_eq!(encoding.decode(&encoded).unwrap(), data);
+        }
+
+        assert_eq!(DataEncoding::Hex.encode(&data), "04A23B00FF");
+        assert_eq!(
+            DataEncoding::Hex.decode("04:a2:3b").unwrap(),
+            [0x04, 0xa2, 0x3b]
+        );
+        assert!(DataEncoding::Hex.decode("04A").is_err());
+        assert!(DataEncoding::Base64.decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_text() {
+        let mut block = b"hOtB6pOx".to_vec();
+        block.resize(16, 0);
+
+        assert_eq!(trim_padding(&block), b"hOtB6pOx");
+        assert_eq!(trim_padding(&[0; 16]), b"");
+        assert_eq!(decode_text(&block).unwrap(), "hOtB6pOx");
+        assert!(decode_text(&[0xff, 0xfe, 0x00]).is_err());
+
+        assert!(validate_text("hOtB6pOx").is_ok());
+        assert!(validate_text("hOtB\0pOx").is_err());
+    }
+}
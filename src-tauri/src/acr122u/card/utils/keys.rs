@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::card::utils::authenticate::KeyType;
+use crate::acr122u::card::utils::layout::sector_of;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Factory default key of Mifare Classic cards.
+pub(crate) const DEFAULT_KEY: [u8; 6] = [0xff; 6];
+
+/// The reader key slot the keys are loaded into before authenticating.
+///
+/// The ACR122U and ACR1252U have two volatile slots, 0x00 and 0x01, only the first one is used.
+pub(crate) const KEY_SLOT: u8 = 0x00;
+
+/// A key and which of the two sector keys (A or B) it is.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SectorKey {
+    pub(crate) key_type: KeyType,
+    pub(crate) key: [u8; 6],
+}
+
+impl Default for SectorKey {
+    fn default() -> Self {
+        SectorKey {
+            key_type: KeyType::A,
+            key: DEFAULT_KEY,
+        }
+    }
+}
+
+/// The Mifare keys used by the app, kept in the system keyring.
+///
+/// * `default` - The key used by sectors without a mapping, the factory key A if not set.
+/// * `sectors` - Maps a sector number to the key used to authenticate it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct KeyStore {
+    pub(crate) default: Option<SectorKey>,
+    #[serde(default)]
+    pub(crate) sectors: HashMap<u8, SectorKey>,
+}
+
+/// What the frontend gets to see from the key store, the keys themselves never leave the backend.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct KeyMapping {
+    pub(crate) default: KeyType,
+    pub(crate) custom_default: bool,
+    pub(crate) sectors: HashMap<u8, KeyType>,
+}
+
+fn get_entry() -> Result<Entry, ReaderError> {
+    Entry::new("PontuAll", "mifare_keys").map_err(|e| ReaderError::KeyStoreError(e.to_string()))
+}
+
+impl KeyStore {
+    /// Loads the key store from the keyring, returns an empty one if nothing was saved yet.
+    pub(crate) fn load() -> Result<KeyStore, ReaderError> {
+        match get_entry()?.get_password() {
+            Ok(keys_json) => serde_json::from_str(&keys_json)
+                .map_err(|e| ReaderError::KeyStoreError(e.to_string())),
+            Err(keyring::Error::NoEntry) => Ok(KeyStore::default()),
+            Err(e) => Err(ReaderError::KeyStoreError(e.to_string())),
+        }
+    }
+
+    /// Saves the key store to the keyring.
+    pub(crate) fn save(&self) -> Result<(), ReaderError> {
+        let keys_json =
+            serde_json::to_string(self).map_err(|e| ReaderError::KeyStoreError(e.to_string()))?;
+
+        get_entry()?
+            .set_password(&keys_json)
+            .map_err(|e| ReaderError::KeyStoreError(e.to_string()))
+    }
+
+    /// Gets the key for a sector, falling back to the default key.
+    pub(crate) fn key_for_sector(&self, sector: u8) -> SectorKey {
+        self.sectors
+            .get(&sector)
+            .or(self.default.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn mapping(&self) -> KeyMapping {
+        KeyMapping {
+            default: self.key_for_sector(u8::MAX).key_type,
+            custom_default: self.default.is_some(),
+            sectors: self
+                .sectors
+                .iter()
+                .map(|(sector, key)| (*sector, key.key_type))
+                .collect(),
+        }
+    }
+}
+
+/// Parses a key written as hex, e.g. "A0A1A2A3A4A5" or "a0:a1:a2:a3:a4:a5".
+pub(crate) fn parse_key(key: &str) -> Result<[u8; 6], ReaderError> {
+    let key = key.replace([':', ' '], "");
+    let bytes = hex::decode(&key)
+        .map_err(|_| ReaderError::KeyStoreError("Key must be written as hex".to_string()))?;
+
+    bytes
+        .try_into()
+        .map_err(|_| ReaderError::KeyStoreError("Key must be 6 bytes long".to_string()))
+}
+
+/// Loads the key of the block's sector into the reader and authenticates the block with it.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader.
+/// * `block_number` - The block to authenticate.
+/// * `keys` - The key store to get the key from.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the authentication is successful.
+/// * `Err(ReaderError)` - If the key couldn't be loaded or the authentication failed.
+pub(crate) fn authenticate_block(
+    reader: &mut dyn CardReader,
+    block_number: u16,
+    keys: &KeyStore,
+) -> Result<(), ReaderError> {
+    let sector_key = keys.key_for_sector(sector_of(block_number));
+
+    reader.load_key(KEY_SLOT, &sector_key.key)?;
+    reader.authenticate(block_number, sector_key.key_type, KEY_SLOT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_for_sector() {
+        let mut keys = KeyStore::default();
+        assert_eq!(keys.key_for_sector(1), SectorKey::default());
+
+        let custom = SectorKey {
+            key_type: KeyType::B,
+            key: [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5],
+        };
+        keys.sectors.insert(1, custom.clone());
+        assert_eq!(keys.key_for_sector(1), custom);
+        assert_eq!(keys.key_for_sector(2), SectorKey::default());
+
+        // Survives the round trip through the keyring
+        let keys_json = serde_json::to_string(&keys).unwrap();
+        let keys: KeyStore = serde_json::from_str(&keys_json).unwrap();
+        assert_eq!(keys.key_for_sector(1), custom);
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(
+            parse_key("a0:a1:a2:a3:a4:a5").unwrap(),
+            [0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5]
+        );
+        assert!(parse_key("FFFFFFFF").is_err());
+        assert!(parse_key("not a key").is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use std::ops::RangeInclusive;
+
+use pcsc::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// The ATR prefix of the storage cards as reported by PC/SC readers (PC/SC Part 3, section 3.1.3.2.3).
+const STORAGE_CARD_ATR: [u8; 6] = [0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F];
+
+/// The cards we know how to read and write.
+///
+/// Mifare Classic cards are split in sectors protected by keys and use 16 bytes blocks.
+/// Ultralight and NTAG21x cards have no keys and use 4 bytes pages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CardType {
+    MifareClassic1K,
+    MifareClassic4K,
+    MifareUltralight,
+    Ntag213,
+    Ntag215,
+    Ntag216,
+}
+
+impl CardType {
+    /// Detects the card type from the card name bytes of the ATR.
+    ///
+    /// The reader builds the ATR from the SAK of the card, so this is as good as reading the SAK.
+    /// Ultralight and NTAG21x share the same card name, use `detect` to tell them apart.
+    pub(crate) fn from_atr(atr: &[u8]) -> Result<CardType, ReaderError> {
+        if !atr.starts_with(&STORAGE_CARD_ATR) || atr.len() < 15 {
+            return Err(unsupported_card());
+        }
+
+        // 3B 8F 80 01 80 4F 0C A0 00 00 03 06 SS C0 C1, C0 C1 being the card name
+        match (atr[13], atr[14]) {
+            (0x00, 0x01) => Ok(CardType::MifareClassic1K),
+            (0x00, 0x02) => Ok(CardType::MifareClassic4K),
+            (0x00, 0x03) => Ok(CardType::MifareUltralight),
+            _ => Err(unsupported_card()),
+        }
+    }
+
+    /// Detects the card type of the connected card.
+    ///
+    /// NTAG21x cards are told apart from Ultralight by the memory size in their capability container (page 3).
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The driver of the reader, already connected to the card.
+    /// * `atr` - The ATR returned when connecting.
+    pub(crate) fn detect(reader: &mut dyn CardReader, atr: &[u8]) -> Result<CardType, ReaderError> {
+        let card_type = CardType::from_atr(atr)?;
+        if card_type != CardType::MifareUltralight {
+            return Ok(card_type);
+        }
+
+        let capability_container = reader.read_block(3, 4)?;
+        Ok(match capability_container.get(2) {
+            Some(0x12) => CardType::Ntag213,
+            Some(0x3e) => CardType::Ntag215,
+            Some(0x6d) => CardType::Ntag216,
+            // Plain Ultralight, or a blank card without a capability container
+            _ => CardType::MifareUltralight,
+        })
+    }
+
+    /// Checks if the card uses sectors and keys.
+    pub(crate) fn is_classic(&self) -> bool {
+        matches!(self, CardType::MifareClassic1K | CardType::MifareClassic4K)
+    }
+
+    /// The number of sectors of Mifare Classic cards, 0 for cards without sectors.
+    pub(crate) fn sector_count(&self) -> u8 {
+        match self {
+            CardType::MifareClassic1K => 16,
+            CardType::MifareClassic4K => 40,
+            _ => 0,
+        }
+    }
+
+    /// The size in bytes of a block (Mifare Classic) or page (Ultralight and NTAG21x).
+    pub(crate) fn block_size(&self) -> u16 {
+        if self.is_classic() {
+            16
+        } else {
+            4
+        }
+    }
+
+    /// The blocks or pages the app may write data to.
+    ///
+    /// Mifare Classic excludes the manufacturer block, the sector trailers still have to be skipped.
+    /// Ultralight and NTAG21x exclude the UID, lock bytes, capability container and configuration pages.
+    pub(crate) fn user_blocks(&self) -> RangeInclusive<u16> {
+        match self {
+            CardType::MifareClassic1K => 1..=63,
+            CardType::MifareClassic4K => 1..=255,
+            CardType::MifareUltralight => 4..=15,
+            CardType::Ntag213 => 4..=39,
+            CardType::Ntag215 => 4..=129,
+            CardType::Ntag216 => 4..=225,
+        }
+    }
+
+    /// Lists the blocks or pages data can be written to, from `first_block` to the end of the card.
+    ///
+    /// Sector trailers are skipped, the data carries on in the first block of the next sector.
+    pub(crate) fn data_blocks(&self, first_block: u16) -> impl Iterator<Item = u16> {
+        let user_blocks = self.user_blocks();
+        let is_classic = self.is_classic();
+
+        (first_block.max(*user_blocks.start())..=*user_blocks.end())
+            .filter(move |block| !is_classic || !is_sector_trailer(*block))
+    }
+}
+
+fn unsupported_card() -> ReaderError {
+    ReaderError::CardError("Unsupported card type.".to_string(), Error::CardUnsupported)
+}
+
+/// Gets the sector of a Mifare Classic block.
+///
+/// The first 32 sectors have 4 blocks, on 4K cards the last 8 sectors have 16 blocks.
+pub(crate) fn sector_of(block_number: u16) -> u8 {
+    if block_number < 128 {
+        (block_number / 4) as u8
+    } else {
+        (32 + (block_number - 128) / 16) as u8
+    }
+}
+
+/// Checks if a Mifare Classic block is a sector trailer, the last block of its sector.
+pub(crate) fn is_sector_trailer(block_number: u16) -> bool {
+    if block_number < 128 {
+        block_number % 4 == 3
+    } else {
+        (block_number - 128) % 16 == 15
+    }
+}
+
+/// Gets the trailer block of a Mifare Classic sector.
+pub(crate) fn trailer_block(sector: u8) -> u16 {
+    if sector < 32 {
+        sector as u16 * 4 + 3
+    } else {
+        128 + (sector as u16 - 32) * 16 + 15
+    }
+}
+
+/// Gets every block of a Mifare Classic sector, the trailer being the last one.
+pub(crate) fn sector_blocks(sector: u8) -> RangeInclusive<u16> {
+    let first = if sector < 32 {
+        sector as u16 * 4
+    } else {
+        128 + (sector as u16 - 32) * 16
+    };
+
+    first..=trailer_block(sector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_type_from_atr() {
+        let atr = |c0: u8, c1: u8| {
+            vec![
+                0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F, 0x0C, 0xA0, 0x00, 0x00, 0x03, 0x06, 0x03, c0,
+                c1, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]
+        };
+
+        assert_eq!(
+            CardType::from_atr(&atr(0x00, 0x01)).unwrap(),
+            CardType::MifareClassic1K
+        );
+        assert_eq!(
+            CardType::from_atr(&atr(0x00, 0x02)).unwrap(),
+            CardType::MifareClassic4K
+        );
+        assert_eq!(
+            CardType::from_atr(&atr(0x00, 0x03)).unwrap(),
+            CardType::MifareUltralight
+        );
+        assert!(CardType::from_atr(&atr(0x00, 0x26)).is_err());
+        assert!(CardType::from_atr(&[0x3B, 0x80, 0x80, 0x01, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_classic_layout() {
+        assert_eq!(sector_of(7), 1);
+        assert_eq!(sector_of(127), 31);
+        assert_eq!(sector_of(128), 32);
+        assert_eq!(sector_of(255), 39);
+
+        assert!(is_sector_trailer(trailer_block(1)));
+        assert!(!is_sector_trailer(4));
+        assert!(!is_sector_trailer(131));
+        assert_eq!(trailer_block(32), 143);
+        assert_eq!(trailer_block(39), 255);
+        assert!((0..40).all(|sector| sector_of(trailer_block(sector)) == sector));
+
+        assert_eq!(sector_blocks(1), 4..=7);
+        assert_eq!(sector_blocks(32), 128..=143);
+        assert!((0..40).all(|sector| sector_blocks(sector).all(|block| sector_of(block) == sector)));
+        assert_eq!(CardType::MifareClassic4K.sector_count(), 40);
+    }
+
+    #[test]
+    fn test_data_blocks() {
+        let blocks: Vec<u16> = CardType::MifareClassic1K.data_blocks(5).take(5).collect();
+        assert_eq!(blocks, vec![5, 6, 8, 9, 10]);
+
+        // Block 0 holds the manufacturer data
+        assert_eq!(CardType::MifareClassic1K.data_blocks(0).next(), Some(1));
+        assert_eq!(CardType::MifareClassic1K.data_blocks(62).count(), 1);
+
+        // Upper sectors of 4K cards have 15 data blocks
+        assert_eq!(CardType::MifareClassic4K.data_blocks(128).count(), 8 * 15);
+
+        let pages: Vec<u16> = CardType::Ntag213.data_blocks(38).collect();
+        assert_eq!(pages, vec![38, 39]);
+    }
+}
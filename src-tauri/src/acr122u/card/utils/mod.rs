@@ -1 +1,5 @@
 pub mod authenticate;
+pub mod encoding;
+pub mod keys;
+pub mod layout;
+pub mod trailer;
@@ -0,0 +1,336 @@
+use pcsc::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::card::utils::authenticate::KeyType;
+use crate::acr122u::card::utils::keys::SectorKey;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Access conditions of a data block, named after what they allow.
+///
+/// The comment on each variant is the C1 C2 C3 bits from the Mifare Classic datasheet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DataBlockAccess {
+    /// 000 - Read and write with key A or B, the factory default.
+    ReadWriteAB,
+    /// 010 - Read with key A or B, never written again.
+    ReadOnlyAB,
+    /// 100 - Read with key A or B, write with key B.
+    ReadABWriteB,
+    /// 110 - Value block, read with key A or B, write and increment with key B, decrement with key A or B.
+    ValueWriteB,
+    /// 001 - Value block, read and decrement with key A or B, never written or incremented.
+    ValueDecrementOnly,
+    /// 011 - Read and write with key B only.
+    ReadWriteB,
+    /// 101 - Read with key B only, never written again.
+    ReadOnlyB,
+    /// 111 - No access at all.
+    Never,
+}
+
+/// Access conditions of the sector trailer itself, which control who can change the keys and the access bits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrailerAccess {
+    /// 000 - Key A writes the keys, key A reads the access bits and key B. Key B is readable, so it can't authenticate.
+    KeyAWritesKeys,
+    /// 010 - Nothing can be changed, key A reads the access bits and key B. Key B is readable.
+    KeyAReadOnly,
+    /// 100 - Key B writes the keys, the access bits are read-only.
+    KeyBWritesKeys,
+    /// 110 - Nothing can be changed, the access bits are readable with key A or B.
+    Frozen,
+    /// 001 - Key A writes everything, including the access bits. Key B is readable. The factory default.
+    Transport,
+    /// 011 - Key B writes everything, including the access bits. The recommended setting for issued cards.
+    KeyBManaged,
+    /// 101 - Key B writes the access bits but not the keys.
+    KeyBWritesAccessBits,
+    /// 111 - Nothing can be changed, the access bits are readable with key A or B.
+    Locked,
+}
+
+/// Maps the access conditions to the C1 C2 C3 bits, in this order.
+fn data_block_bits(access: DataBlockAccess) -> (u8, u8, u8) {
+    match access {
+        DataBlockAccess::ReadWriteAB => (0, 0, 0),
+        DataBlockAccess::ReadOnlyAB => (0, 1, 0),
+        DataBlockAccess::ReadABWriteB => (1, 0, 0),
+        DataBlockAccess::ValueWriteB => (1, 1, 0),
+        DataBlockAccess::ValueDecrementOnly => (0, 0, 1),
+        DataBlockAccess::ReadWriteB => (0, 1, 1),
+        DataBlockAccess::ReadOnlyB => (1, 0, 1),
+        DataBlockAccess::Never => (1, 1, 1),
+    }
+}
+
+fn data_block_access(bits: (u8, u8, u8)) -> DataBlockAccess {
+    match bits {
+        (0, 0, 0) => DataBlockAccess::ReadWriteAB,
+        (0, 1, 0) => DataBlockAccess::ReadOnlyAB,
+        (1, 0, 0) => DataBlockAccess::ReadABWriteB,
+        (1, 1, 0) => DataBlockAccess::ValueWriteB,
+        (0, 0, 1) => DataBlockAccess::ValueDecrementOnly,
+        (0, 1, 1) => DataBlockAccess::ReadWriteB,
+        (1, 0, 1) => DataBlockAccess::ReadOnlyB,
+        _ => DataBlockAccess::Never,
+    }
+}
+
+fn trailer_bits(access: TrailerAccess) -> (u8, u8, u8) {
+    match access {
+        TrailerAccess::KeyAWritesKeys => (0, 0, 0),
+        TrailerAccess::KeyAReadOnly => (0, 1, 0),
+        TrailerAccess::KeyBWritesKeys => (1, 0, 0),
+        TrailerAccess::Frozen => (1, 1, 0),
+        TrailerAccess::Transport => (0, 0, 1),
+        TrailerAccess::KeyBManaged => (0, 1, 1),
+        TrailerAccess::KeyBWritesAccessBits => (1, 0, 1),
+        TrailerAccess::Locked => (1, 1, 1),
+    }
+}
+
+fn trailer_access(bits: (u8, u8, u8)) -> TrailerAccess {
+    match bits {
+        (0, 0, 0) => TrailerAccess::KeyAWritesKeys,
+        (0, 1, 0) => TrailerAccess::KeyAReadOnly,
+        (1, 0, 0) => TrailerAccess::KeyBWritesKeys,
+        (1, 1, 0) => TrailerAccess::Frozen,
+        (0, 0, 1) => TrailerAccess::Transport,
+        (0, 1, 1) => TrailerAccess::KeyBManaged,
+        (1, 0, 1) => TrailerAccess::KeyBWritesAccessBits,
+        _ => TrailerAccess::Locked,
+    }
+}
+
+impl TrailerAccess {
+    /// When key B can be read, the card doesn't accept it for authentication.
+    pub(crate) fn key_b_readable(&self) -> bool {
+        matches!(
+            self,
+            TrailerAccess::KeyAWritesKeys | TrailerAccess::KeyAReadOnly | TrailerAccess::Transport
+        )
+    }
+}
+
+impl DataBlockAccess {
+    /// Checks if the block can only be reached with key B.
+    fn needs_key_b(&self) -> bool {
+        matches!(
+            self,
+            DataBlockAccess::ReadWriteB | DataBlockAccess::ReadOnlyB
+        )
+    }
+
+    /// Checks if the block can only be written with key B.
+    fn written_with_key_b(&self) -> bool {
+        matches!(
+            self,
+            DataBlockAccess::ReadABWriteB
+                | DataBlockAccess::ValueWriteB
+                | DataBlockAccess::ReadWriteB
+        )
+    }
+}
+
+/// The access conditions of a whole sector: its three data blocks and the trailer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AccessConditions {
+    pub(crate) blocks: [DataBlockAccess; 3],
+    pub(crate) trailer: TrailerAccess,
+}
+
+impl Default for AccessConditions {
+    /// The factory default, FF 07 80.
+    fn default() -> Self {
+        AccessConditions {
+            blocks: [DataBlockAccess::ReadWriteAB; 3],
+            trailer: TrailerAccess::Transport,
+        }
+    }
+}
+
+impl AccessConditions {
+    /// Encodes the access conditions into the bytes 6, 7 and 8 of the sector trailer.
+    ///
+    /// Each bit is stored twice, once inverted, which is how the card tells a valid trailer from a corrupted one.
+    pub(crate) fn to_bytes(&self) -> [u8; 3] {
+        let (mut c1, mut c2, mut c3) = (0u8, 0u8, 0u8);
+
+        let bits = self
+            .blocks
+            .iter()
+            .map(|access| data_block_bits(*access))
+            .chain(std::iter::once(trailer_bits(self.trailer)));
+
+        for (i, (b1, b2, b3)) in bits.enumerate() {
+            c1 |= b1 << i;
+            c2 |= b2 << i;
+            c3 |= b3 << i;
+        }
+
+        [
+            ((!c2 & 0x0f) << 4) | (!c1 & 0x0f),
+            (c1 << 4) | (!c3 & 0x0f),
+            (c3 << 4) | c2,
+        ]
+    }
+
+    /// Decodes the bytes 6, 7 and 8 of a sector trailer.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(AccessConditions)` - If the inverted bits match.
+    /// * `Err(ReaderError)` - If the bytes are not a valid access bits encoding.
+    pub(crate) fn from_bytes(bytes: &[u8; 3]) -> Result<AccessConditions, ReaderError> {
+        let c1 = bytes[1] >> 4;
+        let c2 = bytes[2] & 0x0f;
+        let c3 = bytes[2] >> 4;
+
+        if bytes[0] & 0x0f != !c1 & 0x0f
+            || bytes[0] >> 4 != !c2 & 0x0f
+            || bytes[1] & 0x0f != !c3 & 0x0f
+        {
+            return Err(ReaderError::CardError(
+                "Invalid access bits.".to_string(),
+                Error::InvalidValue,
+            ));
+        }
+
+        let bits = |i: u8| ((c1 >> i) & 1, (c2 >> i) & 1, (c3 >> i) & 1);
+
+        Ok(AccessConditions {
+            blocks: [
+                data_block_access(bits(0)),
+                data_block_access(bits(1)),
+                data_block_access(bits(2)),
+            ],
+            trailer: trailer_access(bits(3)),
+        })
+    }
+
+    /// Checks that the sector stays usable with these access conditions.
+    ///
+    /// Key B can't authenticate while it's readable,
+    /// so the blocks that need key B would be lost for good.
+    pub(crate) fn validate(&self) -> Result<(), ReaderError> {
+        if self.trailer.key_b_readable()
+            && self
+                .blocks
+                .iter()
+                .any(|block| block.needs_key_b() || block.written_with_key_b())
+        {
+            return Err(ReaderError::CardError(
+                "Blocks need key B but the trailer leaves key B readable.".to_string(),
+                Error::InvalidParameter,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// A sector trailer: key A, the access bits, the general purpose byte and key B.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SectorTrailer {
+    pub(crate) key_a: [u8; 6],
+    pub(crate) access: AccessConditions,
+    #[serde(default = "default_general_purpose")]
+    pub(crate) general_purpose: u8,
+    pub(crate) key_b: [u8; 6],
+}
+
+fn default_general_purpose() -> u8 {
+    0x69
+}
+
+impl SectorTrailer {
+    /// Builds the 16 bytes written to the trailer block, after validating the access conditions.
+    pub(crate) fn to_bytes(&self) -> Result<[u8; 16], ReaderError> {
+        self.access.validate()?;
+
+        let mut bytes = [0u8; 16];
+        bytes[..6].copy_from_slice(&self.key_a);
+        bytes[6..9].copy_from_slice(&self.access.to_bytes());
+        bytes[9] = self.general_purpose;
+        bytes[10..].copy_from_slice(&self.key_b);
+
+        // Never trust the encoder blindly, a bad trailer bricks the sector
+        let access_bytes = [bytes[6], bytes[7], bytes[8]];
+        if AccessConditions::from_bytes(&access_bytes)? != self.access {
+            return Err(ReaderError::CardError(
+                "Invalid access bits.".to_string(),
+                Error::InvalidValue,
+            ));
+        }
+
+        Ok(bytes)
+    }
+
+    /// The key the app should use for the sector once this trailer is written.
+    ///
+    /// Key B when some block can only be reached or written with it, key A otherwise.
+    pub(crate) fn sector_key(&self) -> SectorKey {
+        let needs_key_b = self
+            .access
+            .blocks
+            .iter()
+            .any(|block| block.needs_key_b() || block.written_with_key_b());
+
+        if needs_key_b && !self.access.trailer.key_b_readable() {
+            SectorKey {
+                key_type: KeyType::B,
+                key: self.key_b,
+            }
+        } else {
+            SectorKey {
+                key_type: KeyType::A,
+                key: self.key_a,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_bits() {
+        // Factory default
+        assert_eq!(AccessConditions::default().to_bytes(), [0xff, 0x07, 0x80]);
+
+        let issued = AccessConditions {
+            blocks: [
+                DataBlockAccess::ReadABWriteB,
+                DataBlockAccess::ReadABWriteB,
+                DataBlockAccess::ReadWriteB,
+            ],
+            trailer: TrailerAccess::KeyBManaged,
+        };
+        let bytes = issued.to_bytes();
+        assert_eq!(AccessConditions::from_bytes(&bytes).unwrap(), issued);
+
+        // Flipping a single bit must be caught
+        assert!(AccessConditions::from_bytes(&[bytes[0] ^ 0x01, bytes[1], bytes[2]]).is_err());
+    }
+
+    #[test]
+    fn test_trailer_validation() {
+        let mut trailer = SectorTrailer {
+            key_a: [0xff; 6],
+            access: AccessConditions {
+                blocks: [DataBlockAccess::ReadABWriteB; 3],
+                trailer: TrailerAccess::Transport,
+            },
+            general_purpose: default_general_purpose(),
+            key_b: [0xff; 6],
+        };
+        assert!(trailer.to_bytes().is_err());
+
+        trailer.access.trailer = TrailerAccess::KeyBManaged;
+        let bytes = trailer.to_bytes().unwrap();
+        assert_eq!(&bytes[..6], &[0xff; 6]);
+        assert_eq!(bytes[9], 0x69);
+        assert_eq!(trailer.sector_key().key_type, KeyType::B);
+    }
+}
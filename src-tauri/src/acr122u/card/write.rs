@@ -1,22 +1,30 @@
-use std::ffi::CString;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use pcsc::*;
+use pcsc::Error;
 
-use crate::acr122u::card::utils::authenticate::{authenticate_14443_3, KeyType};
+use crate::acr122u::card::utils::keys::{authenticate_block, KeyStore};
+use crate::acr122u::card::utils::layout::{is_sector_trailer, trailer_block, CardType};
+use crate::acr122u::card::utils::trailer::SectorTrailer;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::settings::card_deadline;
 use crate::acr122u::utils::errors::ReaderError;
 
 /// Writes data to a specified block on a card.
 ///
+/// Data longer than a block carries on in the next data blocks, skipping the sector trailers (see `write_data`).
+/// On Ultralight and NTAG21x cards `block_number` is the first page and the data is written 4 bytes per page.
+///
 /// # Arguments
 ///
-/// * `ctx` - The PC/SC context.
-/// * `reader` - The name of the reader.
+/// * `reader` - The driver of the reader to write with.
+/// * `keys` - The key store with the key of each sector.
 /// * `block_number` - The block number to write to.
 /// * `data` - The data to write.
 /// * `block_size` - The size of each block (optional).
 /// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
 ///
 /// # Returns
 ///
@@ -26,31 +34,33 @@ use crate::acr122u::utils::errors::ReaderError;
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The reader name is invalid.
 /// * The data length is not a multiple of the block size.
-/// * The write operation fails.
+/// * `block_number` is a sector trailer, those can only be written with `write_sector_trailer`.
+/// * The data doesn't fit between `block_number` and the end of the card.
+/// * A block fails to be written or read back, the error tells which one.
 /// * The card type is unsupported.
 /// * The operation is canceled.
+/// * No card is presented before the timeout, with `ReaderError::Timeout`.
 ///
 /// # Examples
 ///
 /// ```
-/// let (ctx, reader) = reader().unwrap();
+/// let mut driver = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+/// let keys = KeyStore::default();
 /// let cancel_flag = Arc::new(AtomicBool::new(false));
 /// let data = vec![0x01, 0x02, 0x03, 0x04];
-/// let result = write_block(ctx, reader, 4, data, Some(16), &cancel_flag).await;
+/// let result = write_block(&mut driver, &keys, 4, data, Some(16), &cancel_flag, None).await;
 /// assert_eq!(result.unwrap(), true);
 /// ```
 pub(crate) async fn write_block(
-    ctx: Context,
-    reader: String,
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
     block_number: u16,
     data: Vec<u8>,
     block_size: Option<u16>,
     cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
 ) -> Result<bool, ReaderError> {
-    let reader = CString::new(reader)
-        .map_err(|_| ReaderError::UnsupportedReader("Invalid reader name".to_string()))?;
     let block_size = block_size.unwrap_or(16);
 
     if data.len() < block_size as usize || data.len() % block_size as usize != 0 {
@@ -60,157 +70,194 @@ pub(crate) async fn write_block(
         ));
     }
 
-    if data.len() > block_size as usize {
-        let p = data.len() / block_size as usize;
-        let mut commands = Vec::new();
-
-        for i in 0..p {
-            let mut block = block_number + 1;
-
-            // Check if block number is 7, 11, 15 etc., if it is, add +1
-            if block % 4 == 3 {
-                block += 1;
-            }
-
-            let start = i * block_size as usize;
-            let end = (i + 1) * block_size as usize;
-
-            let _size = if end < data.len() {
-                block_size
-            } else {
-                (data.len() - i * block_size as usize) as u8 as u16
-            };
-
-            commands.push(write_block(
-                ctx.clone(),
-                reader.clone().into_string().unwrap(),
-                block,
-                data[start..end].to_vec(),
-                Some(block_size),
-                cancel_flag,
-            ));
-        }
+    if cancel_flag.load(Ordering::Relaxed) {
+        return Err(ReaderError::OperationCancelled("Write Card".to_string()));
+    }
 
-        let responses = futures::future::join_all(commands)
-            .await
-            .into_iter()
-            .map(|c| c.map_err(|e| e))
-            .collect::<Result<Vec<_>, _>>()?;
-
-        for response in responses {
-            if !response {
-                return Err(ReaderError::CardError(
-                    "Write failed.".to_string(),
-                    Error::InvalidParameter,
-                ));
-            }
-        }
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
 
-        Ok(true)
-    } else {
-        let mut reader_states = vec![ReaderState::new(reader, State::UNAWARE)];
-
-        let mut last_event_count = reader_states[0].event_count();
-
-        loop {
-            if cancel_flag.load(Ordering::Relaxed) {
-                return Err(ReaderError::OperationCancelled("Read Card".to_string()));
-            }
-
-            ctx.get_status_change(None, &mut reader_states)
-                .map_err(ReaderError::PcscError)?;
-
-            loop {
-                if cancel_flag.load(Ordering::Relaxed) {
-                    return Err(ReaderError::OperationCancelled("Read Card".to_string()));
-                }
-
-                ctx.get_status_change(None, &mut reader_states)
-                    .map_err(ReaderError::PcscError)?;
-
-                for reader_state in &mut reader_states {
-                    if reader_state.event_count() != last_event_count {
-                        if reader_state.event_state().contains(State::CHANGED) {
-                            if reader_state.event_state().contains(State::PRESENT) {
-                                let mut card = ctx
-                                    .connect(reader_state.name(), ShareMode::Shared, Protocols::ANY)
-                                    .map_err(ReaderError::PcscError)?;
-
-                                {
-                                    let tx = card.transaction().map_err(ReaderError::PcscError)?;
-
-                                    // Get the card tag type: TAG_ISO_14443_3 is Mifare, TAG_ISO_14443_4 is FeliCa
-                                    let status =
-                                        tx.status2_owned().map_err(ReaderError::PcscError)?;
-                                    let atr = status.atr();
-                                    return if atr.starts_with(&[0x3B, 0x8F, 0x80, 0x01, 0x80, 0x4F])
-                                    {
-                                        let packet_header: Vec<u8> = vec![
-                                            0xff,               // Class
-                                            0xd6,               // INS
-                                            0x00,               // P1
-                                            block_number as u8, // P2
-                                            block_size as u8,   // Lc
-                                        ];
-
-                                        let packet = [packet_header, data].concat();
-                                        let mut response_buf = [0; 128]; // Adjust the size as needed
-                                        authenticate_14443_3(&tx, block_number as u8, KeyType::A)?;
-                                        let response = tx
-                                            .transmit(&packet, &mut response_buf)
-                                            .map_err(ReaderError::PcscError)?;
-
-                                        if response.len() < 2 {
-                                            return Err(ReaderError::CardError(
-                                                "Invalid response length.".to_string(),
-                                                Error::InvalidValue,
-                                            ));
-                                        }
-
-                                        //Status code is of UINT16BE type
-                                        let status_code = ((response[response.len() - 2] as u16)
-                                            << 8)
-                                            | response[response.len() - 1] as u16;
-
-                                        if status_code != 0x9000 {
-                                            return Err(ReaderError::CardError(
-                                                format!("Write failed with code: {}", status_code),
-                                                Error::InvalidParameter,
-                                            ));
-                                        }
-
-                                        Ok(true)
-                                    } else {
-                                        Err(ReaderError::CardError(
-                                            "Unsupported card type.".to_string(),
-                                            Error::CardUnsupported,
-                                        ))
-                                    };
-                                }
-                            }
-                            // Sync the current state to the event state after handling the event
-                            reader_state.sync_current_state();
-                        }
-
-                        last_event_count = reader_state.event_count();
-                    }
-                }
-
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-            }
+    // Get the card type from the ATR, anything that isn't Mifare Classic, Ultralight or NTAG21x is rejected
+    let card_type = CardType::detect(reader, &atr)?;
+    if card_type.is_classic() {
+        reject_sector_trailer(block_number)?;
+    }
+
+    write_data(reader, keys, card_type, block_number, &data, cancel_flag)?;
+
+    Ok(true)
+}
+
+/// Writes the data to consecutive data blocks, or pages on Ultralight and NTAG21x cards, starting at `first_block`.
+///
+/// Sector trailers are skipped, the data carries on in the first block of the next sector.
+/// The last block is padded with zeros, and every block is read back after being written,
+/// so a card pulled away mid-write or a block that didn't take the data is caught right away.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader, already connected to the card.
+/// * `keys` - The key store with the key of each sector, only used by Mifare Classic cards.
+/// * `card_type` - The type of the connected card.
+/// * `first_block` - The block or page to start writing at.
+/// * `data` - The data to write.
+/// * `cancel_flag` - A flag to cancel the operation.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u16>)` - The blocks written, in order.
+/// * `Err(ReaderError)` - `BlockWriteFailed` with the block that couldn't be written or verified,
+///   or a `CardError` if the data doesn't fit in the card.
+pub(crate) fn write_data(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    card_type: CardType,
+    first_block: u16,
+    data: &[u8],
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<u16>, ReaderError> {
+    let block_size = card_type.block_size() as usize;
+    let chunks = data.chunks(block_size);
+    let blocks: Vec<u16> = card_type
+        .data_blocks(first_block)
+        .take(chunks.len())
+        .collect();
+
+    if blocks.first() != Some(&first_block) || blocks.len() < chunks.len() {
+        return Err(ReaderError::CardError(
+            format!(
+                "{} bytes from block {} don't fit in the user memory of the card.",
+                data.len(),
+                first_block
+            ),
+            Error::InvalidParameter,
+        ));
+    }
+
+    for (&block, chunk) in blocks.iter().zip(chunks) {
+        if cancel_flag.load(Ordering::Relaxed) {
+            return Err(ReaderError::OperationCancelled("Write Card".to_string()));
         }
+
+        let mut block_data = chunk.to_vec();
+        block_data.resize(block_size, 0);
+
+        write_verified(reader, keys, card_type, block, &block_data)
+            .map_err(|e| ReaderError::BlockWriteFailed(block, e.to_string()))?;
+    }
+
+    Ok(blocks)
+}
+
+/// Writes a single block and reads it back.
+fn write_verified(
+    reader: &mut dyn CardReader,
+    keys: &KeyStore,
+    card_type: CardType,
+    block: u16,
+    data: &[u8],
+) -> Result<(), ReaderError> {
+    if card_type.is_classic() {
+        authenticate_block(reader, block, keys)?;
+    }
+
+    reader.write_block(block, data)?;
+
+    // The sector is still authenticated, no need to do it again for the read
+    let written = reader.read_block(block, data.len() as u8)?;
+    if !written.starts_with(data) {
+        return Err(ReaderError::CardError(
+            "The data read back doesn't match the data written.".to_string(),
+            Error::InvalidValue,
+        ));
     }
+
+    Ok(())
+}
+
+/// Raw writes never touch a sector trailer, a bad one makes the whole sector unreadable.
+fn reject_sector_trailer(block_number: u16) -> Result<(), ReaderError> {
+    if is_sector_trailer(block_number) {
+        return Err(ReaderError::CardError(
+            format!(
+                "Block {} is a sector trailer, use write_sector_trailer instead.",
+                block_number
+            ),
+            Error::InvalidParameter,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writes the sector trailer, changing the keys and the access conditions of a sector.
+///
+/// The trailer is built from the typed access conditions and validated before anything is written,
+/// this is the only way the app writes to a trailer block.
+/// Once written, the key store is updated with the new key of the sector.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader to write with.
+/// * `keys` - The key store, the current key of the sector is used to authenticate.
+/// * `sector` - The sector to write the trailer of.
+/// * `trailer` - The new keys and access conditions.
+/// * `cancel_flag` - A flag to cancel the operation.
+/// * `timeout` - How long to wait for a card (optional), the `card_timeout_secs` of the reader settings if not given.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the trailer was written and the key store updated.
+/// * `Err(ReaderError)` - If the trailer is invalid or the write fails.
+pub(crate) async fn write_sector_trailer(
+    reader: &mut dyn CardReader,
+    keys: &mut KeyStore,
+    sector: u8,
+    trailer: &SectorTrailer,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Option<Duration>,
+) -> Result<(), ReaderError> {
+    // Validated before waiting for the card, so nothing is written if it's wrong
+    let trailer_bytes = trailer.to_bytes()?;
+    let block_number = trailer_block(sector);
+
+    if cancel_flag.load(Ordering::Relaxed) {
+        return Err(ReaderError::OperationCancelled("Write Card".to_string()));
+    }
+
+    // Waits for the card to be present on the reader
+    let atr = reader.connect(cancel_flag, Some(card_deadline(timeout)))?;
+
+    // Only Mifare Classic cards have sector trailers, 1K cards stop at sector 15
+    let card_type = CardType::from_atr(&atr)?;
+    if !card_type.is_classic() || !card_type.user_blocks().contains(&block_number) {
+        return Err(ReaderError::CardError(
+            format!("The card has no sector {}.", sector),
+            Error::InvalidParameter,
+        ));
+    }
+
+    authenticate_block(reader, block_number, keys)?;
+    reader.write_block(block_number, &trailer_bytes)?;
+
+    keys.sectors.insert(sector, trailer.sector_key());
+    keys.save()
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::atomic::AtomicBool;
+    use super::*;
+    use crate::acr122u::card::read::read_data;
+    use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
 
-    use crate::acr122u::reader::connect::reader;
+    fn simulated_reader() -> SimulatedReader {
+        SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]))
+    }
 
     #[tokio::test]
     async fn test_write_single() {
-        let (ctx, reader) = reader().unwrap();
+        let mut driver = simulated_reader();
+        let keys = KeyStore::default();
         let cancel_flag = Arc::new(AtomicBool::new(false));
 
         let mut buffer = vec![0; 16];
@@ -220,17 +267,28 @@ mod tests {
         buffer[..copy_len].copy_from_slice(&data.as_bytes()[..copy_len]);
 
         let result = write_block(
-            ctx,
-            reader,
+            &mut driver,
+            &keys,
             5,
             buffer,
             Option::from(16),
             &cancel_flag,
+            None,
         )
             .await
             .unwrap_or_else(|e| panic!("{:?}", e));
 
         assert_eq!(result, true);
+        let written = read_data(
+            &mut driver,
+            &keys,
+            CardType::MifareClassic1K,
+            5,
+            16,
+            &cancel_flag,
+        )
+        .unwrap();
+        assert_eq!(written, data.as_bytes());
     }
 
     #[tokio::test]
@@ -267,7 +325,8 @@ mod tests {
         block_eight[..block_eight_copy_len]
             .copy_from_slice(&block_eight_data.as_bytes()[..block_eight_copy_len]);
 
-        let (ctx, reader) = reader().unwrap();
+        let mut driver = simulated_reader();
+        let keys = KeyStore::default();
         let cancel_flag = Arc::new(AtomicBool::new(false));
 
         let blocks = vec![
@@ -277,27 +336,60 @@ mod tests {
             (8, block_eight),
         ];
 
-        let mut commands = Vec::new();
         for (block_number, data) in blocks {
-            commands.push(write_block(
-                ctx.clone(),
-                reader.clone(),
+            let response = write_block(
+                &mut driver,
+                &keys,
                 block_number,
-                data,
+                data.clone(),
                 Option::from(16),
                 &cancel_flag,
-            ));
-        }
+                None,
+            )
+                .await
+                .unwrap();
 
-        let responses = futures::future::join_all(commands)
-            .await
-            .into_iter()
-            .map(|c| c.map_err(|e| e))
-            .collect::<Result<Vec<_>, _>>()
+            assert_eq!(response, true);
+            let written = read_data(
+                &mut driver,
+                &keys,
+                CardType::MifareClassic1K,
+                block_number,
+                16,
+                &cancel_flag,
+            )
             .unwrap();
+            assert_eq!(written, data);
+        }
+    }
 
-        for response in responses {
-            assert_eq!(response, true);
+    #[tokio::test]
+    async fn test_write_payload() {
+        let mut driver = simulated_reader();
+        let keys = KeyStore::default();
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+
+        let atr = driver.connect(&cancel_flag, None).unwrap();
+        let card_type = CardType::detect(&mut driver, &atr).unwrap();
+        assert!(card_type.is_classic());
+
+        // 4 blocks from block 5 run over the trailer of sector 1
+        let payload: Vec<u8> = (0..64).collect();
+        let blocks = write_data(&mut driver, &keys, card_type, 5, &payload, &cancel_flag)
+            .unwrap_or_else(|e| panic!("{:?}", e));
+        assert_eq!(blocks, vec![5, 6, 8, 9]);
+
+        // The payload can't start at a trailer or run past the end of the card
+        for first_block in [7, 62] {
+            let result = write_data(
+                &mut driver,
+                &keys,
+                card_type,
+                first_block,
+                &payload,
+                &cancel_flag,
+            );
+            assert!(result.is_err());
         }
     }
 }
@@ -0,0 +1,87 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use pcsc::{Context, Error};
+
+use crate::acr122u::driver::session::PcscSession;
+use crate::acr122u::driver::{CardReader, ReaderModel};
+use crate::acr122u::reader::feedback::FeedbackPattern;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Driver for the ACS ACR122U.
+///
+/// The ACR122U understands the PC/SC Part 3 pseudo-APDUs, so the default card commands are used.
+/// The LEDs and buzzer are driven with its own pseudo-APDU, sent to the connected card like any other.
+pub(crate) struct Acr122u {
+    session: PcscSession,
+}
+
+impl Acr122u {
+    pub(crate) fn new(ctx: Context, reader: String) -> Result<Acr122u, ReaderError> {
+        Ok(Acr122u {
+            session: PcscSession::new(ctx, reader)?,
+        })
+    }
+}
+
+impl CardReader for Acr122u {
+    fn name(&self) -> &str {
+        &self.session.name
+    }
+
+    fn model(&self) -> ReaderModel {
+        ReaderModel::Acr122u
+    }
+
+    fn connect(
+        &mut self,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, ReaderError> {
+        self.session.wait_for_card(cancel_flag, timeout)
+    }
+
+    fn wait_for_removal(&mut self, cancel_flag: &AtomicBool) -> Result<(), ReaderError> {
+        self.session.wait_for_removal(cancel_flag)
+    }
+
+    fn disconnect(&mut self) {
+        self.session.disconnect()
+    }
+
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        self.session.transmit(apdu)
+    }
+
+    fn protocol(&self) -> Option<String> {
+        self.session.protocol()
+    }
+
+    /// The reader answers FF 00 48 00 00 with the version in ASCII, without a status word.
+    ///
+    /// Like the LED command, it only goes through while a card is connected.
+    fn firmware_version(&mut self) -> Result<String, ReaderError> {
+        let response = self.transmit(&[0xff, 0x00, 0x48, 0x00, 0x00])?;
+
+        match String::from_utf8(response) {
+            Ok(version) if version.starts_with("ACR122") => Ok(version.trim().to_string()),
+            _ => Err(ReaderError::CardError(
+                "Get firmware version failed.".to_string(),
+                Error::InvalidValue,
+            )),
+        }
+    }
+
+    fn play_feedback(&mut self, pattern: &FeedbackPattern) -> Result<(), ReaderError> {
+        let response = self.transmit(&pattern.acr122_command())?;
+
+        // 90 XX, XX being the LED state once the pattern is over
+        match response.as_slice() {
+            [0x90, _] => Ok(()),
+            _ => Err(ReaderError::CardError(
+                "LED and buzzer control failed.".to_string(),
+                Error::InvalidValue,
+            )),
+        }
+    }
+}
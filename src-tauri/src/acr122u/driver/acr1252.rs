@@ -0,0 +1,120 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use pcsc::{Context, Error};
+
+use crate::acr122u::driver::session::PcscSession;
+use crate::acr122u::driver::{CardReader, ReaderModel};
+use crate::acr122u::reader::feedback::FeedbackPattern;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Driver for the ACS ACR1252U.
+///
+/// Card commands are the same PC/SC Part 3 pseudo-APDUs used by the ACR122U,
+/// the reader specific commands (LED, buzzer, firmware) go through `SCardControl` instead.
+/// Only the PICC interface is handled here, the SAM interface can't talk to contactless cards.
+pub(crate) struct Acr1252u {
+    session: PcscSession,
+}
+
+impl Acr1252u {
+    pub(crate) fn new(ctx: Context, reader: String) -> Result<Acr1252u, ReaderError> {
+        Ok(Acr1252u {
+            session: PcscSession::new(ctx, reader)?,
+        })
+    }
+
+    /// Sends an escape command, the response starts with E1 00 00 00 followed by the length and the data.
+    fn escape(&mut self, command: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        let response = self.session.control(command)?;
+
+        match response.as_slice() {
+            [0xe1, 0x00, 0x00, 0x00, _, data @ ..] => Ok(data.to_vec()),
+            _ => Err(ReaderError::CardError(
+                "Escape command failed.".to_string(),
+                Error::InvalidValue,
+            )),
+        }
+    }
+
+    /// Sets the LEDs, bit 0 is red and bit 1 is green.
+    fn set_leds(&mut self, leds: u8) -> Result<(), ReaderError> {
+        self.escape(&[0xe0, 0x00, 0x00, 0x29, 0x01, leds])
+            .map(|_| ())
+    }
+}
+
+impl CardReader for Acr1252u {
+    fn name(&self) -> &str {
+        &self.session.name
+    }
+
+    fn model(&self) -> ReaderModel {
+        ReaderModel::Acr1252u
+    }
+
+    fn connect(
+        &mut self,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, ReaderError> {
+        self.session.wait_for_card(cancel_flag, timeout)
+    }
+
+    fn wait_for_removal(&mut self, cancel_flag: &AtomicBool) -> Result<(), ReaderError> {
+        self.session.wait_for_removal(cancel_flag)
+    }
+
+    fn disconnect(&mut self) {
+        self.session.disconnect()
+    }
+
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        self.session.transmit(apdu)
+    }
+
+    fn protocol(&self) -> Option<String> {
+        self.session.protocol()
+    }
+
+    fn firmware_version(&mut self) -> Result<String, ReaderError> {
+        let version = self.escape(&[0xe0, 0x00, 0x00, 0x18, 0x00])?;
+
+        String::from_utf8(version)
+            .map(|version| version.trim().to_string())
+            .map_err(|_| {
+                ReaderError::CardError(
+                    "Get firmware version failed.".to_string(),
+                    Error::InvalidValue,
+                )
+            })
+    }
+
+    /// The ACR1252U has no blinking command, so the pattern is played step by step.
+    fn play_feedback(&mut self, pattern: &FeedbackPattern) -> Result<(), ReaderError> {
+        let (red, green) = pattern.led.leds();
+        let lit = red as u8 | (green as u8) << 1;
+
+        // Put the LEDs back the way they were once the pattern is over
+        let idle = self
+            .escape(&[0xe0, 0x00, 0x00, 0x29, 0x00])?
+            .first()
+            .copied()
+            .unwrap_or(0);
+
+        for _ in 0..pattern.repetitions.max(1) {
+            self.set_leds(lit)?;
+            if pattern.beep {
+                // The buzzer runs on its own, in steps of 10 ms
+                let duration = (pattern.on_ms / 10).clamp(1, 255) as u8;
+                self.escape(&[0xe0, 0x00, 0x00, 0x28, 0x01, duration])?;
+            }
+            std::thread::sleep(Duration::from_millis(pattern.on_ms as u64));
+
+            self.set_leds(0x00)?;
+            std::thread::sleep(Duration::from_millis(pattern.off_ms as u64));
+        }
+
+        self.set_leds(idle)
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use pcsc::Context;
+
+use crate::acr122u::card::utils::authenticate::{authenticate_14443_3, KeyType};
+use crate::acr122u::driver::session::PcscSession;
+use crate::acr122u::driver::{check_status, CardReader, ReaderModel};
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Driver for any other PC/SC contactless reader.
+///
+/// Assumes the reader follows PC/SC Part 3, but falls back to the obsolete
+/// authentication command (FF 88) for readers that don't support GENERAL AUTHENTICATE (FF 86).
+pub(crate) struct GenericPcsc {
+    session: PcscSession,
+}
+
+impl GenericPcsc {
+    pub(crate) fn new(ctx: Context, reader: String) -> Result<GenericPcsc, ReaderError> {
+        Ok(GenericPcsc {
+            session: PcscSession::new(ctx, reader)?,
+        })
+    }
+
+    /// Authenticates with the obsolete PC/SC 2.01 command, still the only one some readers know.
+    fn authenticate_obsolete(
+        &mut self,
+        block_number: u8,
+        key_type: KeyType,
+        key_slot: u8,
+    ) -> Result<(), ReaderError> {
+        let command = [0xff, 0x88, 0x00, block_number, key_type as u8, key_slot];
+        let response = self.transmit(&command)?;
+
+        check_status(&command, &response)
+    }
+}
+
+impl CardReader for GenericPcsc {
+    fn name(&self) -> &str {
+        &self.session.name
+    }
+
+    fn model(&self) -> ReaderModel {
+        ReaderModel::GenericPcsc
+    }
+
+    fn connect(
+        &mut self,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, ReaderError> {
+        self.session.wait_for_card(cancel_flag, timeout)
+    }
+
+    fn wait_for_removal(&mut self, cancel_flag: &AtomicBool) -> Result<(), ReaderError> {
+        self.session.wait_for_removal(cancel_flag)
+    }
+
+    fn disconnect(&mut self) {
+        self.session.disconnect()
+    }
+
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        self.session.transmit(apdu)
+    }
+
+    fn protocol(&self) -> Option<String> {
+        self.session.protocol()
+    }
+
+    fn authenticate(
+        &mut self,
+        block_number: u16,
+        key_type: KeyType,
+        key_slot: u8,
+    ) -> Result<(), ReaderError> {
+        match authenticate_14443_3(self, block_number as u8, key_type, key_slot) {
+            // 6A81 (function not supported) or 6D00 (instruction not supported), try the old command
+            Err(ReaderError::ApduFailed(0x6a81 | 0x6d00, _)) => {
+                self.authenticate_obsolete(block_number as u8, key_type, key_slot)
+            }
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use pcsc::{Context, Error};
+use serde::Serialize;
+
+use crate::acr122u::card::utils::authenticate::{authenticate_14443_3, KeyType};
+use crate::acr122u::driver::acr122::Acr122u;
+use crate::acr122u::driver::acr1252::Acr1252u;
+use crate::acr122u::driver::generic::GenericPcsc;
+use crate::acr122u::driver::simulated::{SimulatedReader, SIMULATED_READER_NAME};
+use crate::acr122u::reader::feedback::FeedbackPattern;
+use crate::acr122u::utils::errors::ReaderError;
+
+pub(crate) mod acr122;
+pub(crate) mod acr1252;
+pub(crate) mod generic;
+pub(crate) mod session;
+pub(crate) mod simulated;
+
+/// The reader models we have a driver for.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReaderModel {
+    Acr122u,
+    Acr1252u,
+    GenericPcsc,
+    Simulated,
+}
+
+impl ReaderModel {
+    /// Detects the model from the PC/SC reader name.
+    ///
+    /// Returns `None` for interfaces that can't talk to contactless cards, like the SAM slot of the ACR1252U.
+    pub(crate) fn detect(reader: &str) -> Option<ReaderModel> {
+        if reader.contains("SAM") {
+            None
+        } else if reader == SIMULATED_READER_NAME {
+            Some(ReaderModel::Simulated)
+        } else if reader.contains("ACR122") {
+            Some(ReaderModel::Acr122u)
+        } else if reader.contains("ACR1252") {
+            Some(ReaderModel::Acr1252u)
+        } else {
+            Some(ReaderModel::GenericPcsc)
+        }
+    }
+}
+
+/// Everything the app needs from a card reader.
+///
+/// Implementations only need to know how to connect and transmit,
+/// the card commands default to the PC/SC Part 3 pseudo-APDUs (class 0xFF) and can be overridden
+/// by readers that need something else.
+pub(crate) trait CardReader: Send {
+    /// The PC/SC name of the reader.
+    fn name(&self) -> &str;
+
+    fn model(&self) -> ReaderModel;
+
+    /// Waits for a card to be presented and connects to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `cancel_flag` - A flag to cancel the operation.
+    /// * `timeout` - How long to wait for the card, forever if `None`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The ATR of the card.
+    /// * `Err(ReaderError)` - If the operation is cancelled, times out (`ReaderError::Timeout`) or the connection fails.
+    fn connect(
+        &mut self,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, ReaderError>;
+
+    /// Waits until the card is removed from the reader.
+    fn wait_for_removal(&mut self, cancel_flag: &AtomicBool) -> Result<(), ReaderError>;
+
+    /// Drops the connection to the card.
+    fn disconnect(&mut self);
+
+    /// Sends a raw APDU to the card, the response includes the status word.
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, ReaderError>;
+
+    /// Blinks the LEDs and sounds the buzzer of the reader, so the employee knows how the tap went.
+    ///
+    /// There's no standard command for this, readers without a driver for it return `UnsupportedFeature`.
+    fn play_feedback(&mut self, _pattern: &FeedbackPattern) -> Result<(), ReaderError> {
+        Err(ReaderError::CardError(
+            "LED and buzzer control not supported.".to_string(),
+            Error::UnsupportedFeature,
+        ))
+    }
+
+    /// Gets the firmware version of the reader, e.g. "ACR122U207".
+    ///
+    /// There's no standard command for this either, readers without a driver for it return `UnsupportedFeature`.
+    fn firmware_version(&mut self) -> Result<String, ReaderError> {
+        Err(ReaderError::CardError(
+            "Firmware version not supported.".to_string(),
+            Error::UnsupportedFeature,
+        ))
+    }
+
+    /// The protocol the connected card talks, e.g. "T1", `None` if there's no card connected.
+    fn protocol(&self) -> Option<String> {
+        None
+    }
+
+    /// Loads a Mifare key into one of the reader's volatile key slots (LOAD AUTHENTICATION KEYS).
+    fn load_key(&mut self, key_slot: u8, key: &[u8; 6]) -> Result<(), ReaderError> {
+        let packet = [
+            [0xff, 0x82, 0x00, key_slot, 0x06].as_slice(),
+            key.as_slice(),
+        ]
+        .concat();
+        let response = self.transmit(&packet)?;
+
+        check_status(&packet, &response)
+    }
+
+    /// Authenticates a Mifare Classic block with the key loaded in `key_slot`.
+    fn authenticate(
+        &mut self,
+        block_number: u16,
+        key_type: KeyType,
+        key_slot: u8,
+    ) -> Result<(), ReaderError> {
+        authenticate_14443_3(self, block_number as u8, key_type, key_slot)
+    }
+
+    /// Reads `length` bytes starting at the given block (READ BINARY).
+    fn read_block(&mut self, block_number: u16, length: u8) -> Result<Vec<u8>, ReaderError> {
+        let packet = [
+            0xff,
+            0xb0,
+            ((block_number >> 8) & 0xff) as u8, // High byte of block_number
+            (block_number & 0xff) as u8,        // Low byte of block_number
+            length,
+        ];
+
+        let response = self.transmit(&packet)?;
+
+        check_status(&packet, &response)?;
+        Ok(response[0..response.len() - 2].to_vec())
+    }
+
+    /// Writes the data to the given block (UPDATE BINARY).
+    fn write_block(&mut self, block_number: u16, data: &[u8]) -> Result<(), ReaderError> {
+        let packet_header: Vec<u8> = vec![
+            0xff,                               // Class
+            0xd6,                               // INS
+            ((block_number >> 8) & 0xff) as u8, // P1
+            (block_number & 0xff) as u8,        // P2
+            data.len() as u8,                   // Lc
+        ];
+
+        let packet = [packet_header, data.to_vec()].concat();
+        let response = self.transmit(&packet)?;
+
+        check_status(&packet, &response)
+    }
+
+    /// Gets the UID of the card (GET DATA).
+    fn get_uid(&mut self) -> Result<Vec<u8>, ReaderError> {
+        let packet = [0xff, 0xca, 0x00, 0x00, 0x00];
+        let response = self.transmit(&packet)?;
+
+        check_status(&packet, &response)?;
+        Ok(response[0..response.len() - 2].to_vec())
+    }
+}
+
+/// Gets the status word (SW1 SW2) at the end of an APDU response.
+///
+/// Status code is of UINT16BE type, returns `None` if the response is too short to have one.
+pub(crate) fn status_word(response: &[u8]) -> Option<u16> {
+    if response.len() < 2 {
+        return None;
+    }
+
+    Some(((response[response.len() - 2] as u16) << 8) | response[response.len() - 1] as u16)
+}
+
+/// Checks that the response to the APDU ends with 90 00.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the command succeeded.
+/// * `Err(ReaderError)` - `ApduFailed` with the status word and the APDU, or a `CardError` if there's no status word.
+pub(crate) fn check_status(apdu: &[u8], response: &[u8]) -> Result<(), ReaderError> {
+    match status_word(response) {
+        Some(0x9000) => Ok(()),
+        Some(status_word) => Err(ReaderError::apdu_failed(status_word, apdu)),
+        None => Err(ReaderError::CardError(
+            "Invalid response.".to_string(),
+            Error::InvalidValue,
+        )),
+    }
+}
+
+/// Opens the driver that matches the reader.
+///
+/// # Arguments
+///
+/// * `ctx` - The PC/SC context.
+/// * `reader` - The name of the reader.
+///
+/// # Returns
+///
+/// * `Ok(Box<dyn CardReader>)` - The driver for the reader.
+/// * `Err(ReaderError)` - If there's no driver for the reader.
+pub(crate) fn open(ctx: Context, reader: String) -> Result<Box<dyn CardReader>, ReaderError> {
+    match ReaderModel::detect(&reader) {
+        Some(ReaderModel::Acr122u) => Ok(Box::new(Acr122u::new(ctx, reader)?)),
+        Some(ReaderModel::Acr1252u) => Ok(Box::new(Acr1252u::new(ctx, reader)?)),
+        Some(ReaderModel::GenericPcsc) => Ok(Box::new(GenericPcsc::new(ctx, reader)?)),
+        Some(ReaderModel::Simulated) => Ok(Box::new(SimulatedReader::shared())),
+        None => Err(ReaderError::UnsupportedReader(reader)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_model() {
+        assert_eq!(
+            ReaderModel::detect("ACS ACR122U PICC Interface 00 00"),
+            Some(ReaderModel::Acr122u)
+        );
+        assert_eq!(
+            ReaderModel::detect("ACS ACR1252 1S CL Reader PICC 0"),
+            Some(ReaderModel::Acr1252u)
+        );
+        assert_eq!(ReaderModel::detect("ACS ACR1252 1S CL Reader SAM 0"), None);
+        assert_eq!(
+            ReaderModel::detect("Identiv uTrust 3700 F CL Reader 0"),
+            Some(ReaderModel::GenericPcsc)
+        );
+        assert_eq!(
+            ReaderModel::detect(SIMULATED_READER_NAME),
+            Some(ReaderModel::Simulated)
+        );
+    }
+
+    #[test]
+    fn test_status_word() {
+        assert_eq!(status_word(&[0x01, 0x02, 0x90, 0x00]), Some(0x9000));
+        assert_eq!(status_word(&[0x63, 0x00]), Some(0x6300));
+        assert_eq!(status_word(&[0x90]), None);
+
+        let read = [0xff, 0xb0, 0x00, 0x04, 0x10];
+        assert!(check_status(&read, &[0x01, 0x90, 0x00]).is_ok());
+        assert!(matches!(
+            check_status(&read, &[0x6a, 0x82]),
+            Err(ReaderError::ApduFailed(0x6a82, apdu)) if apdu == read
+        ));
+        assert!(check_status(&read, &[]).is_err());
+    }
+}
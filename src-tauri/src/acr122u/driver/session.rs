@@ -0,0 +1,198 @@
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use pcsc::*;
+
+use crate::acr122u::utils::errors::ReaderError;
+
+/// The control code of the escape commands, IOCTL_CCID_ESCAPE of the ACS drivers.
+const ESCAPE_CONTROL_CODE: DWORD = 3500;
+
+/// Holds the PC/SC Context, the reader name and the card currently connected to it.
+///
+/// Every driver talks to the card through this, the only thing they change is the APDUs they send.
+pub(crate) struct PcscSession {
+    pub(crate) ctx: Context,
+    pub(crate) name: String,
+    reader: CString,
+    card: Option<Card>,
+}
+
+impl PcscSession {
+    pub(crate) fn new(ctx: Context, name: String) -> Result<PcscSession, ReaderError> {
+        let reader = CString::new(name.as_str())
+            .map_err(|_| ReaderError::UnsupportedReader("Invalid reader name".to_string()))?;
+
+        Ok(PcscSession {
+            ctx,
+            name,
+            reader,
+            card: None,
+        })
+    }
+
+    /// Waits until a card is present on the reader and connects to it.
+    ///
+    /// The wait can be interrupted from another thread with `Context::cancel`, along with setting the cancel flag.
+    ///
+    /// # Arguments
+    ///
+    /// * `cancel_flag` - A flag to cancel the operation.
+    /// * `timeout` - How long to wait for the card, forever if `None`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The ATR of the connected card.
+    /// * `Err(ReaderError)` - If the operation is cancelled, times out or the connection fails.
+    pub(crate) fn wait_for_card(
+        &mut self,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, ReaderError> {
+        let mut reader_states = vec![ReaderState::new(self.reader.clone(), State::UNAWARE)];
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if cancel_flag.load(Ordering::SeqCst) {
+                return Err(ReaderError::OperationCancelled(
+                    "Card Connection".to_string(),
+                ));
+            }
+
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            self.ctx
+                .get_status_change(remaining, &mut reader_states)
+                .map_err(|err| status_change_error(err, "Card Connection"))?;
+
+            let reader_state = &mut reader_states[0];
+            if is_reader_gone(reader_state.event_state()) {
+                return Err(ReaderError::PcscError(Error::ReaderUnavailable));
+            }
+
+            if reader_state.event_state().contains(State::PRESENT) {
+                if cancel_flag.load(Ordering::SeqCst) {
+                    println!("Operation cancelled at card connection.");
+                    return Err(ReaderError::OperationCancelled(
+                        "Card Connection".to_string(),
+                    ));
+                }
+
+                let card = self
+                    .ctx
+                    .connect(&self.reader, ShareMode::Shared, Protocols::ANY)
+                    .map_err(ReaderError::PcscError)?;
+
+                let status = card.status2_owned().map_err(ReaderError::PcscError)?;
+                let atr = status.atr().to_vec();
+
+                self.card = Some(card);
+                return Ok(atr);
+            }
+
+            // Sync the current state to the event state, so the next call waits for a change
+            reader_state.sync_current_state();
+        }
+    }
+
+    /// Waits until the card is removed from the reader.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - Once the reader is empty.
+    /// * `Err(ReaderError)` - If the operation is cancelled or the reader goes away.
+    pub(crate) fn wait_for_removal(&mut self, cancel_flag: &AtomicBool) -> Result<(), ReaderError> {
+        let mut reader_states = vec![ReaderState::new(self.reader.clone(), State::UNAWARE)];
+
+        loop {
+            if cancel_flag.load(Ordering::SeqCst) {
+                return Err(ReaderError::OperationCancelled("Card Removal".to_string()));
+            }
+
+            self.ctx
+                .get_status_change(None, &mut reader_states)
+                .map_err(|err| status_change_error(err, "Card Removal"))?;
+
+            let reader_state = &mut reader_states[0];
+            if is_reader_gone(reader_state.event_state()) {
+                return Err(ReaderError::PcscError(Error::ReaderUnavailable));
+            }
+
+            if !reader_state.event_state().contains(State::PRESENT) {
+                return Ok(());
+            }
+
+            reader_state.sync_current_state();
+        }
+    }
+
+    /// Drops the connection to the card, leaving it as is.
+    pub(crate) fn disconnect(&mut self) {
+        if let Some(card) = self.card.take() {
+            let _ = card.disconnect(Disposition::LeaveCard);
+        }
+    }
+
+    /// Returns the connected card, or an error if `wait_for_card` wasn't called.
+    pub(crate) fn card(&self) -> Result<&Card, ReaderError> {
+        self.card.as_ref().ok_or(ReaderError::CardError(
+            "No card connected.".to_string(),
+            Error::NoSmartcard,
+        ))
+    }
+
+    /// The protocol negotiated with the connected card.
+    pub(crate) fn protocol(&self) -> Option<String> {
+        let status = self.card().ok()?.status2_owned().ok()?;
+        status.protocol2().map(|protocol| format!("{:?}", protocol))
+    }
+
+    /// Sends an APDU to the connected card.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The full response, including the status word.
+    /// * `Err(ReaderError)` - If there's no card connected or the transmission fails.
+    pub(crate) fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        let card = self.card()?;
+        let mut response_buf = [0; MAX_BUFFER_SIZE];
+
+        let response = card
+            .transmit(apdu, &mut response_buf)
+            .map_err(ReaderError::PcscError)?;
+
+        Ok(response.to_vec())
+    }
+
+    /// Sends an escape command to the reader itself (SCardControl), for features that aren't card commands.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The response of the reader.
+    /// * `Err(ReaderError)` - If there's no card connected or the reader rejects the command.
+    pub(crate) fn control(&mut self, command: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        let card = self.card()?;
+        let mut response_buf = [0; MAX_BUFFER_SIZE];
+
+        let response = card
+            .control(ctl_code(ESCAPE_CONTROL_CODE), command, &mut response_buf)
+            .map_err(ReaderError::PcscError)?;
+
+        Ok(response.to_vec())
+    }
+}
+
+/// Maps the errors of `get_status_change`, a cancelled Context or a wait that ran out of time aren't PC/SC failures.
+fn status_change_error(err: Error, operation: &str) -> ReaderError {
+    match err {
+        Error::Cancelled => ReaderError::OperationCancelled(operation.to_string()),
+        Error::Timeout => ReaderError::Timeout(operation.to_string()),
+        err => ReaderError::PcscError(err),
+    }
+}
+
+/// The reader was unplugged or the PC/SC service doesn't know it anymore.
+fn is_reader_gone(state: State) -> bool {
+    state.intersects(State::UNKNOWN | State::UNAVAILABLE)
+}
@@ -0,0 +1,444 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use pcsc::Error;
+
+use crate::acr122u::card::utils::layout::{is_sector_trailer, sector_of, trailer_block};
+use crate::acr122u::driver::{CardReader, ReaderModel};
+use crate::acr122u::reader::feedback::FeedbackPattern;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Set this environment variable to `1` to use the simulated reader instead of the PC/SC readers.
+pub(crate) const SIMULATED_READER_ENV: &str = "PONTUALL_SIMULATED_READER";
+
+/// The name the simulated reader shows up with, as if it was a PC/SC reader.
+pub(crate) const SIMULATED_READER_NAME: &str = "PontuAll Simulated Reader";
+
+/// How often the simulated reader checks if the card was placed or removed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The UID of the demo card.
+const DEMO_UID: [u8; 4] = [0xde, 0x30, 0x4a, 0x11];
+
+/// The ATR of a Mifare Classic 1K, as built by PC/SC readers (PC/SC Part 3, section 3.1.3.2.3).
+pub(crate) const CLASSIC_1K_ATR: [u8; 20] = [
+    0x3b, 0x8f, 0x80, 0x01, 0x80, 0x4f, 0x0c, 0xa0, 0x00, 0x00, 0x03, 0x06, 0x03, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x6a,
+];
+
+/// The trailer of a blank card: key A and key B set to FF, transport access conditions.
+const TRANSPORT_TRAILER: [u8; 16] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x07, 0x80, 0x69, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+const SW_SUCCESS: [u8; 2] = [0x90, 0x00];
+const SW_FAILED: [u8; 2] = [0x63, 0x00];
+const SW_NOT_SUPPORTED: [u8; 2] = [0x6d, 0x00];
+
+/// Checks if the app should use the simulated reader, so it can run without any hardware.
+pub(crate) fn is_enabled() -> bool {
+    std::env::var(SIMULATED_READER_ENV).is_ok_and(|value| value == "1" || value == "true")
+}
+
+/// An in-memory Mifare Classic 1K card.
+///
+/// Authentication checks the key against the sector trailer, like a real card would,
+/// but the access conditions are not enforced: any authenticated key can read and write the whole sector.
+pub(crate) struct VirtualCard {
+    uid: [u8; 4],
+    blocks: [[u8; 16]; 64],
+}
+
+impl VirtualCard {
+    /// A blank card with the given UID, every sector still uses the transport keys.
+    pub(crate) fn classic_1k(uid: [u8; 4]) -> VirtualCard {
+        let mut blocks = [[0u8; 16]; 64];
+
+        // Manufacturer block: UID, BCC, SAK and ATQA
+        blocks[0][..4].copy_from_slice(&uid);
+        blocks[0][4] = uid.iter().fold(0, |bcc, byte| bcc ^ byte);
+        blocks[0][5..8].copy_from_slice(&[0x08, 0x04, 0x00]);
+
+        for sector in 0..16 {
+            blocks[trailer_block(sector) as usize] = TRANSPORT_TRAILER;
+        }
+
+        VirtualCard { uid, blocks }
+    }
+
+    fn block(&self, block_number: u16) -> Option<&[u8; 16]> {
+        self.blocks.get(block_number as usize)
+    }
+
+    /// Checks the key against the key A or key B stored in the trailer of the sector.
+    fn check_key(&self, block_number: u16, key_type: u8, key: &[u8; 6]) -> bool {
+        if self.block(block_number).is_none() {
+            return false;
+        }
+
+        let trailer = &self.blocks[trailer_block(sector_of(block_number)) as usize];
+
+        match key_type {
+            0x60 => trailer[..6] == key[..],
+            0x61 => trailer[10..] == key[..],
+            _ => false,
+        }
+    }
+}
+
+/// The card and whether it's on the reader, shared by every simulated reader of the app.
+struct Field {
+    card: VirtualCard,
+    present: bool,
+}
+
+fn shared_field() -> Arc<Mutex<Field>> {
+    static FIELD: OnceLock<Arc<Mutex<Field>>> = OnceLock::new();
+
+    FIELD
+        .get_or_init(|| {
+            Arc::new(Mutex::new(Field {
+                card: VirtualCard::classic_1k(DEMO_UID),
+                present: true,
+            }))
+        })
+        .clone()
+}
+
+/// Places or removes the demo card, like tapping a real card on the reader.
+pub(crate) fn set_card_present(present: bool) {
+    shared_field().lock().unwrap().present = present;
+}
+
+/// Checks if the demo card is on the reader.
+pub(crate) fn is_card_present() -> bool {
+    shared_field().lock().unwrap().present
+}
+
+/// Driver for an in-process reader with a virtual Mifare Classic 1K card.
+///
+/// It answers the same PC/SC Part 3 pseudo-APDUs as the ACR122U, so the default card commands
+/// and everything built on top of them run unchanged. Used for tests and the demo mode.
+pub(crate) struct SimulatedReader {
+    field: Arc<Mutex<Field>>,
+    key_slots: [Option<[u8; 6]>; 2],
+    authenticated_sector: Option<u8>,
+    connected: bool,
+}
+
+impl SimulatedReader {
+    /// A reader with its own card on it, for tests.
+    #[cfg(test)]
+    pub(crate) fn new(card: VirtualCard) -> SimulatedReader {
+        SimulatedReader::with_field(Arc::new(Mutex::new(Field {
+            card,
+            present: true,
+        })))
+    }
+
+    /// The reader of the demo mode, its card keeps the data written to it until the app exits.
+    pub(crate) fn shared() -> SimulatedReader {
+        SimulatedReader::with_field(shared_field())
+    }
+
+    fn with_field(field: Arc<Mutex<Field>>) -> SimulatedReader {
+        SimulatedReader {
+            field,
+            key_slots: [None; 2],
+            authenticated_sector: None,
+            connected: false,
+        }
+    }
+
+    /// Polls the field until the card is placed (`true`) or removed (`false`), or the timeout runs out.
+    fn wait_for_presence(
+        &self,
+        present: bool,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+        operation: &str,
+    ) -> Result<(), ReaderError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if cancel_flag.load(Ordering::SeqCst) {
+                return Err(ReaderError::OperationCancelled(operation.to_string()));
+            }
+
+            if self.field.lock().unwrap().present == present {
+                return Ok(());
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(ReaderError::Timeout(operation.to_string()));
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Handles an APDU the way the card and the reader firmware would, returning the data and status word.
+    fn execute(&mut self, card: &mut VirtualCard, apdu: &[u8]) -> Vec<u8> {
+        match apdu {
+            // LOAD AUTHENTICATION KEYS
+            [0xff, 0x82, 0x00, slot, 0x06, key @ ..] if key.len() == 6 => {
+                match self.key_slots.get_mut(*slot as usize) {
+                    Some(key_slot) => {
+                        *key_slot = Some(key.try_into().unwrap());
+                        SW_SUCCESS.to_vec()
+                    }
+                    None => SW_FAILED.to_vec(),
+                }
+            }
+            // GENERAL AUTHENTICATE
+            [0xff, 0x86, 0x00, 0x00, 0x05, 0x01, 0x00, block, key_type, slot] => {
+                self.authenticate_block(card, *block as u16, *key_type, *slot)
+            }
+            // Obsolete AUTHENTICATE (PC/SC 2.01)
+            [0xff, 0x88, 0x00, block, key_type, slot] => {
+                self.authenticate_block(card, *block as u16, *key_type, *slot)
+            }
+            // READ BINARY
+            [0xff, 0xb0, high, low, length] => {
+                let block_number = u16::from_be_bytes([*high, *low]);
+                match card.block(block_number) {
+                    Some(block) if self.is_authenticated(block_number) && *length <= 16 => {
+                        let mut data = block[..*length as usize].to_vec();
+                        // Key A can never be read back
+                        if is_sector_trailer(block_number) {
+                            data.iter_mut().take(6).for_each(|byte| *byte = 0);
+                        }
+                        data.extend_from_slice(&SW_SUCCESS);
+                        data
+                    }
+                    _ => SW_FAILED.to_vec(),
+                }
+            }
+            // UPDATE BINARY, the manufacturer block is read only
+            [0xff, 0xd6, high, low, 0x10, data @ ..] if data.len() == 16 => {
+                let block_number = u16::from_be_bytes([*high, *low]);
+                match card.blocks.get_mut(block_number as usize) {
+                    Some(block) if block_number != 0 && self.is_authenticated(block_number) => {
+                        block.copy_from_slice(data);
+                        SW_SUCCESS.to_vec()
+                    }
+                    _ => SW_FAILED.to_vec(),
+                }
+            }
+            // GET DATA (UID)
+            [0xff, 0xca, 0x00, 0x00, 0x00] => [card.uid.as_slice(), &SW_SUCCESS].concat(),
+            _ => SW_NOT_SUPPORTED.to_vec(),
+        }
+    }
+
+    fn authenticate_block(
+        &mut self,
+        card: &VirtualCard,
+        block_number: u16,
+        key_type: u8,
+        slot: u8,
+    ) -> Vec<u8> {
+        let key = self.key_slots.get(slot as usize).copied().flatten();
+
+        match key {
+            Some(key) if card.check_key(block_number, key_type, &key) => {
+                self.authenticated_sector = Some(sector_of(block_number));
+                SW_SUCCESS.to_vec()
+            }
+            _ => {
+                // A failed authentication drops the previous one, like on a real card
+                self.authenticated_sector = None;
+                SW_FAILED.to_vec()
+            }
+        }
+    }
+
+    fn is_authenticated(&self, block_number: u16) -> bool {
+        self.authenticated_sector == Some(sector_of(block_number))
+    }
+}
+
+impl CardReader for SimulatedReader {
+    fn name(&self) -> &str {
+        SIMULATED_READER_NAME
+    }
+
+    fn model(&self) -> ReaderModel {
+        ReaderModel::Simulated
+    }
+
+    fn connect(
+        &mut self,
+        cancel_flag: &AtomicBool,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, ReaderError> {
+        self.wait_for_presence(true, cancel_flag, timeout, "Card Connection")?;
+
+        self.connected = true;
+        self.authenticated_sector = None;
+        Ok(CLASSIC_1K_ATR.to_vec())
+    }
+
+    fn wait_for_removal(&mut self, cancel_flag: &AtomicBool) -> Result<(), ReaderError> {
+        self.wait_for_presence(false, cancel_flag, None, "Card Removal")
+    }
+
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.authenticated_sector = None;
+    }
+
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, ReaderError> {
+        if !self.connected {
+            return Err(ReaderError::CardError(
+                "No card connected.".to_string(),
+                Error::NoSmartcard,
+            ));
+        }
+
+        let field = self.field.clone();
+        let mut field = field.lock().unwrap();
+        if !field.present {
+            self.disconnect();
+            return Err(ReaderError::PcscError(Error::RemovedCard));
+        }
+
+        Ok(self.execute(&mut field.card, apdu))
+    }
+
+    fn firmware_version(&mut self) -> Result<String, ReaderError> {
+        Ok(format!("PontuAll {}", env!("CARGO_PKG_VERSION")))
+    }
+
+    /// Contactless storage cards are always reported as T=1 by PC/SC readers.
+    fn protocol(&self) -> Option<String> {
+        self.connected.then(|| "T1".to_string())
+    }
+
+    /// There's nothing to light up, the pattern is only logged.
+    fn play_feedback(&mut self, pattern: &FeedbackPattern) -> Result<(), ReaderError> {
+        println!(
+            "Simulated reader: {:?} x{}{}",
+            pattern.led,
+            pattern.repetitions,
+            if pattern.beep { " with beep" } else { "" }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::card::read::read_data;
+    use crate::acr122u::card::utils::authenticate::KeyType;
+    use crate::acr122u::card::utils::keys::{KeyStore, SectorKey, DEFAULT_KEY};
+    use crate::acr122u::card::utils::layout::CardType;
+    use crate::acr122u::card::write::write_data;
+
+    fn connected_reader() -> SimulatedReader {
+        let mut reader = SimulatedReader::new(VirtualCard::classic_1k(DEMO_UID));
+        reader.connect(&AtomicBool::new(false), None).unwrap();
+        reader
+    }
+
+    #[test]
+    fn test_card_identity() {
+        let mut reader = connected_reader();
+        let atr = reader.connect(&AtomicBool::new(false), None).unwrap();
+
+        assert_eq!(CardType::from_atr(&atr).unwrap(), CardType::MifareClassic1K);
+        assert_eq!(reader.get_uid().unwrap(), DEMO_UID.to_vec());
+    }
+
+    #[test]
+    fn test_authentication() {
+        let mut reader = connected_reader();
+
+        // Nothing can be read before authenticating the sector
+        assert!(reader.read_block(4, 16).is_err());
+
+        reader.load_key(0, &[0x12; 6]).unwrap();
+        assert!(reader.authenticate(4, KeyType::A, 0).is_err());
+
+        reader.load_key(0, &DEFAULT_KEY).unwrap();
+        reader.authenticate(4, KeyType::B, 0).unwrap();
+        assert_eq!(reader.read_block(5, 16).unwrap(), vec![0; 16]);
+
+        // The authentication only covers its own sector
+        assert!(reader.read_block(8, 16).is_err());
+
+        // Key A is hidden in the trailer
+        let trailer = reader.read_block(7, 16).unwrap();
+        assert_eq!(&trailer[..10], &[0, 0, 0, 0, 0, 0, 0xff, 0x07, 0x80, 0x69]);
+    }
+
+    #[test]
+    fn test_write_and_read_back() {
+        let mut reader = connected_reader();
+        let cancel_flag = AtomicBool::new(false);
+        let mut keys = KeyStore::default();
+
+        let payload: Vec<u8> = (0..40).collect();
+        let blocks = write_data(
+            &mut reader,
+            &keys,
+            CardType::MifareClassic1K,
+            6,
+            &payload,
+            &cancel_flag,
+        )
+        .unwrap();
+        assert_eq!(blocks, vec![6, 8, 9]);
+
+        let data = read_data(
+            &mut reader,
+            &keys,
+            CardType::MifareClassic1K,
+            6,
+            payload.len(),
+            &cancel_flag,
+        )
+        .unwrap();
+        assert_eq!(data, payload);
+
+        // The manufacturer block can't be written
+        assert!(reader.write_block(0, &[0; 16]).is_err());
+
+        // A key the card doesn't have fails to authenticate
+        keys.default = Some(SectorKey {
+            key_type: KeyType::A,
+            key: [0x12; 6],
+        });
+        assert!(read_data(
+            &mut reader,
+            &keys,
+            CardType::MifareClassic1K,
+            6,
+            16,
+            &cancel_flag
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_removed_card() {
+        let mut reader = connected_reader();
+        reader.field.lock().unwrap().present = false;
+
+        assert!(matches!(
+            reader.get_uid(),
+            Err(ReaderError::PcscError(Error::RemovedCard))
+        ));
+        reader.wait_for_removal(&AtomicBool::new(false)).unwrap();
+        assert!(matches!(
+            reader.connect(&AtomicBool::new(true), None),
+            Err(ReaderError::OperationCancelled(_))
+        ));
+        assert!(matches!(
+            reader.connect(&AtomicBool::new(false), Some(Duration::from_millis(150))),
+            Err(ReaderError::Timeout(_))
+        ));
+    }
+}
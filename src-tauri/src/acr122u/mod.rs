@@ -1,6 +1,9 @@
 pub(crate) mod card;
+pub(crate) mod driver;
 pub(crate) mod reader;
+pub(crate) mod source;
 pub(crate) mod utils;
+pub(crate) mod watcher;
 
 #[macro_use]
 pub(crate) mod tauri_commands;
@@ -1,30 +1,186 @@
+use std::ffi::CString;
+use std::time::Duration;
+
 use pcsc::*;
+use serde::Serialize;
 
+use crate::acr122u::driver::simulated::{self, CLASSIC_1K_ATR, SIMULATED_READER_NAME};
+use crate::acr122u::driver::ReaderModel;
+use crate::acr122u::reader::settings::{load_settings, ReaderSettings};
 use crate::acr122u::utils::errors::ReaderError;
 
+/// Describes a reader attached to the system, this is what the frontend gets from `list_readers`.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ReaderInfo {
+    pub(crate) name: String,
+    pub(crate) supported: bool,
+    pub(crate) model: Option<ReaderModel>,
+    pub(crate) card_present: bool,
+    pub(crate) atr: Option<String>,
+    pub(crate) selected: bool,
+    pub(crate) roles: Vec<String>,
+}
+
+/// Checks if the reader is one we know how to talk to.
+pub(crate) fn is_supported(reader: &str) -> bool {
+    ReaderModel::detect(reader).is_some()
+}
+
+/// Lists the names of every reader currently attached.
+pub(crate) fn reader_names(ctx: &Context) -> Result<Vec<String>, ReaderError> {
+    let mut readers_buf = [0; 2048];
+    let readers = match ctx.list_readers(&mut readers_buf) {
+        Ok(readers) => readers,
+        Err(Error::NoReadersAvailable) => return Ok(Vec::new()),
+        Err(err) => return Err(ReaderError::PcscError(err)),
+    };
+
+    readers
+        .map(|reader| {
+            reader
+                .to_str()
+                .map(|name| name.to_owned())
+                .map_err(|_| ReaderError::UnsupportedReader("Invalid reader name".to_string()))
+        })
+        .collect()
+}
+
+/// Lists every attached reader along with its capabilities and the roles bound to it.
+///
+/// In demo mode the simulated reader is the only one listed.
+pub(crate) fn list_readers() -> Result<Vec<ReaderInfo>, ReaderError> {
+    if simulated::is_enabled() {
+        let settings = load_settings()?;
+        let card_present = simulated::is_card_present();
+
+        return Ok(vec![ReaderInfo {
+            name: SIMULATED_READER_NAME.to_string(),
+            supported: true,
+            model: Some(ReaderModel::Simulated),
+            card_present,
+            atr: card_present.then(|| hex::encode_upper(CLASSIC_1K_ATR)),
+            selected: settings.selected_reader.as_deref() == Some(SIMULATED_READER_NAME),
+            roles: settings.roles_for_reader(SIMULATED_READER_NAME),
+        }]);
+    }
+
+    let ctx = Context::establish(Scope::User).map_err(ReaderError::PcscError)?;
+    let settings = load_settings()?;
+    let names = reader_names(&ctx)?;
+
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut reader_states = names
+        .iter()
+        .map(|name| {
+            CString::new(name.as_str())
+                .map(|name| ReaderState::new(name, State::UNAWARE))
+                .map_err(|_| ReaderError::UnsupportedReader(name.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // A zero timeout only fetches the current state of each reader, it doesn't wait for changes.
+    match ctx.get_status_change(Duration::ZERO, &mut reader_states) {
+        Ok(_) | Err(Error::Timeout) => {}
+        Err(err) => return Err(ReaderError::PcscError(err)),
+    }
+
+    let readers = names
+        .into_iter()
+        .zip(reader_states.iter())
+        .map(|(name, reader_state)| {
+            let card_present = reader_state.event_state().contains(State::PRESENT);
+            let atr = if card_present {
+                Some(hex::encode_upper(reader_state.atr()))
+            } else {
+                None
+            };
+
+            ReaderInfo {
+                supported: is_supported(&name),
+                model: ReaderModel::detect(&name),
+                card_present,
+                atr,
+                selected: settings.selected_reader.as_deref() == Some(name.as_str()),
+                roles: settings.roles_for_reader(&name),
+                name,
+            }
+        })
+        .collect();
+
+    Ok(readers)
+}
+
+/// Picks the reader to be used based on the settings.
+///
+/// The reader bound to `role` wins, then the selected reader, and if none is set the first reader with a dedicated driver.
+/// Generic readers are only picked by default when there's nothing else, so built-in smartcard slots don't take precedence.
+pub(crate) fn resolve_reader(
+    available: &[String],
+    settings: &ReaderSettings,
+    role: Option<&str>,
+) -> Result<String, ReaderError> {
+    let preferred = match role {
+        Some(role) => Some(
+            settings
+                .reader_for_role(role)
+                .ok_or_else(|| ReaderError::RoleNotBound(role.to_string()))?,
+        ),
+        None => settings.selected_reader.as_ref(),
+    };
+
+    if let Some(preferred) = preferred {
+        return if available.contains(preferred) {
+            Ok(preferred.clone())
+        } else {
+            Err(ReaderError::ReaderNotFound(preferred.clone()))
+        };
+    }
+
+    let reader = available.first().ok_or(ReaderError::NoReadersFound)?;
+
+    available
+        .iter()
+        .find(|reader| {
+            matches!(
+                ReaderModel::detect(reader),
+                Some(ReaderModel::Acr122u) | Some(ReaderModel::Acr1252u)
+            )
+        })
+        .or_else(|| available.iter().find(|reader| is_supported(reader)))
+        .cloned()
+        .ok_or_else(|| ReaderError::UnsupportedReader(reader.clone()))
+}
+
+/// Picks the reader for the role among the readers attached to the Context, see `resolve_reader`.
+pub(crate) fn pick_reader(ctx: &Context, role: Option<&str>) -> Result<String, ReaderError> {
+    let available = reader_names(ctx)?;
+    if available.is_empty() {
+        return Err(ReaderError::NoReadersFound);
+    }
+
+    let settings = load_settings()?;
+    let reader = resolve_reader(&available, &settings, role)?;
+
+    // Check if there's a driver for the reader
+    if !is_supported(&reader) {
+        return Err(ReaderError::UnsupportedReader(reader));
+    }
+
+    Ok(reader)
+}
+
 //Create a connection to the card reader, check if it's acr122u and keep the connection alive to be used later
-pub(crate) fn reader<'buf>() -> Result<(Context, String), ReaderError> {
+//When a role is given, the reader bound to it is used instead of the selected one
+pub(crate) fn reader(role: Option<&str>) -> Result<(Context, String), ReaderError> {
     let ctx = match Context::establish(Scope::User) {
         Ok(ctx) => ctx,
         Err(err) => return Err(ReaderError::PcscError(err)),
     };
 
-    let mut readers_buf = [0; 2048];
-    let mut readers = ctx
-        .list_readers(&mut readers_buf)
-        .map_err(ReaderError::PcscError)?;
-
-    let reader = readers
-        .next()
-        .ok_or(ReaderError::NoReadersFound)?
-        .to_str()
-        .map_err(|_| ReaderError::UnsupportedReader("Invalid reader name".to_string()))?
-        .to_owned();
-
-    // Check if the reader is ACR122
-    if !reader.contains("ACR122") {
-        return Err(ReaderError::UnsupportedReader(reader));
-    }
+    let reader = pick_reader(&ctx, role)?;
 
     //Return the context and reader_name
     Ok((ctx, reader))
@@ -43,7 +199,7 @@ mod tests {
 
     #[test]
     fn test_connect() {
-        let result = reader().unwrap();
+        let result = reader(None).unwrap();
         match result {
             (ctx, reader) => {
                 println!("Connection successful. Reader: {}", reader);
@@ -54,11 +210,54 @@ mod tests {
 
     #[test]
     fn test_disconnect() {
-        let ctx = reader().unwrap();
+        let ctx = reader(None).unwrap();
         let result = disconnect(ctx.0);
         match result {
             Ok(_) => println!("Disconnection successful."),
             Err(_) => panic!("Unexpected error occurred."),
         }
     }
+
+    #[test]
+    fn test_list_readers() {
+        let readers = list_readers().unwrap();
+        for reader in readers {
+            println!("{:?}", reader);
+        }
+    }
+
+    #[test]
+    fn test_resolve_reader() {
+        let available = vec![
+            "Broadcom Corp Contacted SmartCard 0".to_string(),
+            "ACS ACR122U PICC Interface 00 00".to_string(),
+            "ACS ACR122U PICC Interface 01 00".to_string(),
+        ];
+        let mut settings = ReaderSettings::default();
+
+        // Without settings, the first supported reader is used.
+        assert_eq!(
+            resolve_reader(&available, &settings, None).unwrap(),
+            "ACS ACR122U PICC Interface 00 00"
+        );
+
+        settings.selected_reader = Some("ACS ACR122U PICC Interface 01 00".to_string());
+        settings.roles.insert(
+            "exit".to_string(),
+            "ACS ACR122U PICC Interface 00 00".to_string(),
+        );
+
+        assert_eq!(
+            resolve_reader(&available, &settings, None).unwrap(),
+            "ACS ACR122U PICC Interface 01 00"
+        );
+        assert_eq!(
+            resolve_reader(&available, &settings, Some("Exit")).unwrap(),
+            "ACS ACR122U PICC Interface 00 00"
+        );
+        assert!(matches!(
+            resolve_reader(&available, &settings, Some("entrance")),
+            Err(ReaderError::RoleNotBound(_))
+        ));
+    }
 }
@@ -0,0 +1,261 @@
+use std::ffi::CString;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use pcsc::*;
+use serde::Serialize;
+
+use crate::acr122u::card::utils::authenticate::KeyType;
+use crate::acr122u::card::utils::keys::{KeyStore, KEY_SLOT};
+use crate::acr122u::card::utils::layout::{trailer_block, CardType};
+use crate::acr122u::driver::{CardReader, ReaderModel};
+use crate::acr122u::utils::errors::ReaderError;
+
+/// How long to wait for a card, the diagnostics don't need one to be useful.
+const CARD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The PC/SC reader state flags and their names, as in the `SCARD_STATE_*` constants.
+const STATE_FLAGS: [(State, &str); 11] = [
+    (State::IGNORE, "IGNORE"),
+    (State::CHANGED, "CHANGED"),
+    (State::UNKNOWN, "UNKNOWN"),
+    (State::UNAVAILABLE, "UNAVAILABLE"),
+    (State::EMPTY, "EMPTY"),
+    (State::PRESENT, "PRESENT"),
+    (State::ATRMATCH, "ATRMATCH"),
+    (State::EXCLUSIVE, "EXCLUSIVE"),
+    (State::INUSE, "INUSE"),
+    (State::MUTE, "MUTE"),
+    (State::UNPOWERED, "UNPOWERED"),
+];
+
+/// The result of authenticating a sector with the key from the key store, the key itself is never included.
+///
+/// * `sector` - The sector tested.
+/// * `key_type` - Which key the key store has for the sector.
+/// * `success` - Whether the card accepted the key.
+/// * `error` - Why it didn't.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct AuthenticationTest {
+    pub(crate) sector: u8,
+    pub(crate) key_type: KeyType,
+    pub(crate) success: bool,
+    pub(crate) error: Option<String>,
+}
+
+/// What could be found out about the card on the reader.
+///
+/// * `atr` - The ATR of the card as hex.
+/// * `protocol` - The protocol negotiated with the card, e.g. "T1".
+/// * `card_type` - The card family, `None` if it's not one the app supports.
+/// * `uid` - The card UID as an uppercase hex string.
+/// * `authentication` - The result of the test authentication, if a sector was given.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct CardDiagnostics {
+    pub(crate) atr: String,
+    pub(crate) protocol: Option<String>,
+    pub(crate) card_type: Option<CardType>,
+    pub(crate) uid: Option<String>,
+    pub(crate) authentication: Option<AuthenticationTest>,
+}
+
+/// A snapshot of the reader and its card, meant to be attached to support tickets.
+///
+/// Every probe runs even if the ones before it failed, their errors are collected in `errors`.
+///
+/// * `reader` - The PC/SC name of the reader.
+/// * `model` - The model of the reader.
+/// * `firmware` - The firmware version, if the reader can tell it.
+/// * `state` - The PC/SC state flags of the reader, e.g. `["CHANGED", "PRESENT"]`.
+/// * `card` - The card on the reader, `None` if there's none.
+/// * `errors` - What went wrong while collecting the rest.
+/// * `collected_at` - When the diagnostics were collected, in RFC 3339.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ReaderDiagnostics {
+    pub(crate) reader: String,
+    pub(crate) model: ReaderModel,
+    pub(crate) firmware: Option<String>,
+    pub(crate) state: Vec<String>,
+    pub(crate) card: Option<CardDiagnostics>,
+    pub(crate) errors: Vec<String>,
+    pub(crate) collected_at: String,
+}
+
+/// Names the flags set in a PC/SC reader state.
+pub(crate) fn state_flags(state: State) -> Vec<String> {
+    STATE_FLAGS
+        .iter()
+        .filter(|(flag, _)| state.contains(*flag))
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// Gets the current PC/SC state of the reader, without waiting for it to change.
+pub(crate) fn reader_state(ctx: &Context, reader: &str) -> Result<State, ReaderError> {
+    let name =
+        CString::new(reader).map_err(|_| ReaderError::UnsupportedReader(reader.to_string()))?;
+    let mut reader_states = [ReaderState::new(name, State::UNAWARE)];
+
+    match ctx.get_status_change(Duration::ZERO, &mut reader_states) {
+        Ok(()) | Err(Error::Timeout) => Ok(reader_states[0].event_state()),
+        Err(err) => Err(ReaderError::PcscError(err)),
+    }
+}
+
+/// Authenticates the trailer of the sector with the key the key store has for it.
+fn test_authentication(
+    reader: &mut dyn CardReader,
+    card_type: Option<CardType>,
+    keys: &KeyStore,
+    sector: u8,
+) -> AuthenticationTest {
+    let sector_key = keys.key_for_sector(sector);
+
+    let result = match card_type {
+        Some(card_type) if sector < card_type.sector_count() => {
+            reader.load_key(KEY_SLOT, &sector_key.key).and_then(|_| {
+                reader.authenticate(trailer_block(sector), sector_key.key_type, KEY_SLOT)
+            })
+        }
+        Some(card_type) if card_type.is_classic() => Err(ReaderError::CardError(
+            format!("The card has no sector {}.", sector),
+            Error::InvalidParameter,
+        )),
+        _ => Err(ReaderError::CardError(
+            "Only Mifare Classic cards have sectors.".to_string(),
+            Error::CardUnsupported,
+        )),
+    };
+
+    AuthenticationTest {
+        sector,
+        key_type: sector_key.key_type,
+        success: result.is_ok(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// Probes the card that was just connected.
+fn card_diagnostics(
+    reader: &mut dyn CardReader,
+    atr: &[u8],
+    keys: &KeyStore,
+    sector: Option<u8>,
+    errors: &mut Vec<String>,
+) -> CardDiagnostics {
+    let card_type = CardType::detect(reader, atr)
+        .map_err(|e| errors.push(format!("Card type: {}", e)))
+        .ok();
+    let uid = reader
+        .get_uid()
+        .map(hex::encode_upper)
+        .map_err(|e| errors.push(format!("UID: {}", e)))
+        .ok();
+
+    CardDiagnostics {
+        atr: hex::encode_upper(atr),
+        protocol: reader.protocol(),
+        card_type,
+        uid,
+        // Last, a failed authentication leaves the card unusable until it's selected again
+        authentication: sector.map(|sector| test_authentication(reader, card_type, keys, sector)),
+    }
+}
+
+/// Collects the diagnostics of the reader and of the card on it, if any.
+///
+/// # Arguments
+///
+/// * `reader` - The driver of the reader, not connected yet.
+/// * `state` - The PC/SC state of the reader, see `reader_state`.
+/// * `keys` - The key store, used for the test authentication.
+/// * `sector` - The sector to test the authentication on (optional).
+/// * `cancel_flag` - A flag to cancel the wait for the card.
+pub(crate) fn collect_diagnostics(
+    reader: &mut dyn CardReader,
+    state: State,
+    keys: &KeyStore,
+    sector: Option<u8>,
+    cancel_flag: &AtomicBool,
+) -> ReaderDiagnostics {
+    let mut errors = Vec::new();
+
+    let atr = match reader.connect(cancel_flag, Some(CARD_TIMEOUT)) {
+        Ok(atr) => Some(atr),
+        Err(ReaderError::Timeout(_)) => None,
+        Err(e) => {
+            errors.push(format!("Card connection: {}", e));
+            None
+        }
+    };
+
+    // Asked while the card is connected, the ACR122U doesn't take its own commands otherwise
+    let firmware = reader
+        .firmware_version()
+        .map_err(|e| errors.push(format!("Firmware: {}", e)))
+        .ok();
+
+    let card = atr.map(|atr| card_diagnostics(reader, &atr, keys, sector, &mut errors));
+
+    ReaderDiagnostics {
+        reader: reader.name().to_string(),
+        model: reader.model(),
+        firmware,
+        state: state_flags(state),
+        card,
+        errors,
+        collected_at: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::card::utils::keys::SectorKey;
+    use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
+
+    #[test]
+    fn test_state_flags() {
+        assert_eq!(
+            state_flags(State::PRESENT | State::CHANGED | State::INUSE),
+            vec!["CHANGED", "PRESENT", "INUSE"]
+        );
+        assert!(state_flags(State::UNAWARE).is_empty());
+    }
+
+    #[test]
+    fn test_collect_diagnostics() {
+        let mut reader = SimulatedReader::new(VirtualCard::classic_1k([0x04, 0xa2, 0x3b, 0x1c]));
+        let cancel_flag = AtomicBool::new(false);
+        let mut keys = KeyStore::default();
+
+        let diagnostics =
+            collect_diagnostics(&mut reader, State::PRESENT, &keys, Some(1), &cancel_flag);
+        assert!(diagnostics.errors.is_empty());
+        assert!(diagnostics.firmware.is_some());
+        assert_eq!(diagnostics.state, vec!["PRESENT"]);
+
+        let card = diagnostics.card.unwrap();
+        assert_eq!(card.card_type, Some(CardType::MifareClassic1K));
+        assert_eq!(card.uid.as_deref(), Some("04A23B1C"));
+        assert!(card.authentication.unwrap().success);
+
+        keys.sectors.insert(
+            1,
+            SectorKey {
+                key_type: KeyType::B,
+                key: [0x12; 6],
+            },
+        );
+        let diagnostics =
+            collect_diagnostics(&mut reader, State::PRESENT, &keys, Some(16), &cancel_flag);
+        let authentication = diagnostics.card.unwrap().authentication.unwrap();
+        assert!(authentication.error.is_some());
+
+        let diagnostics =
+            collect_diagnostics(&mut reader, State::PRESENT, &keys, Some(1), &cancel_flag);
+        let authentication = diagnostics.card.unwrap().authentication.unwrap();
+        assert!(!authentication.success);
+        assert_eq!(authentication.key_type, KeyType::B);
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+
+/// The LEDs of the reader, the ACR122U and ACR1252U have a red and a green one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LedColor {
+    Green,
+    Red,
+    /// Both LEDs at once.
+    Orange,
+}
+
+impl LedColor {
+    /// Which LEDs to light, as (red, green).
+    pub(crate) fn leds(&self) -> (bool, bool) {
+        match self {
+            LedColor::Green => (false, true),
+            LedColor::Red => (true, false),
+            LedColor::Orange => (true, true),
+        }
+    }
+}
+
+/// A blink pattern played on the reader, with an optional beep on each blink.
+///
+/// The ACR122U counts in steps of 100 ms, durations are rounded down to that.
+///
+/// * `led` - The LEDs to blink.
+/// * `on_ms` - How long the LED stays lit (and the buzzer sounds), in milliseconds.
+/// * `off_ms` - How long the LED stays off between blinks, in milliseconds.
+/// * `repetitions` - How many times to blink.
+/// * `beep` - Whether the buzzer sounds while the LED is lit.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FeedbackPattern {
+    pub(crate) led: LedColor,
+    pub(crate) on_ms: u16,
+    pub(crate) off_ms: u16,
+    pub(crate) repetitions: u8,
+    pub(crate) beep: bool,
+}
+
+impl FeedbackPattern {
+    /// Builds the ACR122U LED and buzzer control pseudo-APDU (FF 00 40, ACR122U API section 6.2).
+    ///
+    /// Only the blinking is set, the final LED state is left as is so the reader goes back to its idle state.
+    pub(crate) fn acr122_command(&self) -> [u8; 9] {
+        let (red, green) = self.led.leds();
+
+        // Bits 4-5: initial blinking state, bits 6-7: blinking mask
+        let mut led_state = 0x00;
+        if red {
+            led_state |= 0x10 | 0x40;
+        }
+        if green {
+            led_state |= 0x20 | 0x80;
+        }
+
+        let on = (self.on_ms / 100).clamp(1, 255) as u8;
+        let off = (self.off_ms / 100).clamp(1, 255) as u8;
+        // Link to buzzer: 01 sounds it during T1, while the LED is on
+        let buzzer = if self.beep { 0x01 } else { 0x00 };
+
+        [
+            0xff,
+            0x00,
+            0x40,
+            led_state,
+            0x04,
+            on,
+            off,
+            self.repetitions.max(1),
+            buzzer,
+        ]
+    }
+}
+
+/// What happened to a tap, each one has its own pattern.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PunchOutcome {
+    /// The card belongs to an employee and the tap goes through.
+    Accepted,
+    /// The card is unknown, or its badge didn't check out.
+    Rejected,
+    /// The same card was already tapped a moment ago.
+    Duplicate,
+    /// The badge checks out, but the punch waits for the PIN of the employee.
+    PendingPin,
+}
+
+/// The patterns played on the reader after each tap.
+///
+/// * `enabled` - Turns the feedback off, for readers in quiet places.
+/// * `accepted` - Played when the punch is registered, a green blink and a short beep by default.
+/// * `rejected` - Played for unknown cards and invalid badges, a red blink and a long beep by default.
+/// * `duplicate` - Played when the same card is tapped again right away, same as `rejected` by default.
+/// * `pending_pin` - Played when the site asks for the PIN after the badge, two short orange blinks by default.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FeedbackSettings {
+    pub(crate) enabled: bool,
+    pub(crate) accepted: FeedbackPattern,
+    pub(crate) rejected: FeedbackPattern,
+    pub(crate) duplicate: FeedbackPattern,
+    #[serde(default = "default_pending_pin")]
+    pub(crate) pending_pin: FeedbackPattern,
+}
+
+fn default_pending_pin() -> FeedbackPattern {
+    FeedbackPattern {
+        led: LedColor::Orange,
+        on_ms: 100,
+        off_ms: 100,
+        repetitions: 2,
+        beep: true,
+    }
+}
+
+impl FeedbackSettings {
+    /// Gets the pattern for the outcome, `None` if the feedback is turned off.
+    pub(crate) fn pattern(&self, outcome: PunchOutcome) -> Option<&FeedbackPattern> {
+        if !self.enabled {
+            return None;
+        }
+
+        Some(match outcome {
+            PunchOutcome::Accepted => &self.accepted,
+            PunchOutcome::Rejected => &self.rejected,
+            PunchOutcome::Duplicate => &self.duplicate,
+            PunchOutcome::PendingPin => &self.pending_pin,
+        })
+    }
+}
+
+impl Default for FeedbackSettings {
+    fn default() -> Self {
+        let long_red = FeedbackPattern {
+            led: LedColor::Red,
+            on_ms: 1000,
+            off_ms: 100,
+            repetitions: 1,
+            beep: true,
+        };
+
+        FeedbackSettings {
+            enabled: true,
+            accepted: FeedbackPattern {
+                led: LedColor::Green,
+                on_ms: 100,
+                off_ms: 100,
+                repetitions: 1,
+                beep: true,
+            },
+            rejected: long_red,
+            duplicate: long_red,
+            pending_pin: default_pending_pin(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acr122_command() {
+        let settings = FeedbackSettings::default();
+
+        assert_eq!(
+            settings.accepted.acr122_command(),
+            [0xff, 0x00, 0x40, 0xa0, 0x04, 0x01, 0x01, 0x01, 0x01]
+        );
+        assert_eq!(
+            settings.rejected.acr122_command(),
+            [0xff, 0x00, 0x40, 0x50, 0x04, 0x0a, 0x01, 0x01, 0x01]
+        );
+
+        let silent = FeedbackPattern {
+            led: LedColor::Orange,
+            on_ms: 50,
+            off_ms: 300,
+            repetitions: 0,
+            beep: false,
+        };
+        assert_eq!(
+            silent.acr122_command(),
+            [0xff, 0x00, 0x40, 0xf0, 0x04, 0x01, 0x03, 0x01, 0x00]
+        );
+    }
+}
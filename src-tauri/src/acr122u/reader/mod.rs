@@ -1 +1,6 @@
 pub(crate) mod connect;
+pub(crate) mod diagnostics;
+pub(crate) mod feedback;
+pub(crate) mod monitor;
+pub(crate) mod queue;
+pub(crate) mod settings;
@@ -0,0 +1,271 @@
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+use pcsc::*;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::acr122u::driver::simulated::{self, SIMULATED_READER_NAME};
+use crate::acr122u::driver::ReaderModel;
+use crate::acr122u::reader::connect::{reader_names, resolve_reader};
+use crate::acr122u::reader::settings::load_settings;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// How long to wait before trying again when the PC/SC service is down.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How often to look at the readers when the platform can't notify about them.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Current state of the readers, this is what the frontend gets from `reader_status`.
+///
+/// * `connected` - Whether the active reader is plugged in.
+/// * `reader` - The reader used when no role is given, if any is plugged in.
+/// * `model` - The model of the active reader.
+/// * `readers` - Every reader currently attached.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ReaderStatus {
+    pub(crate) connected: bool,
+    pub(crate) reader: Option<String>,
+    pub(crate) model: Option<ReaderModel>,
+    pub(crate) readers: Vec<String>,
+}
+
+impl ReaderStatus {
+    /// Builds the status from the attached readers, picking the active one the same way `connect_reader` does.
+    fn from_readers(readers: Vec<String>) -> ReaderStatus {
+        let settings = load_settings().unwrap_or_default();
+        let reader = resolve_reader(&readers, &settings, None).ok();
+
+        ReaderStatus {
+            connected: reader.is_some(),
+            model: reader.as_deref().and_then(ReaderModel::detect),
+            reader,
+            readers,
+        }
+    }
+}
+
+/// Payload of the `reader:connected` and `reader:disconnected` events.
+///
+/// * `reader` - The name of the reader that was plugged in or unplugged.
+/// * `model` - The model of the reader, `None` if there's no driver for it.
+/// * `active` - Whether it's the reader used when no role is given.
+/// * `timestamp` - When the change was noticed, in RFC 3339.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ReaderEvent {
+    pub(crate) reader: String,
+    pub(crate) model: Option<ReaderModel>,
+    pub(crate) active: bool,
+    pub(crate) timestamp: String,
+}
+
+/// What the monitor last saw, `status` is `None` until it starts.
+///
+/// `updates` counts the changes, so waiting for the reader only wakes up for a new one.
+#[derive(Default)]
+struct MonitorState {
+    status: Option<ReaderStatus>,
+    updates: u64,
+}
+
+/// The state of the monitor, the condvar wakes up whoever waits for a reader.
+fn shared() -> &'static (Mutex<MonitorState>, Condvar) {
+    static STATE: OnceLock<(Mutex<MonitorState>, Condvar)> = OnceLock::new();
+    STATE.get_or_init(|| (Mutex::new(MonitorState::default()), Condvar::new()))
+}
+
+fn set_status(status: ReaderStatus) {
+    let (lock, changed) = shared();
+    let mut state = lock.lock().unwrap();
+    state.status = Some(status);
+    state.updates += 1;
+    changed.notify_all();
+}
+
+/// Gets the current state of the readers.
+///
+/// Uses what the monitor last saw, or asks PC/SC directly if it isn't running yet.
+pub(crate) fn reader_status() -> Result<ReaderStatus, ReaderError> {
+    // Picked again in case the selected reader changed since
+    if let Some(status) = shared().0.lock().unwrap().status.clone() {
+        return Ok(ReaderStatus::from_readers(status.readers));
+    }
+
+    if simulated::is_enabled() {
+        return Ok(ReaderStatus::from_readers(vec![
+            SIMULATED_READER_NAME.to_string()
+        ]));
+    }
+
+    let ctx = Context::establish(Scope::User).map_err(ReaderError::PcscError)?;
+    Ok(ReaderStatus::from_readers(reader_names(&ctx)?))
+}
+
+/// Blocks until the monitor sees the active reader being plugged in, or the timeout runs out.
+///
+/// Only changes seen after the call count, so a reader that's plugged in but can't be opened doesn't spin the caller.
+///
+/// # Returns
+///
+/// * `true` - If the active reader was plugged in.
+/// * `false` - If it didn't come back in time, or the monitor isn't running.
+pub(crate) fn wait_for_reader(timeout: Duration) -> bool {
+    let (lock, changed) = shared();
+    let state = lock.lock().unwrap();
+    let since = state.updates;
+
+    let is_back = |state: &MonitorState| {
+        state.updates != since && state.status.as_ref().is_some_and(|status| status.connected)
+    };
+
+    let (state, _) = changed
+        .wait_timeout_while(state, timeout, |state| !is_back(state))
+        .unwrap();
+
+    is_back(&state)
+}
+
+/// Works out which readers were plugged in and which were unplugged since the last status.
+fn changes<'a>(
+    previous: &'a ReaderStatus,
+    current: &'a ReaderStatus,
+) -> (Vec<&'a String>, Vec<&'a String>) {
+    let connected = current
+        .readers
+        .iter()
+        .filter(|reader| !previous.readers.contains(reader))
+        .collect();
+    let disconnected = previous
+        .readers
+        .iter()
+        .filter(|reader| !current.readers.contains(reader))
+        .collect();
+
+    (connected, disconnected)
+}
+
+/// Emits the events for the readers that changed, and keeps the new status.
+fn update(app: &AppHandle, previous: &ReaderStatus, current: ReaderStatus) {
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    let (connected, disconnected) = changes(previous, &current);
+
+    let events = disconnected
+        .into_iter()
+        .map(|reader| ("reader:disconnected", reader, previous))
+        .chain(
+            connected
+                .into_iter()
+                .map(|reader| ("reader:connected", reader, &current)),
+        );
+
+    for (event, reader, status) in events {
+        let payload = ReaderEvent {
+            reader: reader.clone(),
+            model: ReaderModel::detect(reader),
+            active: status.reader.as_ref() == Some(reader),
+            timestamp: timestamp.clone(),
+        };
+
+        if let Err(e) = app.emit(event, payload) {
+            println!("Reader monitor: failed to emit event: {}", e);
+        }
+    }
+
+    set_status(current);
+}
+
+/// Follows the readers until the PC/SC service goes away.
+///
+/// Waits on the `\\?PnP?\Notification` pseudo-reader, which changes every time a reader is plugged in or unplugged.
+fn follow_readers(
+    app: &AppHandle,
+    ctx: &Context,
+    status: &mut ReaderStatus,
+) -> Result<(), ReaderError> {
+    let mut reader_states = vec![ReaderState::new(PNP_NOTIFICATION(), State::UNAWARE)];
+
+    loop {
+        let current = ReaderStatus::from_readers(reader_names(ctx)?);
+        if current != *status {
+            update(app, status, current.clone());
+            *status = current;
+        }
+
+        match ctx.get_status_change(None, &mut reader_states) {
+            Ok(()) | Err(Error::Timeout) => {}
+            Err(err) => return Err(ReaderError::PcscError(err)),
+        }
+
+        // Without PnP support the pseudo-reader is unknown and the call returns right away, so poll instead
+        if reader_states[0].event_state().contains(State::UNKNOWN) {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        reader_states[0].sync_current_state();
+    }
+}
+
+/// Watches for readers being plugged in and unplugged, and emits a `reader:connected` or `reader:disconnected`
+/// event for each one.
+///
+/// The card watcher waits on this to open the active reader again as soon as it comes back.
+/// This runs for the whole life of the app, so it should be spawned on a blocking thread.
+pub(crate) fn monitor_readers(app: AppHandle) {
+    let mut status = ReaderStatus::default();
+
+    // The simulated reader can't be unplugged
+    if simulated::is_enabled() {
+        let current = ReaderStatus::from_readers(vec![SIMULATED_READER_NAME.to_string()]);
+        update(&app, &status, current);
+        return;
+    }
+
+    loop {
+        let result = Context::establish(Scope::User)
+            .map_err(ReaderError::PcscError)
+            .and_then(|ctx| follow_readers(&app, &ctx, &mut status));
+
+        if let Err(e) = result {
+            println!("Reader monitor: {}", e);
+        }
+
+        // The service is gone, and the readers with it
+        let current = ReaderStatus::default();
+        if current != status {
+            update(&app, &status, current.clone());
+            status = current;
+        }
+
+        std::thread::sleep(RETRY_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let status = |readers: &[&str]| ReaderStatus {
+            readers: readers.iter().map(|reader| reader.to_string()).collect(),
+            ..ReaderStatus::default()
+        };
+
+        let previous = status(&[
+            "ACS ACR122U PICC Interface 00 00",
+            "Broadcom Corp Contacted SmartCard 0",
+        ]);
+        let current = status(&[
+            "Broadcom Corp Contacted SmartCard 0",
+            "ACS ACR1252 1S CL Reader PICC 0",
+        ]);
+
+        let (connected, disconnected) = changes(&previous, &current);
+        assert_eq!(connected, vec!["ACS ACR1252 1S CL Reader PICC 0"]);
+        assert_eq!(disconnected, vec!["ACS ACR122U PICC Interface 00 00"]);
+
+        let (connected, disconnected) = changes(&current, &current);
+        assert!(connected.is_empty() && disconnected.is_empty());
+    }
+}
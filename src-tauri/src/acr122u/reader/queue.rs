@@ -0,0 +1,575 @@
+use std::cmp::Ordering as Order;
+use std::collections::{BinaryHeap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+use pcsc::{Context, Scope};
+use tokio::sync::oneshot;
+
+use crate::acr122u::driver::simulated::{self, SimulatedReader, SIMULATED_READER_NAME};
+use crate::acr122u::driver::{open, CardReader};
+use crate::acr122u::reader::connect::pick_reader;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// How urgent an operation is, the queue always runs the most urgent one first
+/// and operations of the same priority in the order they came in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    /// The card watcher waiting for taps, it gives way as soon as anything else comes in.
+    Idle,
+    /// Reads and writes asked for by the frontend.
+    Normal,
+    /// Enrolling cards and issuing badges, an admin is standing at the reader waiting for it.
+    High,
+}
+
+struct TokenState {
+    cancelled: Arc<AtomicBool>,
+    /// The Context of the operation while it runs, so cancelling wakes up a wait for the card right away.
+    context: Mutex<Option<Context>>,
+}
+
+/// Cancels a single operation of the queue, whether it's still waiting for its turn or already running.
+#[derive(Clone)]
+pub(crate) struct CancelToken(Arc<TokenState>);
+
+impl CancelToken {
+    pub(crate) fn new() -> CancelToken {
+        CancelToken(Arc::new(TokenState {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            context: Mutex::new(None),
+        }))
+    }
+
+    /// The flag the card functions check, set once the token is cancelled.
+    pub(crate) fn flag(&self) -> &Arc<AtomicBool> {
+        &self.0.cancelled
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Sets the cancel flag and interrupts the blocking `get_status_change` of the operation, if it's running.
+    pub(crate) fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+
+        if let Some(ctx) = self.0.context.lock().unwrap().as_ref() {
+            // Fails if nothing is waiting on the Context, the flag covers that case
+            let _ = ctx.cancel();
+        }
+    }
+
+    fn attach(&self, ctx: Option<Context>) {
+        *self.0.context.lock().unwrap() = ctx;
+    }
+}
+
+/// The tokens of the operations started by the same kind of command, so `cancel_read` and `cancel_write`
+/// can stop them all. Tokens of finished operations are dropped along the way.
+#[derive(Default)]
+pub(crate) struct TokenGroup(Mutex<Vec<Weak<TokenState>>>);
+
+impl TokenGroup {
+    /// A new token, cancelled along with the rest of the group.
+    pub(crate) fn token(&self) -> CancelToken {
+        let token = CancelToken::new();
+
+        let mut tokens = self.0.lock().unwrap();
+        tokens.retain(|token| token.strong_count() > 0);
+        tokens.push(Arc::downgrade(&token.0));
+
+        token
+    }
+
+    /// Cancels every operation of the group that's still queued or running.
+    pub(crate) fn cancel(&self) {
+        for token in self.0.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            CancelToken(token).cancel();
+        }
+    }
+}
+
+/// What an operation gets to work with once its turn comes.
+///
+/// * `driver` - The driver of the reader, not connected to the card yet. It's disconnected by the queue afterwards.
+/// * `context` - The PC/SC Context of the reader, `None` for the simulated reader.
+/// * `token` - The token of the operation, its `flag` is the cancel flag to hand to the card functions.
+pub(crate) struct Operation<'a> {
+    pub(crate) driver: &'a mut dyn CardReader,
+    pub(crate) context: Option<&'a Context>,
+    pub(crate) token: &'a CancelToken,
+}
+
+type Task = Box<dyn FnOnce(Result<Operation<'_>, ReaderError>) + Send>;
+
+/// Finds the name of the reader bound to a role, see `pick_reader`.
+type Resolver = Box<dyn FnMut(Option<&str>) -> Result<String, ReaderError> + Send>;
+
+/// Opens the driver of a reader by its name, every reader gets an opener of its own.
+type Opener =
+    Box<dyn FnMut(&str) -> Result<(Box<dyn CardReader>, Option<Context>), ReaderError> + Send>;
+
+type OpenerFactory = Box<dyn Fn() -> Opener + Send + Sync>;
+
+struct Job {
+    priority: Priority,
+    sequence: u64,
+    token: CancelToken,
+    task: Task,
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Order::Equal
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Order> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    /// The heap pops the greatest job first: the highest priority, then the one that came in first.
+    fn cmp(&self, other: &Self) -> Order {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    jobs: BinaryHeap<Job>,
+    sequence: u64,
+    /// The priority and token of the operation running right now.
+    running: Option<(Priority, CancelToken)>,
+}
+
+/// The operations of a single reader, run one at a time on a thread of its own.
+struct Lane {
+    pending: Mutex<Pending>,
+    available: Condvar,
+}
+
+impl Lane {
+    /// Starts the thread of the lane, it runs for the whole life of the app.
+    fn start(reader: &str, opener: Opener) -> Arc<Lane> {
+        let lane = Arc::new(Lane {
+            pending: Mutex::new(Pending::default()),
+            available: Condvar::new(),
+        });
+
+        let actor = lane.clone();
+        let name = reader.to_string();
+        std::thread::Builder::new()
+            .name(format!("reader-queue {}", reader))
+            .spawn(move || actor.run_jobs(&name, opener))
+            .expect("Couldn't start the reader queue");
+
+        lane
+    }
+
+    /// Queues the job, a running `Idle` operation is cancelled to make way for anything more urgent.
+    fn push(&self, priority: Priority, token: CancelToken, task: Task) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some((Priority::Idle, running)) = &pending.running {
+            if priority > Priority::Idle {
+                running.cancel();
+            }
+        }
+
+        pending.sequence += 1;
+        let sequence = pending.sequence;
+        pending.jobs.push(Job {
+            priority,
+            sequence,
+            token,
+            task,
+        });
+        self.available.notify_one();
+    }
+
+    /// Waits for the next job and marks it as running.
+    fn next_job(&self) -> Job {
+        let mut pending = self.pending.lock().unwrap();
+
+        loop {
+            if let Some(job) = pending.jobs.pop() {
+                pending.running = Some((job.priority, job.token.clone()));
+                return job;
+            }
+            pending = self.available.wait(pending).unwrap();
+        }
+    }
+
+    /// The loop of the lane thread.
+    fn run_jobs(&self, reader: &str, mut opener: Opener) {
+        loop {
+            let Job { token, task, .. } = self.next_job();
+
+            if token.is_cancelled() {
+                task(Err(ReaderError::OperationCancelled(
+                    "Queued Operation".to_string(),
+                )));
+            } else {
+                match opener(reader) {
+                    Ok((mut driver, ctx)) => {
+                        token.attach(ctx.clone());
+
+                        // A panicking operation only loses its own result, the queue carries on
+                        let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+                            task(Ok(Operation {
+                                driver: driver.as_mut(),
+                                context: ctx.as_ref(),
+                                token: &token,
+                            }))
+                        }));
+                        if ran.is_err() {
+                            println!("Reader queue: an operation panicked on {}", reader);
+                        }
+
+                        driver.disconnect();
+                        token.attach(None);
+                    }
+                    Err(e) => task(Err(e)),
+                }
+            }
+
+            self.pending.lock().unwrap().running = None;
+        }
+    }
+}
+
+/// Runs the card operations of each reader one at a time, every reader on a thread of its own.
+///
+/// Operations on the same reader never open transactions at once, while readers bound to other roles
+/// don't wait on each other. Each reader gets its own PC/SC Context, established on its first operation
+/// and again if PC/SC restarts.
+/// An operation coming in while the card watcher waits for a tap on the same reader cancels the wait,
+/// the watcher queues itself again and gets its turn back once everything else is done.
+pub(crate) struct ReaderQueue {
+    resolver: Mutex<Resolver>,
+    new_opener: OpenerFactory,
+    /// The lane of every reader an operation ran on, by reader name.
+    lanes: Mutex<HashMap<String, Arc<Lane>>>,
+}
+
+/// Gets the Context, establishing it again if it's gone.
+fn valid_context(ctx: &mut Option<Context>) -> Result<Context, ReaderError> {
+    let current = match ctx.take() {
+        Some(current) if current.is_valid().is_ok() => current,
+        _ => Context::establish(Scope::User).map_err(ReaderError::PcscError)?,
+    };
+    *ctx = Some(current.clone());
+
+    Ok(current)
+}
+
+/// Finds the PC/SC reader bound to the role, or the simulated one in demo mode.
+fn resolve_pcsc(ctx: &mut Option<Context>, role: Option<&str>) -> Result<String, ReaderError> {
+    if simulated::is_enabled() {
+        return Ok(SIMULATED_READER_NAME.to_string());
+    }
+
+    pick_reader(&valid_context(ctx)?, role)
+}
+
+/// Opens the driver of the reader on the Context of its lane.
+fn open_pcsc(
+    ctx: &mut Option<Context>,
+    reader: &str,
+) -> Result<(Box<dyn CardReader>, Option<Context>), ReaderError> {
+    if simulated::is_enabled() {
+        return Ok((Box::new(SimulatedReader::shared()), None));
+    }
+
+    let current = valid_context(ctx)?;
+    Ok((open(current.clone(), reader.to_string())?, Some(current)))
+}
+
+impl ReaderQueue {
+    /// Starts the queue on the PC/SC readers, or the simulated one in demo mode.
+    pub(crate) fn start() -> Arc<ReaderQueue> {
+        let mut ctx = None;
+        ReaderQueue::with_opener(
+            Box::new(move |role| resolve_pcsc(&mut ctx, role)),
+            Box::new(|| {
+                let mut ctx = None;
+                Box::new(move |reader| open_pcsc(&mut ctx, reader))
+            }),
+        )
+    }
+
+    fn with_opener(resolver: Resolver, new_opener: OpenerFactory) -> Arc<ReaderQueue> {
+        Arc::new(ReaderQueue {
+            resolver: Mutex::new(resolver),
+            new_opener,
+            lanes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Finds the name of the reader bound to the role, or of the selected reader when no role is given.
+    pub(crate) fn resolve(&self, role: Option<&str>) -> Result<String, ReaderError> {
+        (self.resolver.lock().unwrap())(role)
+    }
+
+    /// Queues the operation on the lane of the reader bound to the role, the receiver gets its result once it has run.
+    ///
+    /// A running `Idle` operation on the same reader is cancelled to make way for anything more urgent.
+    fn submit<T, F>(
+        &self,
+        priority: Priority,
+        role: Option<String>,
+        token: CancelToken,
+        operation: F,
+    ) -> oneshot::Receiver<Result<T, ReaderError>>
+    where
+        T: Send + 'static,
+        F: FnOnce(Operation<'_>) -> Result<T, ReaderError> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+
+        let reader = match self.resolve(role.as_deref()) {
+            Ok(reader) => reader,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return receiver;
+            }
+        };
+
+        let task: Task = Box::new(move |started| {
+            // The caller may have stopped waiting, there's nobody to tell then
+            let _ = sender.send(started.and_then(operation));
+        });
+
+        let lane = self
+            .lanes
+            .lock()
+            .unwrap()
+            .entry(reader)
+            .or_insert_with_key(|reader| Lane::start(reader, (self.new_opener)()))
+            .clone();
+        lane.push(priority, token, task);
+
+        receiver
+    }
+
+    /// Queues an operation and waits for its result.
+    ///
+    /// # Arguments
+    ///
+    /// * `priority` - How urgent the operation is.
+    /// * `role` - The role of the reader to run it on (optional), the selected reader is used when not given.
+    /// * `token` - The token to cancel it with.
+    /// * `operation` - What to do with the reader, it runs on the thread of the reader.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(T)` - What the operation returned.
+    /// * `Err(ReaderError)` - The error of the operation, `OperationCancelled` if it was cancelled before its turn,
+    ///   or the error finding or opening the reader.
+    pub(crate) async fn run<T, F>(
+        &self,
+        priority: Priority,
+        role: Option<String>,
+        token: CancelToken,
+        operation: F,
+    ) -> Result<T, ReaderError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Operation<'_>) -> Result<T, ReaderError> + Send + 'static,
+    {
+        self.submit(priority, role, token, operation)
+            .await
+            .unwrap_or_else(|_| Err(operation_lost()))
+    }
+
+    /// Same as `run`, for callers on a blocking thread like the card watcher.
+    pub(crate) fn run_blocking<T, F>(
+        &self,
+        priority: Priority,
+        role: Option<String>,
+        token: CancelToken,
+        operation: F,
+    ) -> Result<T, ReaderError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Operation<'_>) -> Result<T, ReaderError> + Send + 'static,
+    {
+        self.submit(priority, role, token, operation)
+            .blocking_recv()
+            .unwrap_or_else(|_| Err(operation_lost()))
+    }
+}
+
+/// The error of an operation whose result never came back, the operation panicked.
+fn operation_lost() -> ReaderError {
+    ReaderError::CardError(
+        "The reader operation failed unexpectedly.".to_string(),
+        pcsc::Error::InternalError,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::driver::simulated::VirtualCard;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Every role is a reader of its own, the selected reader is "selected".
+    fn simulated_queue() -> Arc<ReaderQueue> {
+        ReaderQueue::with_opener(
+            Box::new(|role| Ok(role.unwrap_or("selected").to_string())),
+            Box::new(|| {
+                Box::new(|_| {
+                    Ok((
+                        Box::new(SimulatedReader::new(VirtualCard::classic_1k([
+                            0x04, 0xa2, 0x3b, 0x1c,
+                        ]))),
+                        None,
+                    ))
+                })
+            }),
+        )
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let queue = simulated_queue();
+        let (started, wait_for_start) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+
+        // Holds the queue until every other job is in
+        let blocker = queue.submit(Priority::Normal, None, CancelToken::new(), move |_| {
+            started.send(()).unwrap();
+            gate.recv().unwrap();
+            Ok("blocker")
+        });
+        wait_for_start.recv().unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let job = |name: &'static str| {
+            let order = order.clone();
+            move |_: Operation<'_>| {
+                order.lock().unwrap().push(name);
+                Ok(())
+            }
+        };
+
+        let idle = queue.submit(Priority::Idle, None, CancelToken::new(), job("idle"));
+        let first = queue.submit(Priority::Normal, None, CancelToken::new(), job("first"));
+        let urgent = queue.submit(Priority::High, None, CancelToken::new(), job("urgent"));
+        let second = queue.submit(Priority::Normal, None, CancelToken::new(), job("second"));
+
+        let cancelled = CancelToken::new();
+        let skipped = queue.submit(Priority::High, None, cancelled.clone(), job("skipped"));
+        cancelled.cancel();
+
+        release.send(()).unwrap();
+        assert_eq!(blocker.blocking_recv().unwrap().unwrap(), "blocker");
+        for receiver in [idle, first, urgent, second] {
+            receiver.blocking_recv().unwrap().unwrap();
+        }
+        assert!(matches!(
+            skipped.blocking_recv().unwrap(),
+            Err(ReaderError::OperationCancelled(_))
+        ));
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["urgent", "first", "second", "idle"]
+        );
+    }
+
+    #[test]
+    fn test_preempt_idle() {
+        let queue = simulated_queue();
+        let (started, wait_for_start) = mpsc::channel();
+
+        // Like the card watcher, waits until it's told to stop
+        let watcher = queue.submit(Priority::Idle, None, CancelToken::new(), move |operation| {
+            started.send(()).unwrap();
+            while !operation.token.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err::<(), _>(ReaderError::OperationCancelled(
+                "Card Connection".to_string(),
+            ))
+        });
+        wait_for_start.recv().unwrap();
+
+        let uid = queue.run_blocking(Priority::High, None, CancelToken::new(), |operation| {
+            operation.driver.connect(operation.token.flag(), None)?;
+            operation.driver.get_uid()
+        });
+        assert_eq!(uid.unwrap(), vec![0x04, 0xa2, 0x3b, 0x1c]);
+        assert!(matches!(
+            watcher.blocking_recv().unwrap(),
+            Err(ReaderError::OperationCancelled(_))
+        ));
+    }
+
+    #[test]
+    fn test_readers_run_apart() {
+        let queue = simulated_queue();
+        let (started, wait_for_start) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+
+        // Waits for a card on the entrance reader
+        let entrance = queue.submit(
+            Priority::Normal,
+            Some("entrance".to_string()),
+            CancelToken::new(),
+            move |_| {
+                started.send(()).unwrap();
+                gate.recv().unwrap();
+                Ok("entrance")
+            },
+        );
+        wait_for_start.recv().unwrap();
+
+        // Runs right away on the other reader
+        let selected = queue.run_blocking(Priority::Normal, None, CancelToken::new(), |_| {
+            Ok("selected")
+        });
+        assert_eq!(selected.unwrap(), "selected");
+
+        release.send(()).unwrap();
+        assert_eq!(entrance.blocking_recv().unwrap().unwrap(), "entrance");
+        assert_eq!(queue.lanes.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let queue = simulated_queue();
+
+        // The watcher of a role keys its reader by the same name the lanes use
+        assert_eq!(queue.resolve(Some("entrance")).unwrap(), "entrance");
+        assert_eq!(queue.resolve(None).unwrap(), "selected");
+        assert!(queue.lanes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_token_group() {
+        let group = TokenGroup::default();
+        let first = group.token();
+        let second = group.token();
+        drop(group.token());
+
+        group.cancel();
+        assert!(first.is_cancelled() && second.is_cancelled());
+        assert_eq!(group.0.lock().unwrap().len(), 3);
+
+        // Finished operations are dropped when the next one starts
+        drop(first);
+        let _third = group.token();
+        assert_eq!(group.0.lock().unwrap().len(), 2);
+    }
+}
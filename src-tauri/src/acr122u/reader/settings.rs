@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::reader::feedback::FeedbackSettings;
+use crate::acr122u::source::SourceSettings;
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Reader preferences persisted between sessions.
+///
+/// * `selected_reader` - The reader used by `read_card`/`write_card` when no role is given.
+/// * `roles` - Binds a role (e.g. "entrance", "exit") to a specific reader name.
+/// * `feedback` - The LED and buzzer patterns played after each tap.
+/// * `sources` - The keyboard wedge and manual entry of badge numbers.
+/// * `card_timeout_secs` - How long the card commands wait for a card when they aren't given a timeout.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct ReaderSettings {
+    pub(crate) selected_reader: Option<String>,
+    #[serde(default)]
+    pub(crate) roles: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) feedback: FeedbackSettings,
+    #[serde(default)]
+    pub(crate) sources: SourceSettings,
+    #[serde(default = "default_card_timeout")]
+    pub(crate) card_timeout_secs: u64,
+}
+
+fn default_card_timeout() -> u64 {
+    30
+}
+
+impl Default for ReaderSettings {
+    fn default() -> Self {
+        ReaderSettings {
+            selected_reader: None,
+            roles: HashMap::new(),
+            feedback: FeedbackSettings::default(),
+            sources: SourceSettings::default(),
+            card_timeout_secs: default_card_timeout(),
+        }
+    }
+}
+
+impl ReaderSettings {
+    /// Returns the reader bound to the given role, if any.
+    pub(crate) fn reader_for_role(&self, role: &str) -> Option<&String> {
+        self.roles.get(&normalize_role(role))
+    }
+
+    /// How long to wait for a card, the timeout given to the command or the one of the settings.
+    ///
+    /// A command stuck waiting for a card that never comes holds the queue of its reader, so there's always a deadline.
+    pub(crate) fn card_timeout(&self, timeout_ms: Option<u64>) -> Duration {
+        timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(self.card_timeout_secs))
+    }
+
+    /// Returns every role bound to the given reader.
+    pub(crate) fn roles_for_reader(&self, reader: &str) -> Vec<String> {
+        let mut roles: Vec<String> = self
+            .roles
+            .iter()
+            .filter(|(_, name)| name.as_str() == reader)
+            .map(|(role, _)| role.clone())
+            .collect();
+        roles.sort();
+        roles
+    }
+}
+
+/// Roles are stored lowercase so "Entrance" and "entrance" point to the same reader.
+pub(crate) fn normalize_role(role: &str) -> String {
+    role.trim().to_lowercase()
+}
+
+fn get_settings_path() -> Result<PathBuf, ReaderError> {
+    let mut settings_path = dirs::config_dir()
+        .ok_or_else(|| ReaderError::SettingsError("Failed to get config path".to_string()))?;
+    settings_path.push("PontuAll");
+    settings_path.push("reader_settings.json");
+    Ok(settings_path)
+}
+
+/// Loads the reader settings from the config directory.
+///
+/// Returns the default settings if the file does not exist yet.
+pub(crate) fn load_settings() -> Result<ReaderSettings, ReaderError> {
+    let settings_path = get_settings_path()?;
+
+    if !settings_path.exists() {
+        return Ok(ReaderSettings::default());
+    }
+
+    let settings_json = std::fs::read_to_string(&settings_path)
+        .map_err(|e| ReaderError::SettingsError(e.to_string()))?;
+    serde_json::from_str(&settings_json).map_err(|e| ReaderError::SettingsError(e.to_string()))
+}
+
+/// How long a card function waits for a card, the timeout it was given or the `card_timeout_secs` of the reader settings.
+pub(crate) fn card_deadline(timeout: Option<Duration>) -> Duration {
+    timeout.unwrap_or_else(|| load_settings().unwrap_or_default().card_timeout(None))
+}
+
+/// Saves the reader settings to the config directory, creating it if needed.
+pub(crate) fn save_settings(settings: &ReaderSettings) -> Result<(), ReaderError> {
+    let settings_path = get_settings_path()?;
+
+    if let Some(parent) = settings_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| ReaderError::SettingsError(e.to_string()))?;
+    }
+
+    let settings_json =
+        serde_json::to_string(settings).map_err(|e| ReaderError::SettingsError(e.to_string()))?;
+    std::fs::write(settings_path, settings_json)
+        .map_err(|e| ReaderError::SettingsError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_are_normalized() {
+        let mut settings = ReaderSettings::default();
+        settings.roles.insert(
+            normalize_role(" Entrance "),
+            "ACS ACR122U PICC Interface 00 00".to_string(),
+        );
+
+        assert_eq!(
+            settings.reader_for_role("ENTRANCE"),
+            Some(&"ACS ACR122U PICC Interface 00 00".to_string())
+        );
+        assert_eq!(
+            settings.roles_for_reader("ACS ACR122U PICC Interface 00 00"),
+            vec!["entrance".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_card_timeout() {
+        let mut settings: ReaderSettings =
+            serde_json::from_str(r#"{"selected_reader":null}"#).unwrap();
+        assert_eq!(settings.card_timeout(None), Duration::from_secs(30));
+
+        settings.card_timeout_secs = 5;
+        assert_eq!(settings.card_timeout(None), Duration::from_secs(5));
+        assert_eq!(settings.card_timeout(Some(250)), Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,262 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::acr122u::utils::errors::ReaderError;
+
+/// Badge numbers longer than this are garbage, the longest UID (10 bytes) is 25 decimal digits.
+const MAX_NUMBER_LENGTH: usize = 32;
+
+/// Where a badge came from, reported in the `card:tapped` and `card:rejected` events.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SourceKind {
+    /// A card tapped on a PC/SC reader.
+    Nfc,
+    /// A USB HID reader that types the badge number like a keyboard.
+    Wedge,
+    /// A badge number typed in by hand.
+    Manual,
+}
+
+impl SourceKind {
+    /// The name reported as the reader of the events, for the sources that aren't a PC/SC reader.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            SourceKind::Nfc => "NFC Reader",
+            SourceKind::Wedge => "Keyboard Wedge",
+            SourceKind::Manual => "Manual Entry",
+        }
+    }
+}
+
+/// How the badge numbers are written, it depends on the model and configuration of the wedge reader.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum NumberFormat {
+    /// The UID as hex, separators like "04:A2" or "04-A2" are accepted.
+    #[default]
+    Hex,
+    /// The UID as a decimal number, first byte most significant.
+    Decimal,
+    /// The UID as a decimal number, first byte least significant, as many 10 digit wedges print it.
+    DecimalReversed,
+}
+
+impl NumberFormat {
+    /// Turns a badge number into the card UID.
+    pub(crate) fn parse(&self, number: &str) -> Result<Vec<u8>, ReaderError> {
+        let invalid =
+            || ReaderError::BadgeError(format!("Not a valid {:?} badge number: {}", self, number));
+
+        let uid = match self {
+            NumberFormat::Hex => {
+                hex::decode(number.replace([':', ' ', '-'], "")).map_err(|_| invalid())?
+            }
+            NumberFormat::Decimal | NumberFormat::DecimalReversed => {
+                if !number.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                let value: u128 = number.parse().map_err(|_| invalid())?;
+
+                // UIDs are 4, 7 or 10 bytes long
+                let length = if value < 1 << 32 {
+                    4
+                } else if value < 1 << 56 {
+                    7
+                } else if value < 1 << 80 {
+                    10
+                } else {
+                    return Err(invalid());
+                };
+                let bytes = value.to_be_bytes();
+                let mut uid = bytes[bytes.len() - length..].to_vec();
+                if *self == NumberFormat::DecimalReversed {
+                    uid.reverse();
+                }
+                uid
+            }
+        };
+
+        if ![4, 7, 10].contains(&uid.len()) {
+            return Err(invalid());
+        }
+        Ok(uid)
+    }
+}
+
+/// Settings of the badge sources that aren't a PC/SC reader, both are off until a branch turns them on.
+///
+/// * `wedge` - Whether badge numbers typed by a keyboard wedge are accepted.
+/// * `manual` - Whether badge numbers typed in by hand are accepted.
+/// * `format` - How the badge numbers are written.
+/// * `max_key_interval_ms` - Keys further apart than this were typed by a person, not by the wedge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceSettings {
+    pub(crate) wedge: bool,
+    pub(crate) manual: bool,
+    pub(crate) format: NumberFormat,
+    pub(crate) max_key_interval_ms: u64,
+}
+
+impl Default for SourceSettings {
+    fn default() -> Self {
+        SourceSettings {
+            wedge: false,
+            manual: false,
+            format: NumberFormat::Hex,
+            max_key_interval_ms: 50,
+        }
+    }
+}
+
+/// A source of badges that gives their number instead of the card itself.
+///
+/// Whatever the source, the number goes through the same checks as a tap on the reader
+/// and ends up in the same events, see `watcher::submit_badge`.
+pub(crate) trait BadgeSource {
+    fn kind(&self) -> SourceKind;
+
+    /// Takes the next input of the source.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - The badge numbers completed by the input, as typed.
+    fn feed(&mut self, input: &str, now: Instant) -> Vec<String>;
+}
+
+/// A keyboard wedge reader, it types the badge number and presses Enter (or Tab).
+///
+/// The frontend forwards the keys as they come in, so the ones typed by a person are told apart
+/// by the time between them: a key that comes too late drops what was typed before it and starts a new number.
+/// A person is never fast enough to get to the Enter, a scan right after them still goes through.
+pub(crate) struct WedgeSource {
+    buffer: String,
+    last_key: Option<Instant>,
+    pub(crate) max_key_interval: Duration,
+}
+
+impl Default for WedgeSource {
+    fn default() -> Self {
+        WedgeSource {
+            buffer: String::new(),
+            last_key: None,
+            max_key_interval: Duration::from_millis(SourceSettings::default().max_key_interval_ms),
+        }
+    }
+}
+
+impl BadgeSource for WedgeSource {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Wedge
+    }
+
+    fn feed(&mut self, input: &str, now: Instant) -> Vec<String> {
+        // Only the time between the keys of a number counts, a key after a longer wait starts a new one
+        if let Some(last_key) = self.last_key {
+            if now.duration_since(last_key) > self.max_key_interval {
+                self.buffer.clear();
+            }
+        }
+        self.last_key = Some(now);
+
+        let mut numbers = Vec::new();
+        for key in input.chars() {
+            match key {
+                '\r' | '\n' | '\t' => {
+                    if !self.buffer.is_empty() {
+                        numbers.push(std::mem::take(&mut self.buffer));
+                    }
+                }
+                key if key.is_control() => {}
+                key => {
+                    self.buffer.push(key);
+                    if self.buffer.len() > MAX_NUMBER_LENGTH {
+                        self.buffer.clear();
+                    }
+                }
+            }
+        }
+
+        numbers
+    }
+}
+
+/// Badge numbers typed in by hand, one per input.
+#[derive(Default)]
+pub(crate) struct ManualSource;
+
+impl BadgeSource for ManualSource {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Manual
+    }
+
+    fn feed(&mut self, input: &str, _now: Instant) -> Vec<String> {
+        let number = input.trim();
+        if number.is_empty() || number.len() > MAX_NUMBER_LENGTH {
+            return Vec::new();
+        }
+
+        vec![number.to_string()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_format() {
+        assert_eq!(
+            NumberFormat::Hex.parse("04:a2:3b:1c").unwrap(),
+            [0x04, 0xa2, 0x3b, 0x1c]
+        );
+        assert_eq!(
+            NumberFormat::Decimal.parse("0077740828").unwrap(),
+            [0x04, 0xa2, 0x3b, 0x1c]
+        );
+        assert_eq!(
+            NumberFormat::DecimalReversed.parse("0473670148").unwrap(),
+            [0x04, 0xa2, 0x3b, 0x1c]
+        );
+        assert_eq!(NumberFormat::Hex.parse("04A23B1C2D3E4F").unwrap().len(), 7);
+
+        assert!(NumberFormat::Hex.parse("04A23B").is_err());
+        assert!(NumberFormat::Decimal.parse("-1").is_err());
+        assert!(NumberFormat::Decimal.parse("hOtB6pOx").is_err());
+    }
+
+    #[test]
+    fn test_wedge_source() {
+        let mut wedge = WedgeSource::default();
+        let start = Instant::now();
+        let key = Duration::from_millis(10);
+
+        assert!(wedge.feed("04A2", start).is_empty());
+        assert_eq!(wedge.feed("3B1C\r", start + key), vec!["04A23B1C"]);
+
+        // Typed by a person, too slow to be the wedge
+        wedge.feed("0", start + key * 10);
+        wedge.feed("4", start + key * 20);
+        assert!(wedge.feed("\n", start + key * 30).is_empty());
+
+        assert_eq!(
+            wedge.feed("04A23B1C\r\n04FFFFFF\t", start + key * 31),
+            vec!["04A23B1C", "04FFFFFF"]
+        );
+
+        // A scan right after a person typed something without Enter
+        wedge.feed("12", start + key * 40);
+        assert!(wedge.feed("04A2", start + key * 50).is_empty());
+        assert_eq!(wedge.feed("3B1C\r", start + key * 51), vec!["04A23B1C"]);
+    }
+
+    #[test]
+    fn test_manual_source() {
+        let mut manual = ManualSource;
+
+        assert_eq!(
+            manual.feed(" 04A23B1C \n", Instant::now()),
+            vec!["04A23B1C"]
+        );
+        assert!(manual.feed("   ", Instant::now()).is_empty());
+    }
+}
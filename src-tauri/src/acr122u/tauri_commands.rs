@@ -1,113 +1,163 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::executor::block_on;
 use pcsc::Context;
 use serde::Serialize;
 use tauri::ipc::InvokeError;
-use tauri::State;
+use tauri::{AppHandle, Manager, State};
 
-use crate::acr122u::card::read::read_block;
-use crate::acr122u::card::write::write_block;
-use crate::acr122u::reader::connect::reader;
+use crate::acr122u::card::badge::{
+    check_counter, current_counter, load_badge_key, read_badge, save_counter, write_badge, Badge,
+};
+use crate::acr122u::card::dump::{read_card_dump, write_card_dump, CardDump, DumpFormat};
+use crate::acr122u::card::format::{read_record, BadgeRecord, StoredData};
+use crate::acr122u::card::ndef::record::NdefRecord;
+use crate::acr122u::card::ndef::{read_message, write_message};
+use crate::acr122u::card::read::{read_range, read_uid};
+use crate::acr122u::card::revocation::{
+    check_revoked, list_revoked, remove_revoked, save_revoked, RevokedCard,
+};
+use crate::acr122u::card::utils::authenticate::KeyType;
+use crate::acr122u::card::utils::encoding::{validate_text, DataEncoding};
+use crate::acr122u::card::utils::keys::{parse_key, KeyMapping, KeyStore, SectorKey};
+use crate::acr122u::card::utils::trailer::{AccessConditions, SectorTrailer};
+use crate::acr122u::card::write::{write_block, write_sector_trailer};
+use crate::acr122u::driver::simulated::{self, SimulatedReader, SIMULATED_READER_NAME};
+use crate::acr122u::driver::{open, CardReader, ReaderModel};
+use crate::acr122u::reader::connect::{list_readers as list_attached_readers, reader, ReaderInfo};
+use crate::acr122u::reader::diagnostics::{self, collect_diagnostics, ReaderDiagnostics};
+use crate::acr122u::reader::feedback::FeedbackSettings;
+use crate::acr122u::reader::monitor::{reader_status as current_reader_status, ReaderStatus};
+use crate::acr122u::reader::queue::{CancelToken, Priority, ReaderQueue, TokenGroup};
+use crate::acr122u::reader::settings::{
+    load_settings, normalize_role, save_settings, ReaderSettings,
+};
+use crate::acr122u::source::{BadgeSource, ManualSource, SourceSettings, WedgeSource};
 use crate::acr122u::utils::errors::ReaderError;
+use crate::acr122u::watcher::submit_badge;
+use crate::cache::get::{find_cached_user_by_uid, get_cache, normalize_uid};
+use crate::cache::update::{set_user_card, CardRecord};
+use crate::database::connect::SharedDatabases;
+use crate::database::revoked_cards::{delete_revoked_card, insert_revoked_card};
+use crate::database::schemas::permission_verify::{PermissionAction, PermissionChecker};
+use crate::misc::token::verify;
 
 /// This will be used by the backend to keep the connection alive and pass the Context to other functions.
+///
+/// The simulated reader doesn't go through PC/SC, so it has no Context.
 pub(crate) struct FullReaderResult {
-    pub(crate) ctx: Context,
+    pub(crate) ctx: Option<Context>,
     pub(crate) reader: String,
 }
 
-/// This will be used by the frontend to keep track of the cancel flag.
+impl FullReaderResult {
+    /// Opens the driver for the connected reader.
+    pub(crate) fn driver(self) -> Result<Box<dyn CardReader>, ReaderError> {
+        match self.ctx {
+            Some(ctx) => open(ctx, self.reader),
+            None => Ok(Box::new(SimulatedReader::shared())),
+        }
+    }
+}
+
+/// This will be used by the frontend to cancel the reads in progress.
 #[derive(Default)]
 pub(crate) struct ReadState {
-    pub(crate) cancel_flag: Arc<AtomicBool>,
-    pub(crate) read_in_progress: Arc<Mutex<bool>>,
+    pub(crate) tokens: TokenGroup,
 }
 
+/// This will be used by the frontend to cancel the writes in progress.
 #[derive(Default)]
 pub(crate) struct WriteState {
-    pub(crate) cancel_flag: Arc<AtomicBool>,
+    pub(crate) tokens: TokenGroup,
+}
+
+/// The keys typed by the keyboard wedge so far, until the badge number is complete.
+#[derive(Default)]
+pub(crate) struct WedgeState {
+    pub(crate) source: Mutex<WedgeSource>,
 }
 
-/// FrontEnd expects a JSON object with a single field "reader" containing the reader name.
+/// FrontEnd expects a JSON object with a field "reader" containing the reader name.
 #[derive(Serialize)]
 pub(crate) struct ReaderResult {
     pub(crate) reader: String,
+    pub(crate) model: ReaderModel,
 }
 
 /** Functions for Commands */
 
 /// Connects to the reader, sets the Context and Reader name to the Backend struct and Reader name to the FrontEnd struct.
 ///
-/// This function does not take any arguments, but it needs to be called first to set the Context and Reader name.
+/// It needs to be called first to set the Context and Reader name.
 /// If not called first, everything else will fail miserably.
 ///
+/// # Arguments
+///
+/// * `role` - The role of the reader to connect to (optional), the selected reader is used when not given.
+///
 /// # Returns
 ///
 /// * `Ok(FullReaderResult)` - If the connection is successful.
 /// * `Err(ReaderError)` - If an error occurs during the connection.
-fn connect() -> Result<FullReaderResult, ReaderError> {
-    match reader() {
-        Ok((ctx, reader)) => Ok(FullReaderResult { ctx, reader }),
-        Err(e) => Err(e),
+fn connect(role: Option<&str>) -> Result<FullReaderResult, ReaderError> {
+    if simulated::is_enabled() {
+        return Ok(FullReaderResult {
+            ctx: None,
+            reader: SIMULATED_READER_NAME.to_string(),
+        });
     }
-}
 
-/// Validates the given PC/SC context.
-///
-/// # Arguments
-///
-/// * `context` - A reference to the PC/SC context.
-///
-/// # Returns
-///
-/// * `true` if the context is valid.
-/// * `false` if the context is invalid.
-fn validate_context(context: &Context) -> bool {
-    match context.is_valid() {
-        Ok(_valid) => true,
-        Err(_) => false,
+    match reader(role) {
+        Ok((ctx, reader)) => Ok(FullReaderResult {
+            ctx: Some(ctx),
+            reader,
+        }),
+        Err(e) => Err(e),
     }
 }
 
-/// Reads data from a specified block on the card.
+/// Reads the badge record, or the legacy id, stored at a block on the card.
 ///
 /// # Arguments
 ///
-/// * `block_number` - The block number to read from.
-/// * `state` - The state containing the cancel flag.
+/// * `block_number` - The block number the record starts at.
+/// * `role` - The role of the reader to read from (optional).
+/// * `timeout` - How long to wait for a card, the `card_timeout_secs` of the reader settings when the command isn't given one.
+/// * `queue` - The queue the read runs on.
+/// * `token` - The token to cancel the read with.
 ///
 /// # Returns
 ///
-/// * `Ok(Vec<u8>)` - The data read from the block.
-/// * `Err(ReaderError)` - If an error occurs during the read operation.
-async fn mcp_read(block_number: u16, state: Arc<ReadState>) -> Result<Vec<u8>, ReaderError> {
-    // Use the FullReaderResult struct to get the Context and Reader name
-    let connect = match connect() {
-        Ok(result) => result,
-        Err(e) => {
-            return Err(e);
-        }
-    };
+/// * `Ok(StoredData)` - What's stored at the block.
+/// * `Err(ReaderError)` - If the record is corrupted or an error occurs during the read operation.
+async fn mcp_read(
+    block_number: u16,
+    role: Option<String>,
+    timeout: Duration,
+    queue: &ReaderQueue,
+    token: CancelToken,
+) -> Result<StoredData, ReaderError> {
+    let keys = KeyStore::load()?;
 
-    let context = connect.ctx.clone();
-
-    if validate_context(&context) {
-        let reader_name = connect.reader;
-        let read = read_block(
-            context,
-            reader_name,
-            block_number,
-            &state.cancel_flag,
-            None,
-            None,
-            None,
-        )
-            .await?;
-        Ok(read)
-    } else {
-        Err(ReaderError::PcscError(pcsc::Error::InvalidHandle))
-    }
+    queue
+        .run(Priority::Normal, role, token, move |operation| {
+            block_on(read_record(
+                operation.driver,
+                &keys,
+                block_number,
+                operation.token.flag(),
+                Some(timeout),
+            ))
+        })
+        .await
+}
+
+/// How long to wait for a card, the timeout given to the command or the one of the reader settings.
+fn card_timeout(timeout_ms: Option<u64>) -> Duration {
+    load_settings().unwrap_or_default().card_timeout(timeout_ms)
 }
 
 /** Actual Commands */
@@ -120,104 +170,184 @@ async fn mcp_read(block_number: u16, state: Arc<ReadState>) -> Result<Vec<u8>, R
 /// * `Err(InvokeError)` - If an error occurs during the connection.
 #[tauri::command]
 pub(crate) fn connect_reader() -> Result<ReaderResult, InvokeError> {
-    let result = connect().map_err(|e| InvokeError::from(e))?;
+    let result = connect(None).map_err(|e| InvokeError::from(e))?;
+    let driver = result.driver().map_err(|e| InvokeError::from(e))?;
     Ok(ReaderResult {
-        reader: result.reader,
+        reader: driver.name().to_string(),
+        model: driver.model(),
     })
 }
 
-/// Reads data from a specified block on the card.
+/// Reads the employee id stored at a block on the card by `write_card`.
+///
+/// Cards written before badge records hold a bare id, it's returned the same way.
 ///
 /// # Arguments
 ///
-/// * `block_number` - The block number to read from.
-/// * `state` - The state containing the cancel flag.
+/// * `block_number` - The block number the record starts at.
+/// * `role` - The role of the reader to read from (optional), e.g. "entrance" or "exit".
+/// * `timeout_ms` - How long to wait for a card in milliseconds (optional), the one of the reader settings if not given.
+///   Fails with `Timeout` once it runs out.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
 ///
 /// # Returns
 ///
-/// * `Ok(String)` - The data read from the block as a UTF-8 string.
-/// * `Err(InvokeError)` - If an error occurs during the read operation.
+/// * `Ok(String)` - The employee id, empty if nothing was ever written there.
+/// * `Err(InvokeError)` - A `FormatError` if the record is corrupted or was written by a newer version,
+///   a `CardError` if the data is neither a record nor text, use `read_card_raw` for binary data, or the read fails.
 #[tauri::command]
 pub(crate) async fn read_card(
     block_number: u16,
+    role: Option<String>,
+    timeout_ms: Option<u64>,
+    queue: State<'_, Arc<ReaderQueue>>,
     state: State<'_, Arc<ReadState>>,
 ) -> Result<String, InvokeError> {
-    let cancel_flag = state.cancel_flag.clone();
-    cancel_flag.store(false, Ordering::SeqCst);
-
-    let read_in_progress = Arc::clone(&state.read_in_progress);
-
-    // Ensure that only one read operation can be in progress at a time
-    {
-        let mut in_progress = read_in_progress.lock().unwrap();
-        if *in_progress {
-            println!("Read already in progress.");
-            return Err(InvokeError::from(ReaderError::CardError(
-                "Read already in progress.".to_string(),
-                pcsc::Error::ServerTooBusy,
-            )));
-        }
-        *in_progress = true;
-    } // MutexGuard is dropped here to release the lock
-
-    let state_clone = state.inner().clone();
-
-    let result = mcp_read(block_number, state_clone).await;
-    let mut in_progress = read_in_progress.lock().unwrap(); // Re-acquire the lock to ensure we're modifying the most up-to-date state
-    *in_progress = false; // Ensure this line executes regardless of success or failure
+    let result = mcp_read(
+        block_number,
+        role,
+        card_timeout(timeout_ms),
+        &queue,
+        state.tokens.token(),
+    )
+    .await;
 
     match result {
-        Ok(data) => {
-            let data = String::from_utf8(data);
-            match data {
-                Ok(data) => Ok(data),
-                Err(e) => Err(InvokeError::from(ReaderError::CardError(
-                    e.to_string(),
-                    pcsc::Error::UnknownError,
-                ))),
-            }
-        }
+        Ok(StoredData::Record(record)) => Ok(record.employee_id),
+        Ok(StoredData::Legacy(id)) => Ok(id),
+        Ok(StoredData::Blank) => Ok(String::new()),
         Err(e) => Err(InvokeError::from(e)),
     }
 }
 
-/// Cancels the read operation.
+/// Reads raw bytes from the card, starting at a block and across as many blocks as needed.
+///
+/// Nothing is decoded or trimmed, the NUL padding of the last block is returned as is.
+///
+/// # Arguments
+///
+/// * `block_number` - The block or page to start reading at.
+/// * `length` - The number of bytes to read (optional), a 16 bytes block if not given.
+/// * `encoding` - How to encode the bytes, `Hex` (the default) or `Base64`.
+/// * `role` - The role of the reader to read from (optional).
+/// * `timeout_ms` - How long to wait for a card in milliseconds (optional), the one of the reader settings if not given.
+///   Fails with `Timeout` once it runs out.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The bytes read, encoded with `encoding`.
+/// * `Err(InvokeError)` - If the range goes past the user memory of the card or the read fails.
+#[tauri::command]
+pub(crate) async fn read_card_raw(
+    block_number: u16,
+    length: Option<u16>,
+    encoding: Option<DataEncoding>,
+    role: Option<String>,
+    timeout_ms: Option<u64>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<ReadState>>,
+) -> Result<String, InvokeError> {
+    let keys = KeyStore::load()?;
+    let length = length.unwrap_or(16) as usize;
+    let timeout = card_timeout(timeout_ms);
+
+    let data = queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            block_on(read_range(
+                operation.driver,
+                &keys,
+                block_number,
+                length,
+                operation.token.flag(),
+                Some(timeout),
+            ))
+        })
+        .await?;
+
+    Ok(encoding.unwrap_or_default().encode(&data))
+}
+
+/// Reads the hardware UID of the card, this is what identifies an employee.
 ///
 /// # Arguments
 ///
-/// * `state` - The state containing the cancel flag.
+/// * `role` - The role of the reader to read from (optional), e.g. "entrance" or "exit".
+/// * `timeout_ms` - How long to wait for a card in milliseconds (optional), the one of the reader settings if not given.
+///   Fails with `Timeout` once it runs out.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The UID as an uppercase hex string.
+/// * `Err(InvokeError)` - If an error occurs during the read operation.
+#[tauri::command]
+pub(crate) async fn read_card_uid(
+    role: Option<String>,
+    timeout_ms: Option<u64>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<ReadState>>,
+) -> Result<String, InvokeError> {
+    let timeout = card_timeout(timeout_ms);
+
+    let uid = queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            block_on(read_uid(operation.driver, operation.token.flag(), Some(timeout)))
+        })
+        .await?;
+
+    Ok(hex::encode_upper(uid))
+}
+
+/// Cancels the read operations, the ones running and the ones waiting for their turn.
+///
+/// A read waiting for a card stops right away, it doesn't need another tap to notice.
+///
+/// # Arguments
+///
+/// * `state` - The state with the tokens of the reads.
 ///
 /// # Returns
 ///
 /// * `Ok(())` - If the cancel operation is successful.
 #[tauri::command]
 pub(crate) fn cancel_read(state: State<'_, Arc<ReadState>>) -> Result<(), InvokeError> {
-    state.cancel_flag.store(true, Ordering::SeqCst);
+    state.tokens.cancel();
     Ok(())
 }
 
-/// Cancels the write operation.
+/// Cancels the write operations, the ones running and the ones waiting for their turn.
+///
+/// A write waiting for a card stops right away, it doesn't need another tap to notice.
 ///
 /// # Arguments
 ///
-/// * `state` - The state containing the cancel flag.
+/// * `state` - The state with the tokens of the writes.
 ///
 /// # Returns
 ///
 /// * `Ok(())` - If the cancel operation is successful.
 #[tauri::command]
 pub(crate) fn cancel_write(state: State<'_, Arc<WriteState>>) -> Result<(), InvokeError> {
-    state.cancel_flag.store(true, Ordering::SeqCst);
+    state.tokens.cancel();
     Ok(())
 }
 
 /// Writes data to a specified block on the card.
 ///
+/// Data longer than 16 bytes is written to the following data blocks, skipping the sector trailers.
+///
 /// # Arguments
 ///
 /// * `block_number` - The block number to write to.
-/// * `data` - The data to write.
-/// * `state` - The state containing the cancel flag.
+/// * `data` - The data to write, padded with zeros to whole blocks.
+/// * `role` - The role of the reader to write with (optional).
+/// * `timeout` - How long to wait for a card, the `card_timeout_secs` of the reader settings when the command isn't given one.
+/// * `queue` - The queue the write runs on.
+/// * `token` - The token to cancel the write with.
 ///
 /// # Returns
 ///
@@ -225,58 +355,56 @@ pub(crate) fn cancel_write(state: State<'_, Arc<WriteState>>) -> Result<(), Invo
 /// * `Err(ReaderError)` - If an error occurs during the write operation.
 async fn mcp_write(
     block_number: u16,
-    data: String,
-    state: Arc<WriteState>,
+    data: Vec<u8>,
+    role: Option<String>,
+    timeout: Duration,
+    queue: &ReaderQueue,
+    token: CancelToken,
 ) -> Result<(), ReaderError> {
-    // Use the FullReaderResult struct to get the Context and Reader name
-    let connect = match connect() {
-        Ok(result) => result,
-        Err(e) => {
-            return Err(e);
-        }
-    };
+    let keys = KeyStore::load()?;
+
+    // Padded with zeros to whole blocks, longer data carries on in the next data blocks
+    let mut buffer = data;
+    buffer.resize(buffer.len().div_ceil(16).max(1) * 16, 0);
 
-    let context = connect.ctx.clone();
-
-    if validate_context(&context) {
-        let reader_name = connect.reader;
-
-        let mut buffer = vec![0; 16];
-        let data_len = data.len();
-        let copy_len = std::cmp::min(data_len, buffer.len());
-        buffer[..copy_len].copy_from_slice(&data.as_bytes()[..copy_len]);
-
-        let result = write_block(
-            context,
-            reader_name,
-            block_number,
-            buffer,
-            Option::from(16),
-            &state.cancel_flag,
-        )
-            .await
-            .unwrap_or_else(|e| panic!("{:?}", e));
-
-        if result {
-            Ok(())
-        } else {
-            Err(ReaderError::CardError(
-                "Write failed.".to_string(),
-                pcsc::Error::CardNotAuthenticated,
+    let result = queue
+        .run(Priority::Normal, role, token, move |operation| {
+            block_on(write_block(
+                operation.driver,
+                &keys,
+                block_number,
+                buffer,
+                Option::from(16),
+                operation.token.flag(),
+                Some(timeout),
             ))
-        }
+        })
+        .await?;
+
+    if result {
+        Ok(())
     } else {
-        Err(ReaderError::PcscError(pcsc::Error::InvalidHandle))
+        Err(ReaderError::CardError(
+            "Write failed.".to_string(),
+            pcsc::Error::CardNotAuthenticated,
+        ))
     }
 }
 
-/// Writes data to a specified block on the card.
+/// Writes an employee id to the card as a badge record, see `BadgeRecord` for the layout.
+///
+/// The record is written to the following data blocks when it's longer than 16 bytes, skipping the sector trailers.
+/// Use `write_card_raw` for anything else than an employee id.
 ///
 /// # Arguments
 ///
-/// * `block_number` - The block number to write to.
-/// * `data` - The data to write.
-/// * `state` - The state containing the cancel flag.
+/// * `block_number` - The block number the record starts at.
+/// * `data` - The employee id, at most 51 bytes of UTF-8 without NUL characters.
+/// * `role` - The role of the reader to write with (optional), e.g. "entrance" or "exit".
+/// * `timeout_ms` - How long to wait for a card in milliseconds (optional), the one of the reader settings if not given.
+///   Fails with `Timeout` once it runs out.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
 ///
 /// # Returns
 ///
@@ -286,20 +414,637 @@ async fn mcp_write(
 pub(crate) async fn write_card(
     block_number: u16,
     data: String,
+    role: Option<String>,
+    timeout_ms: Option<u64>,
+    queue: State<'_, Arc<ReaderQueue>>,
     state: State<'_, Arc<WriteState>>,
 ) -> Result<bool, InvokeError> {
-    let cancel_flag = state.cancel_flag.clone();
-    cancel_flag.store(false, Ordering::Relaxed);
+    validate_text(&data).map_err(InvokeError::from)?;
+    let record = BadgeRecord::new(&data)
+        .encode()
+        .map_err(InvokeError::from)?;
 
-    let state_clone = state.inner().clone();
-
-    let result = mcp_write(block_number, data, state_clone).await;
+    let result = mcp_write(
+        block_number,
+        record,
+        role,
+        card_timeout(timeout_ms),
+        &queue,
+        state.tokens.token(),
+    )
+    .await;
     match result {
         Ok(_) => Ok(true),
         Err(e) => Err(InvokeError::from(e)),
     }
 }
 
+/// Writes raw bytes, given as hex or base64, starting at a block on the card.
+///
+/// Data longer than a block carries on in the next data blocks, skipping the sector trailers,
+/// and the last block is padded with zeros.
+///
+/// # Arguments
+///
+/// * `block_number` - The block or page to start writing at.
+/// * `data` - The bytes to write, encoded with `encoding`.
+/// * `encoding` - `Hex` (the default) or `Base64`.
+/// * `role` - The role of the reader to write with (optional).
+/// * `timeout_ms` - How long to wait for a card in milliseconds (optional), the one of the reader settings if not given.
+///   Fails with `Timeout` once it runs out.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
+///
+/// # Returns
+///
+/// * `Ok(true)` - If the data was written.
+/// * `Err(ReaderError)` - If the data isn't valid for the encoding, doesn't fit in the card or the write fails.
+#[tauri::command]
+pub(crate) async fn write_card_raw(
+    block_number: u16,
+    data: String,
+    encoding: Option<DataEncoding>,
+    role: Option<String>,
+    timeout_ms: Option<u64>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<WriteState>>,
+) -> Result<bool, ReaderError> {
+    let data = encoding.unwrap_or_default().decode(&data)?;
+    mcp_write(
+        block_number,
+        data,
+        role,
+        card_timeout(timeout_ms),
+        &queue,
+        state.tokens.token(),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Writes the sector trailer of a card, setting its keys and access conditions.
+///
+/// This is the only way to write a trailer block, `write_card` rejects them.
+/// The new key of the sector is saved to the key store so the card can still be read afterwards.
+///
+/// # Arguments
+///
+/// * `sector` - The sector to write the trailer of.
+/// * `access` - The access conditions of the three data blocks and of the trailer.
+/// * `key_a` - The new key A written as hex.
+/// * `key_b` - The new key B written as hex.
+/// * `general_purpose` - The general purpose byte (optional), e.g. 0xC1 for the MAD sector of NDEF cards.
+/// * `role` - The role of the reader to write with (optional).
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
+///
+/// # Returns
+///
+/// * `Ok(KeyMapping)` - The updated mapping of sectors to key types.
+/// * `Err(ReaderError)` - If the access conditions are invalid or the write fails.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn write_card_trailer(
+    sector: u8,
+    access: AccessConditions,
+    key_a: String,
+    key_b: String,
+    general_purpose: Option<u8>,
+    role: Option<String>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<WriteState>>,
+) -> Result<KeyMapping, ReaderError> {
+    let trailer = SectorTrailer {
+        key_a: parse_key(&key_a)?,
+        access,
+        general_purpose: general_purpose.unwrap_or(0x69),
+        key_b: parse_key(&key_b)?,
+    };
+
+    let mut keys = KeyStore::load()?;
+    let timeout = card_timeout(None);
+
+    queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            block_on(write_sector_trailer(
+                operation.driver,
+                &mut keys,
+                sector,
+                &trailer,
+                operation.token.flag(),
+                Some(timeout),
+            ))?;
+            Ok(keys.mapping())
+        })
+        .await
+}
+
+/// Writes an NDEF message with Text and URI records to the card, readable by phones and other NFC tools.
+///
+/// # Arguments
+///
+/// * `records` - The records of the message.
+/// * `role` - The role of the reader to write with (optional).
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
+///
+/// # Returns
+///
+/// * `Ok(true)` - If the message was written.
+/// * `Err(ReaderError)` - If the message doesn't fit in the card or the write fails.
+#[tauri::command]
+pub(crate) async fn write_ndef(
+    records: Vec<NdefRecord>,
+    role: Option<String>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<WriteState>>,
+) -> Result<bool, ReaderError> {
+    let keys = KeyStore::load()?;
+    let timeout = card_timeout(None);
+
+    queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            block_on(write_message(
+                operation.driver,
+                &keys,
+                &records,
+                operation.token.flag(),
+                Some(timeout),
+            ))
+        })
+        .await?;
+
+    Ok(true)
+}
+
+/// Reads the NDEF message of the card.
+///
+/// # Arguments
+///
+/// * `role` - The role of the reader to read from (optional).
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
+///
+/// # Returns
+///
+/// * `Ok(Vec<NdefRecord>)` - The Text and URI records of the message.
+/// * `Err(InvokeError)` - If the card has no NDEF message or the read fails.
+#[tauri::command]
+pub(crate) async fn read_ndef(
+    role: Option<String>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<ReadState>>,
+) -> Result<Vec<NdefRecord>, InvokeError> {
+    let keys = KeyStore::load()?;
+    let timeout = card_timeout(None);
+
+    let records = queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            block_on(read_message(
+                operation.driver,
+                &keys,
+                operation.token.flag(),
+                Some(timeout),
+            ))
+        })
+        .await?;
+
+    Ok(records)
+}
+
+/// Dumps every sector of the Mifare Classic card to a file, as a backup or to copy it onto another card.
+///
+/// Sectors are opened with the key store, falling back to the transport keys,
+/// the keys that opened each sector are saved with the dump.
+///
+/// # Arguments
+///
+/// * `path` - The file to save the dump to.
+/// * `format` - `Json` (the default) or the `Binary` image used by other Mifare tools.
+/// * `role` - The role of the reader to read from (optional).
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
+///
+/// # Returns
+///
+/// * `Ok(CardDump)` - The dump saved to the file.
+/// * `Err(InvokeError)` - If the card isn't a Mifare Classic, the read fails or the file can't be written.
+#[tauri::command]
+pub(crate) async fn dump_card(
+    path: String,
+    format: Option<DumpFormat>,
+    role: Option<String>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<ReadState>>,
+) -> Result<CardDump, InvokeError> {
+    let keys = KeyStore::load()?;
+    let timeout = card_timeout(None);
+
+    let dump = queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            read_card_dump(operation.driver, &keys, operation.token.flag(), Some(timeout))
+        })
+        .await?;
+
+    let bytes = dump.to_bytes(format.unwrap_or(DumpFormat::Json))?;
+    std::fs::write(&path, bytes)
+        .map_err(|e| ReaderError::DumpError(format!("Couldn't save {}: {}", path, e)))?;
+
+    Ok(dump)
+}
+
+/// Restores a dump saved by `dump_card`, or a binary image from other Mifare tools, onto a blank card.
+///
+/// The manufacturer block can't be written, so the card keeps its own UID.
+/// With `write_trailers`, the keys and access conditions of the dump are restored too
+/// and the key store is updated with them.
+///
+/// # Arguments
+///
+/// * `path` - The file to load the dump from.
+/// * `write_trailers` - Whether to restore the sector trailers (optional), `false` by default.
+/// * `role` - The role of the reader to write with (optional).
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u16>)` - The blocks written, in order.
+/// * `Err(ReaderError)` - If the file isn't a dump, a trailer can't be rebuilt or the write fails.
+#[tauri::command]
+pub(crate) async fn restore_card(
+    path: String,
+    write_trailers: Option<bool>,
+    role: Option<String>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<WriteState>>,
+) -> Result<Vec<u16>, ReaderError> {
+    let bytes = std::fs::read(&path)
+        .map_err(|e| ReaderError::DumpError(format!("Couldn't open {}: {}", path, e)))?;
+    let dump = CardDump::from_bytes(&bytes)?;
+
+    let mut keys = KeyStore::load()?;
+    let write_trailers = write_trailers.unwrap_or(false);
+    let timeout = card_timeout(None);
+
+    queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            write_card_dump(
+                operation.driver,
+                &mut keys,
+                &dump,
+                write_trailers,
+                operation.token.flag(),
+                Some(timeout),
+            )
+        })
+        .await
+}
+
+/// Gets a handle to the local sled database, where the badge counters and the revoked cards are kept.
+async fn local_db(app: &AppHandle) -> Result<sled::Db, ReaderError> {
+    let db = app.state::<SharedDatabases>();
+    let sled_db = db
+        .sled_db
+        .as_ref()
+        .ok_or_else(|| ReaderError::BadgeError("Sled database unavailable".to_string()))?;
+
+    let sled_db = sled_db.lock().await.clone();
+    Ok(sled_db)
+}
+
+/// Writes a signed badge for the employee to the card.
+///
+/// The badge is bound to the UID of the card and replaces every badge issued to the employee before it.
+/// Only admins with the `WriteOthers` permission can issue badges.
+///
+/// # Arguments
+///
+/// * `user_id` - The id of the employee.
+/// * `token` - The login token of the admin issuing the badge.
+/// * `role` - The role of the reader to write with (optional).
+/// * `app` - The app handle, used to get the local database.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
+///
+/// # Returns
+///
+/// * `Ok(u32)` - The issue counter of the new badge.
+/// * `Err(ReaderError)` - If the admin isn't allowed, the employee doesn't exist or the write fails.
+#[tauri::command]
+pub(crate) async fn issue_badge(
+    user_id: String,
+    token: String,
+    role: Option<String>,
+    app: AppHandle,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<WriteState>>,
+) -> Result<u32, ReaderError> {
+    // Issuing a badge turns the current one down, so it's as sensitive as enrolling a card
+    require_write_others(token, &app).await?;
+
+    if !get_cache().values().any(|user| user.id == user_id) {
+        return Err(ReaderError::BadgeError("User not found".to_string()));
+    }
+
+    let counters = local_db(&app).await?;
+    let counter = current_counter(&counters, &user_id)? + 1;
+    let keys = KeyStore::load()?;
+    let badge_key = load_badge_key()?;
+    let timeout = card_timeout(None);
+
+    let employee_id = user_id.clone();
+    let badge = queue
+        .run(Priority::High, role, state.tokens.token(), move |operation| {
+            block_on(write_badge(
+                operation.driver,
+                &keys,
+                &badge_key,
+                &employee_id,
+                counter,
+                operation.token.flag(),
+                Some(timeout),
+            ))
+        })
+        .await?;
+
+    // Only saved once the card is written, so a failed write doesn't lock out the current badge
+    save_counter(&counters, &badge.employee_id, badge.counter)?;
+
+    Ok(badge.counter)
+}
+
+/// Reads the badge on the card and checks its signature, UID binding and counter.
+///
+/// Unlike `read_card`, this never trusts what's written on the card without checking it first.
+///
+/// # Arguments
+///
+/// * `role` - The role of the reader to read from (optional).
+/// * `app` - The app handle, used to get the local database.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The id of the employee the badge was issued to.
+/// * `Err(InvokeError)` - If the card has no valid badge or the read fails.
+#[tauri::command]
+pub(crate) async fn verify_badge(
+    role: Option<String>,
+    app: AppHandle,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<ReadState>>,
+) -> Result<String, InvokeError> {
+    let counters = local_db(&app).await?;
+    let keys = KeyStore::load()?;
+    let badge_key = load_badge_key()?;
+    let timeout = card_timeout(None);
+
+    let badge = queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            block_on(read_badge(
+                operation.driver,
+                &keys,
+                &badge_key,
+                operation.token.flag(),
+                Some(timeout),
+            ))
+        })
+        .await?;
+    check_counter(&counters, &badge)?;
+
+    Ok(badge.employee_id)
+}
+
+/// Checks the login token of an admin and that they have the `WriteOthers` permission.
+///
+/// Returns the id of the admin.
+async fn require_write_others(token: String, app: &AppHandle) -> Result<String, ReaderError> {
+    // A token that doesn't check out can't prove the permission either
+    let admin = verify(token, app.clone())
+        .await
+        .map_err(|_| ReaderError::PermissionDenied("WriteOthers".to_string()))?;
+
+    if !PermissionChecker::check_permission(
+        admin.worker_data.permissions,
+        PermissionAction::WriteOthers,
+    ) {
+        return Err(ReaderError::PermissionDenied("WriteOthers".to_string()));
+    }
+
+    Ok(admin.id)
+}
+
+/// Enrolls a card to an employee: writes a signed badge to it, checks it back and saves the card on the user.
+///
+/// The card enrolled to the employee before it stops working, both its UID and its badge are replaced.
+/// Only admins with the `WriteOthers` permission can enroll cards, and the app has to be online.
+///
+/// # Arguments
+///
+/// * `user_id` - The id of the employee getting the card.
+/// * `token` - The login token of the admin enrolling the card.
+/// * `role` - The role of the reader to write with (optional).
+/// * `app` - The app handle, used to get the databases.
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the writes.
+///
+/// # Returns
+///
+/// * `Ok(CardRecord)` - The UID, issue date and serial saved on the user.
+/// * `Err(ReaderError)` - If the admin isn't allowed, the card belongs to someone else or the write fails.
+#[tauri::command]
+pub(crate) async fn enroll_card(
+    user_id: String,
+    token: String,
+    role: Option<String>,
+    app: AppHandle,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<WriteState>>,
+) -> Result<CardRecord, ReaderError> {
+    require_write_others(token, &app).await?;
+
+    if !get_cache().values().any(|user| user.id == user_id) {
+        return Err(ReaderError::EnrollmentError("User not found".to_string()));
+    }
+
+    // Checked before touching the card, the user can't be saved offline anyway
+    if !app.state::<SharedDatabases>().is_online.load(Ordering::SeqCst) {
+        return Err(ReaderError::EnrollmentError(
+            "App is offline and cannot enroll cards for now.".to_string(),
+        ));
+    }
+
+    let counters = local_db(&app).await?;
+    let counter = current_counter(&counters, &user_id)? + 1;
+    let keys = KeyStore::load()?;
+    let badge_key = load_badge_key()?;
+    let timeout = card_timeout(None);
+
+    // Runs ahead of the reads waiting in the queue, the admin is holding the card on the reader
+    let (local, employee_id) = (counters.clone(), user_id.clone());
+    let badge = queue
+        .run(Priority::High, role, state.tokens.token(), move |operation| {
+            block_on(mcp_enroll(
+                operation.driver,
+                &local,
+                &keys,
+                &badge_key,
+                &employee_id,
+                counter,
+                operation.token.flag(),
+                timeout,
+            ))
+        })
+        .await?;
+
+    let card = CardRecord {
+        uid: Some(hex::encode_upper(&badge.uid)),
+        issued_at: Some(chrono::Utc::now().to_rfc3339()),
+        serial: Some(badge.counter),
+    };
+    set_user_card(app, &user_id, card.clone())
+        .await
+        .map_err(ReaderError::EnrollmentError)?;
+
+    // Only saved once the card is on the user, it's what turns the badge of the previous card down,
+    // so an enrollment that fails halfway doesn't leave the employee without a working card
+    save_counter(&counters, &badge.employee_id, badge.counter)?;
+
+    Ok(card)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn mcp_enroll(
+    driver: &mut dyn CardReader,
+    local_db: &sled::Db,
+    keys: &KeyStore,
+    badge_key: &[u8],
+    user_id: &str,
+    counter: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    timeout: Duration,
+) -> Result<Badge, ReaderError> {
+    // A card enrolled to someone else has to be taken from them first
+    let uid = hex::encode_upper(read_uid(driver, cancel_flag, Some(timeout)).await?);
+    check_revoked(local_db, &uid)?;
+    if find_cached_user_by_uid(&uid).is_some_and(|owner| owner.id != user_id) {
+        return Err(ReaderError::EnrollmentError(
+            "Card is already enrolled to another user".to_string(),
+        ));
+    }
+
+    let written = write_badge(
+        driver,
+        keys,
+        badge_key,
+        user_id,
+        counter,
+        cancel_flag,
+        Some(timeout),
+    )
+    .await?;
+
+    // Read from the card again, so a badge that didn't stick isn't saved on the user
+    let badge = read_badge(driver, keys, badge_key, cancel_flag, Some(timeout)).await?;
+    if badge != written || hex::encode_upper(&badge.uid) != uid {
+        return Err(ReaderError::EnrollmentError(
+            "The badge read back doesn't match the one written".to_string(),
+        ));
+    }
+
+    Ok(badge)
+}
+
+/// Revokes a lost or stolen card, every tap of it is turned down from then on, even offline.
+///
+/// The card doesn't need to be on the reader, it's revoked by its UID.
+/// Only admins with the `WriteOthers` permission can revoke cards, and the app has to be online.
+///
+/// # Arguments
+///
+/// * `uid` - The UID of the card as hex.
+/// * `reason` - Why the card is revoked, shown on the kiosk when it's tapped.
+/// * `token` - The login token of the admin revoking the card.
+/// * `app` - The app handle, used to get the databases.
+///
+/// # Returns
+///
+/// * `Ok(RevokedCard)` - The card added to the revocation list.
+/// * `Err(ReaderError)` - If the admin isn't allowed or the revocation list can't be saved.
+#[tauri::command]
+pub(crate) async fn revoke_card(
+    uid: String,
+    reason: String,
+    token: String,
+    app: AppHandle,
+) -> Result<RevokedCard, ReaderError> {
+    let revoked_by = require_write_others(token, &app).await?;
+
+    let uid = normalize_uid(&uid);
+    if uid.is_empty() {
+        return Err(ReaderError::BadgeError("Invalid card UID".to_string()));
+    }
+
+    let card = RevokedCard {
+        uid,
+        reason,
+        revoked_by,
+        revoked_at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    insert_revoked_card(app.clone(), &card)
+        .await
+        .map_err(ReaderError::BadgeError)?;
+    save_revoked(&local_db(&app).await?, &card)?;
+
+    Ok(card)
+}
+
+/// Takes a card out of the revocation list, so it punches in again.
+///
+/// Only admins with the `WriteOthers` permission can reinstate cards, and the app has to be online.
+///
+/// # Arguments
+///
+/// * `uid` - The UID of the card as hex.
+/// * `token` - The login token of the admin reinstating the card.
+/// * `app` - The app handle, used to get the databases.
+///
+/// # Returns
+///
+/// * `Ok(true)` - If the card was reinstated.
+/// * `Err(ReaderError)` - If the admin isn't allowed or the revocation list can't be saved.
+#[tauri::command]
+pub(crate) async fn reinstate_card(
+    uid: String,
+    token: String,
+    app: AppHandle,
+) -> Result<bool, ReaderError> {
+    require_write_others(token, &app).await?;
+
+    let uid = normalize_uid(&uid);
+    delete_revoked_card(app.clone(), &uid)
+        .await
+        .map_err(ReaderError::BadgeError)?;
+    remove_revoked(&local_db(&app).await?, &uid)?;
+
+    Ok(true)
+}
+
+/// Lists the revoked cards, from the local copy of the revocation list.
+///
+/// # Returns
+///
+/// * `Ok(Vec<RevokedCard>)` - The revoked cards, with the reason each one was revoked.
+/// * `Err(ReaderError)` - If the local database is unavailable.
+#[tauri::command]
+pub(crate) async fn list_revoked_cards(app: AppHandle) -> Result<Vec<RevokedCard>, ReaderError> {
+    list_revoked(&local_db(&app).await?)
+}
+
 /// Gets the connection to the reader and returns the reader name.
 ///
 /// # Returns
@@ -308,8 +1053,421 @@ pub(crate) async fn write_card(
 /// * `Err(ReaderError)` - If an error occurs during the connection.
 #[tauri::command]
 pub(crate) fn get_connection() -> Result<String, ReaderError> {
-    match connect() {
+    match connect(None) {
         Ok(result) => Ok(result.reader),
         Err(e) => Err(e),
     }
 }
+
+/// Lists every reader attached to the system.
+///
+/// # Returns
+///
+/// * `Ok(Vec<ReaderInfo>)` - The readers, with their capabilities and the roles bound to them.
+/// * `Err(ReaderError)` - If the readers could not be listed.
+#[tauri::command]
+pub(crate) fn list_readers() -> Result<Vec<ReaderInfo>, ReaderError> {
+    list_attached_readers()
+}
+
+/// Gets whether the active reader is plugged in, along with every reader attached.
+///
+/// Changes are also sent as `reader:connected` and `reader:disconnected` events, so this is only needed at startup.
+///
+/// # Returns
+///
+/// * `Ok(ReaderStatus)` - The current state of the readers.
+/// * `Err(ReaderError)` - If PC/SC couldn't be reached.
+#[tauri::command]
+pub(crate) fn reader_status() -> Result<ReaderStatus, ReaderError> {
+    current_reader_status()
+}
+
+/// Collects the firmware, PC/SC state, card and test authentication of the reader, for support tickets.
+///
+/// Doesn't wait for a card, the card details are left out if there's none on the reader.
+/// A probe failing doesn't stop the others, its error is listed in the result instead.
+///
+/// # Arguments
+///
+/// * `sector` - The sector to test the authentication on (optional), with the key from the key store.
+/// * `role` - The role of the reader to check (optional).
+/// * `queue` - The queue of card operations it runs on.
+/// * `state` - The state with the cancel tokens of the reads.
+///
+/// # Returns
+///
+/// * `Ok(ReaderDiagnostics)` - Everything that could be found out about the reader and its card.
+/// * `Err(InvokeError)` - If the reader couldn't be opened at all.
+#[tauri::command]
+pub(crate) async fn reader_diagnostics(
+    sector: Option<u8>,
+    role: Option<String>,
+    queue: State<'_, Arc<ReaderQueue>>,
+    state: State<'_, Arc<ReadState>>,
+) -> Result<ReaderDiagnostics, InvokeError> {
+    let mut errors = Vec::new();
+    let keys = KeyStore::load().unwrap_or_else(|e| {
+        errors.push(format!("Key store: {}", e));
+        KeyStore::default()
+    });
+
+    let diagnostics = queue
+        .run(Priority::Normal, role, state.tokens.token(), move |operation| {
+            let reader_state = match operation.context {
+                Some(ctx) => diagnostics::reader_state(ctx, operation.driver.name())
+                    .unwrap_or_else(|e| {
+                        errors.push(format!("Reader state: {}", e));
+                        pcsc::State::UNAWARE
+                    }),
+                None if simulated::is_card_present() => pcsc::State::PRESENT,
+                None => pcsc::State::EMPTY,
+            };
+
+            let mut diagnostics = collect_diagnostics(
+                operation.driver,
+                reader_state,
+                &keys,
+                sector,
+                operation.token.flag(),
+            );
+            diagnostics.errors.splice(0..0, errors);
+            Ok(diagnostics)
+        })
+        .await?;
+
+    Ok(diagnostics)
+}
+
+/// Gets the reader settings, with the selected reader and the roles bound to each reader.
+///
+/// # Returns
+///
+/// * `Ok(ReaderSettings)` - The current settings.
+/// * `Err(ReaderError)` - If the settings could not be loaded.
+#[tauri::command]
+pub(crate) fn get_reader_settings() -> Result<ReaderSettings, ReaderError> {
+    load_settings()
+}
+
+/// Selects the reader used by `read_card` and `write_card` when no role is given.
+///
+/// # Arguments
+///
+/// * `reader` - The name of the reader, as returned by `list_readers`. `None` goes back to the first supported reader.
+///
+/// # Returns
+///
+/// * `Ok(ReaderSettings)` - The updated settings.
+/// * `Err(ReaderError)` - If the reader is not attached or the settings could not be saved.
+#[tauri::command]
+pub(crate) fn select_reader(reader: Option<String>) -> Result<ReaderSettings, ReaderError> {
+    if let Some(ref reader) = reader {
+        ensure_attached(reader)?;
+    }
+
+    let mut settings = load_settings()?;
+    settings.selected_reader = reader;
+    save_settings(&settings)?;
+
+    Ok(settings)
+}
+
+/// Sets the LED and buzzer patterns the readers play after each tap.
+///
+/// # Arguments
+///
+/// * `feedback` - The patterns for accepted, rejected and duplicate taps, and taps waiting for the PIN.
+///
+/// # Returns
+///
+/// * `Ok(ReaderSettings)` - The updated settings.
+/// * `Err(ReaderError)` - If the settings could not be saved.
+#[tauri::command]
+pub(crate) fn set_feedback_settings(
+    feedback: FeedbackSettings,
+) -> Result<ReaderSettings, ReaderError> {
+    let mut settings = load_settings()?;
+    settings.feedback = feedback;
+    save_settings(&settings)?;
+
+    Ok(settings)
+}
+
+/// Sets how long the card commands wait for a card when they aren't given a timeout.
+///
+/// # Arguments
+///
+/// * `timeout_secs` - The timeout in seconds, at least 1.
+///
+/// # Returns
+///
+/// * `Ok(ReaderSettings)` - The updated settings.
+/// * `Err(ReaderError)` - If the timeout is 0 or the settings could not be saved.
+#[tauri::command]
+pub(crate) fn set_card_timeout(timeout_secs: u64) -> Result<ReaderSettings, ReaderError> {
+    if timeout_secs == 0 {
+        return Err(ReaderError::SettingsError(
+            "The card timeout must be at least 1 second".to_string(),
+        ));
+    }
+
+    let mut settings = load_settings()?;
+    settings.card_timeout_secs = timeout_secs;
+    save_settings(&settings)?;
+
+    Ok(settings)
+}
+
+/// Turns the keyboard wedge and the manual entry of badge numbers on or off, and sets how the numbers are written.
+///
+/// # Arguments
+///
+/// * `sources` - The settings of the badge sources.
+///
+/// # Returns
+///
+/// * `Ok(ReaderSettings)` - The updated settings.
+/// * `Err(ReaderError)` - If the settings could not be saved.
+#[tauri::command]
+pub(crate) fn set_source_settings(sources: SourceSettings) -> Result<ReaderSettings, ReaderError> {
+    let mut settings = load_settings()?;
+    settings.sources = sources;
+    save_settings(&settings)?;
+
+    Ok(settings)
+}
+
+/// Takes the keys typed by a keyboard wedge badge reader, the frontend forwards them as they come in.
+///
+/// Every badge number the keys complete goes through the same checks as a tap on the reader
+/// and is emitted as `card:tapped` or `card:rejected`, see `submit_badge`.
+/// A number that isn't valid doesn't hold back the ones after it, the first of them is reported once they're all in.
+///
+/// # Arguments
+///
+/// * `keys` - The keys typed, Enter as "\n".
+/// * `app` - The app handle, to emit the events.
+/// * `wedge` - The state with the keys typed so far.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the keys were taken, whether they completed a badge number or not.
+/// * `Err(ReaderError)` - If the keyboard wedge is turned off or a badge number isn't valid.
+#[tauri::command]
+pub(crate) async fn wedge_input(
+    keys: String,
+    app: AppHandle,
+    wedge: State<'_, Arc<WedgeState>>,
+) -> Result<(), ReaderError> {
+    let settings = load_settings()?.sources;
+    if !settings.wedge {
+        return Err(ReaderError::SettingsError(
+            "The keyboard wedge is turned off".to_string(),
+        ));
+    }
+
+    let (kind, numbers) = {
+        let mut source = wedge.source.lock().unwrap();
+        source.max_key_interval = Duration::from_millis(settings.max_key_interval_ms);
+        (source.kind(), source.feed(&keys, Instant::now()))
+    };
+
+    let mut invalid = None;
+    for number in numbers {
+        match settings.format.parse(&number) {
+            Ok(uid) => submit_badge(&app, kind, &uid).await,
+            Err(e) => {
+                println!("Keyboard wedge: {}", e);
+                invalid.get_or_insert(e);
+            }
+        }
+    }
+
+    match invalid {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Takes a badge number typed in by hand, for employees who forgot their card or when the reader is down.
+///
+/// It goes through the same checks as a tap on the reader and is emitted as `card:tapped` or `card:rejected`.
+///
+/// # Arguments
+///
+/// * `number` - The badge number, as printed on the card.
+/// * `app` - The app handle, to emit the event.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the badge number was taken.
+/// * `Err(ReaderError)` - If the manual entry is turned off or the badge number isn't valid.
+#[tauri::command]
+pub(crate) async fn submit_badge_number(number: String, app: AppHandle) -> Result<(), ReaderError> {
+    let settings = load_settings()?.sources;
+    if !settings.manual {
+        return Err(ReaderError::SettingsError(
+            "The manual entry of badge numbers is turned off".to_string(),
+        ));
+    }
+
+    let mut source = ManualSource;
+    let numbers = source.feed(&number, Instant::now());
+    if numbers.is_empty() {
+        return Err(ReaderError::BadgeError("The badge number is empty".to_string()));
+    }
+
+    for number in numbers {
+        let uid = settings.format.parse(&number)?;
+        submit_badge(&app, source.kind(), &uid).await;
+    }
+
+    Ok(())
+}
+
+/// Binds a reader to a role, e.g. "entrance" or "exit".
+///
+/// # Arguments
+///
+/// * `role` - The role to bind.
+/// * `reader` - The name of the reader, as returned by `list_readers`. `None` removes the binding.
+///
+/// # Returns
+///
+/// * `Ok(ReaderSettings)` - The updated settings.
+/// * `Err(ReaderError)` - If the reader is not attached or the settings could not be saved.
+#[tauri::command]
+pub(crate) fn set_reader_role(
+    role: String,
+    reader: Option<String>,
+) -> Result<ReaderSettings, ReaderError> {
+    let role = normalize_role(&role);
+    if role.is_empty() {
+        return Err(ReaderError::SettingsError(
+            "Role cannot be empty".to_string(),
+        ));
+    }
+
+    let mut settings = load_settings()?;
+    match reader {
+        Some(reader) => {
+            ensure_attached(&reader)?;
+            settings.roles.insert(role, reader);
+        }
+        None => {
+            settings.roles.remove(&role);
+        }
+    }
+    save_settings(&settings)?;
+
+    Ok(settings)
+}
+
+/// Sets the Mifare key used to authenticate a sector.
+///
+/// The key is kept in the system keyring and is never sent back to the frontend.
+///
+/// # Arguments
+///
+/// * `sector` - The sector to use the key for. `None` sets the default key, used by every sector without its own key.
+/// * `key_type` - Whether the key is the sector's Key A or Key B.
+/// * `key` - The key written as hex, e.g. "A0A1A2A3A4A5".
+///
+/// # Returns
+///
+/// * `Ok(KeyMapping)` - The updated mapping of sectors to key types.
+/// * `Err(ReaderError)` - If the key is invalid or could not be saved.
+#[tauri::command]
+pub(crate) fn set_sector_key(
+    sector: Option<u8>,
+    key_type: KeyType,
+    key: String,
+) -> Result<KeyMapping, ReaderError> {
+    let sector_key = SectorKey {
+        key_type,
+        key: parse_key(&key)?,
+    };
+
+    let mut keys = KeyStore::load()?;
+    match sector {
+        Some(sector) => {
+            keys.sectors.insert(sector, sector_key);
+        }
+        None => keys.default = Some(sector_key),
+    }
+    keys.save()?;
+
+    Ok(keys.mapping())
+}
+
+/// Removes the key of a sector, so it goes back to the default key.
+///
+/// # Arguments
+///
+/// * `sector` - The sector to remove the key from. `None` resets the default key to the factory key.
+///
+/// # Returns
+///
+/// * `Ok(KeyMapping)` - The updated mapping of sectors to key types.
+/// * `Err(ReaderError)` - If the key store could not be saved.
+#[tauri::command]
+pub(crate) fn remove_sector_key(sector: Option<u8>) -> Result<KeyMapping, ReaderError> {
+    let mut keys = KeyStore::load()?;
+    match sector {
+        Some(sector) => {
+            keys.sectors.remove(&sector);
+        }
+        None => keys.default = None,
+    }
+    keys.save()?;
+
+    Ok(keys.mapping())
+}
+
+/// Gets which key type is used by each sector, without the keys themselves.
+///
+/// # Returns
+///
+/// * `Ok(KeyMapping)` - The default key type and the sectors with their own key.
+/// * `Err(ReaderError)` - If the key store could not be loaded.
+#[tauri::command]
+pub(crate) fn get_key_mapping() -> Result<KeyMapping, ReaderError> {
+    Ok(KeyStore::load()?.mapping())
+}
+
+/// Places or removes the card of the simulated reader, to try the clock-in flow in demo mode.
+///
+/// # Arguments
+///
+/// * `present` - `true` to place the card on the reader, `false` to take it away.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the card was placed or removed.
+/// * `Err(ReaderError)` - If the app isn't running with the simulated reader.
+#[tauri::command]
+pub(crate) fn simulate_card(present: bool) -> Result<(), ReaderError> {
+    if !simulated::is_enabled() {
+        return Err(ReaderError::ReaderNotFound(
+            SIMULATED_READER_NAME.to_string(),
+        ));
+    }
+
+    simulated::set_card_present(present);
+    Ok(())
+}
+
+/// Makes sure the reader is attached and supported before saving it to the settings.
+fn ensure_attached(reader: &str) -> Result<(), ReaderError> {
+    let attached = list_attached_readers()?
+        .into_iter()
+        .find(|info| info.name == reader)
+        .ok_or_else(|| ReaderError::ReaderNotFound(reader.to_string()))?;
+
+    if !attached.supported {
+        return Err(ReaderError::UnsupportedReader(attached.name));
+    }
+
+    Ok(())
+}
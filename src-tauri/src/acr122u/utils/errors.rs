@@ -4,6 +4,8 @@ use pcsc::Error;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
+use crate::acr122u::utils::status_words::{decode_status_word, redact_apdu};
+
 #[derive(Debug)]
 pub enum ReaderError {
     UnsupportedReader(String),
@@ -11,6 +13,47 @@ pub enum ReaderError {
     NoReadersFound,
     CardError(String, Error),
     OperationCancelled(String),
+    Timeout(String),
+    ReaderNotFound(String),
+    RoleNotBound(String),
+    SettingsError(String),
+    KeyStoreError(String),
+    BadgeError(String),
+    BlockWriteFailed(u16, String),
+    PermissionDenied(String),
+    EnrollmentError(String),
+    CardRevoked(String),
+    DumpError(String),
+    /// The data on the card has the magic bytes of a badge record but can't be decoded, see `card::format`.
+    FormatError(String),
+    /// A card or reader command answered with something else than 90 00: the status word and the APDU sent,
+    /// cut down by `redact_apdu`.
+    ApduFailed(u16, Vec<u8>),
+}
+
+impl ReaderError {
+    /// Builds the error of a failed APDU, leaving out the keys and card data it may carry.
+    pub(crate) fn apdu_failed(status_word: u16, apdu: &[u8]) -> ReaderError {
+        ReaderError::ApduFailed(status_word, redact_apdu(apdu))
+    }
+
+    /// Checks if the error means the reader itself is gone (unplugged, or the PC/SC service stopped),
+    /// as opposed to a problem with the card.
+    pub(crate) fn is_reader_lost(&self) -> bool {
+        matches!(
+            self,
+            ReaderError::NoReadersFound
+                | ReaderError::ReaderNotFound(_)
+                | ReaderError::PcscError(
+                    Error::ReaderUnavailable
+                        | Error::UnknownReader
+                        | Error::NoReadersAvailable
+                        | Error::NoService
+                        | Error::ServiceStopped
+                        | Error::InvalidHandle
+                )
+        )
+    }
 }
 
 impl fmt::Display for ReaderError {
@@ -27,6 +70,35 @@ impl fmt::Display for ReaderError {
             ReaderError::OperationCancelled(ref operation) => {
                 write!(f, "Operation cancelled: {}", operation)
             }
+            ReaderError::Timeout(ref operation) => write!(f, "Operation timed out: {}", operation),
+            ReaderError::ReaderNotFound(ref reader) => write!(f, "Reader not found: {}", reader),
+            ReaderError::RoleNotBound(ref role) => {
+                write!(f, "No reader bound to role: {}", role)
+            }
+            ReaderError::SettingsError(ref message) => {
+                write!(f, "Reader settings error: {}", message)
+            }
+            ReaderError::KeyStoreError(ref message) => write!(f, "Key store error: {}", message),
+            ReaderError::BadgeError(ref message) => write!(f, "Badge error: {}", message),
+            ReaderError::BlockWriteFailed(block, ref message) => {
+                write!(f, "Write failed at block {}: {}", block, message)
+            }
+            ReaderError::PermissionDenied(ref permission) => {
+                write!(f, "Permission denied, {} is required", permission)
+            }
+            ReaderError::EnrollmentError(ref message) => {
+                write!(f, "Enrollment error: {}", message)
+            }
+            ReaderError::CardRevoked(ref reason) => write!(f, "Card revoked: {}", reason),
+            ReaderError::DumpError(ref message) => write!(f, "Dump error: {}", message),
+            ReaderError::FormatError(ref message) => write!(f, "Format error: {}", message),
+            ReaderError::ApduFailed(status_word, ref apdu) => write!(
+                f,
+                "APDU {} failed with {:04X}: {}",
+                hex::encode_upper(apdu),
+                status_word,
+                decode_status_word(status_word, apdu).1
+            ),
         }
     }
 }
@@ -73,6 +145,89 @@ impl Serialize for ReaderError {
                 state.serialize_field("operation", operation)?;
                 state.end()
             }
+            ReaderError::Timeout(ref operation) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Timeout")?;
+                state.serialize_field("operation", operation)?;
+                state.end()
+            }
+            ReaderError::ReaderNotFound(ref reader) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Reader Not Found")?;
+                state.serialize_field("reader", reader)?;
+                state.end()
+            }
+            ReaderError::RoleNotBound(ref role) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Role Not Bound")?;
+                state.serialize_field("role", role)?;
+                state.end()
+            }
+            ReaderError::SettingsError(ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Settings Error")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::KeyStoreError(ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Key Store Error")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::BadgeError(ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Badge Error")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::BlockWriteFailed(block, ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 3)?;
+                state.serialize_field("error", "Block Write Failed")?;
+                state.serialize_field("block", &block)?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::PermissionDenied(ref permission) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Permission Denied")?;
+                state.serialize_field("permission", permission)?;
+                state.end()
+            }
+            ReaderError::EnrollmentError(ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Enrollment Error")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::CardRevoked(ref reason) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Card Revoked")?;
+                state.serialize_field("reason", reason)?;
+                state.end()
+            }
+            ReaderError::DumpError(ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Dump Error")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::FormatError(ref message) => {
+                let mut state = serializer.serialize_struct("ReaderError", 2)?;
+                state.serialize_field("error", "Format Error")?;
+                state.serialize_field("message", message)?;
+                state.end()
+            }
+            ReaderError::ApduFailed(status_word, ref apdu) => {
+                let (code, meaning) = decode_status_word(status_word, apdu);
+                let mut state = serializer.serialize_struct("ReaderError", 5)?;
+                state.serialize_field("error", "APDU Failed")?;
+                state.serialize_field("code", code)?;
+                state.serialize_field("status_word", &format!("{:04X}", status_word))?;
+                state.serialize_field("meaning", meaning)?;
+                state.serialize_field("apdu", &hex::encode_upper(apdu))?;
+                state.end()
+            }
         }
     }
 }
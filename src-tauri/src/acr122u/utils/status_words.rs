@@ -0,0 +1,114 @@
+/// Instructions whose data must never end up in an error: the keys of LOAD AUTHENTICATION KEYS
+/// and the card data of UPDATE BINARY. Only their header is kept.
+const REDACTED_INSTRUCTIONS: [u8; 2] = [0x82, 0xd6];
+
+/// Decodes a status word into a stable code for the frontend and a meaning for people.
+///
+/// The codes never change once released, the frontend matches on them.
+/// 63 00 is the catch-all failure of the PC/SC pseudo-APDUs, so it's only called an authentication failure
+/// when it answers GENERAL AUTHENTICATE (FF 86) or the obsolete AUTHENTICATE (FF 88).
+///
+/// # Arguments
+///
+/// * `status_word` - The SW1 SW2 of the response.
+/// * `apdu` - The APDU the card or reader answered.
+///
+/// # Returns
+///
+/// * `(&str, &str)` - The code, e.g. "AUTHENTICATION_FAILED", and what it means.
+pub(crate) fn decode_status_word(status_word: u16, apdu: &[u8]) -> (&'static str, &'static str) {
+    let is_authentication = matches!(apdu.get(1), Some(0x86 | 0x88));
+
+    match status_word {
+        0x9000 => ("SUCCESS", "The command succeeded"),
+        0x6300 if is_authentication => (
+            "AUTHENTICATION_FAILED",
+            "The card rejected the key, or the block doesn't exist",
+        ),
+        0x6300 => ("OPERATION_FAILED", "The operation failed"),
+        0x6281 => (
+            "DATA_CORRUPTED",
+            "Part of the returned data may be corrupted",
+        ),
+        0x6282 => (
+            "END_OF_DATA",
+            "The end of the card was reached before reading the requested length",
+        ),
+        0x6700 => ("WRONG_LENGTH", "The length of the command is wrong"),
+        0x6800 => (
+            "CLASS_FUNCTION_NOT_SUPPORTED",
+            "A function of the class byte is not supported",
+        ),
+        0x6981 => (
+            "COMMAND_INCOMPATIBLE",
+            "The command is incompatible with the card",
+        ),
+        0x6982 => (
+            "SECURITY_STATUS_NOT_SATISFIED",
+            "The block needs to be authenticated first, or the key doesn't allow it",
+        ),
+        0x6983 => ("AUTHENTICATION_BLOCKED", "The authentication is blocked"),
+        0x6986 => ("COMMAND_NOT_ALLOWED", "The command is not allowed"),
+        0x6a81 => (
+            "FUNCTION_NOT_SUPPORTED",
+            "The reader doesn't support this function",
+        ),
+        0x6a82 => ("NOT_FOUND", "The block or page doesn't exist on the card"),
+        0x6b00 => (
+            "WRONG_PARAMETERS",
+            "The parameters P1 P2 are wrong, e.g. the block is out of range",
+        ),
+        0x6c00..=0x6cff => ("WRONG_EXPECTED_LENGTH", "The expected length Le is wrong"),
+        0x6d00 => (
+            "INSTRUCTION_NOT_SUPPORTED",
+            "The reader doesn't know this instruction",
+        ),
+        0x6e00 => (
+            "CLASS_NOT_SUPPORTED",
+            "The reader doesn't know this class byte",
+        ),
+        0x6f00 => ("UNKNOWN_ERROR", "The card failed without saying why"),
+        _ => ("UNKNOWN_STATUS", "Unknown status word"),
+    }
+}
+
+/// Keeps the part of the APDU that's safe to log and send to the frontend.
+///
+/// Commands carrying keys or card data are cut down to their header (CLA INS P1 P2 Lc).
+pub(crate) fn redact_apdu(apdu: &[u8]) -> Vec<u8> {
+    match apdu.get(1) {
+        Some(ins) if REDACTED_INSTRUCTIONS.contains(ins) => apdu.iter().take(5).copied().collect(),
+        _ => apdu.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_status_word() {
+        let authenticate = [0xff, 0x86, 0x00, 0x00, 0x05, 0x01, 0x00, 0x07, 0x60, 0x00];
+        let read = [0xff, 0xb0, 0x00, 0x04, 0x10];
+
+        assert_eq!(
+            decode_status_word(0x6300, &authenticate).0,
+            "AUTHENTICATION_FAILED"
+        );
+        assert_eq!(decode_status_word(0x6300, &read).0, "OPERATION_FAILED");
+        assert_eq!(decode_status_word(0x6a82, &read).0, "NOT_FOUND");
+        assert_eq!(decode_status_word(0x6c10, &read).0, "WRONG_EXPECTED_LENGTH");
+        assert_eq!(decode_status_word(0x1234, &read).0, "UNKNOWN_STATUS");
+    }
+
+    #[test]
+    fn test_redact_apdu() {
+        let load_key = [
+            0xff, 0x82, 0x00, 0x00, 0x06, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5,
+        ];
+        assert_eq!(redact_apdu(&load_key), vec![0xff, 0x82, 0x00, 0x00, 0x06]);
+
+        let read = [0xff, 0xb0, 0x00, 0x04, 0x10];
+        assert_eq!(redact_apdu(&read), read.to_vec());
+    }
+}
@@ -0,0 +1,926 @@
+use std::collections::HashSet;
+use std::iter;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use pcsc::Error;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::acr122u::card::badge::{
+    check_counter, current_counter, load_badge_key, read_badge_data, Badge, BADGE_BLOCK,
+};
+use crate::acr122u::card::format::{is_record, BadgeRecord};
+use crate::acr122u::card::revocation::check_revoked;
+use crate::acr122u::card::utils::keys::KeyStore;
+use crate::acr122u::card::utils::layout::CardType;
+use crate::acr122u::card::write::write_data;
+use crate::acr122u::driver::CardReader;
+use crate::acr122u::reader::feedback::{FeedbackSettings, PunchOutcome};
+use crate::acr122u::reader::monitor::wait_for_reader;
+use crate::acr122u::reader::queue::{CancelToken, Operation, Priority, ReaderQueue};
+use crate::acr122u::reader::settings::load_settings;
+use crate::acr122u::source::SourceKind;
+use crate::acr122u::utils::errors::ReaderError;
+use crate::cache::get::{get_cache, normalize_uid};
+use crate::cache::update::{set_user_card, CardRecord};
+use crate::database::connect::SharedDatabases;
+use crate::database::schemas::user_schema::UserExternal;
+use crate::misc::card_pin::require_pin;
+use crate::misc::policy::load_policy;
+
+/// How long to wait before looking for the reader again after it goes away, if the monitor doesn't see it come back first.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// How often the watcher looks for roles bound since it started.
+const ROLES_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A card tapped again within this time is flagged as a duplicate instead of a new punch.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Payload of the `card:tapped` event.
+///
+/// * `uid` - The card UID as an uppercase hex string.
+/// * `user_id` - The id of the employee, from a verified badge or the UID the card is enrolled to, if any.
+/// * `payload` - The employee id in the badge record, or the bare id of older cards, on cards without a signed badge.
+///   It's never trusted to identify anyone.
+/// * `migrated` - The bare id on the card was just upgraded to a signed badge, and the card enrolled to the employee.
+/// * `duplicate` - The card was already tapped a moment ago, the frontend shouldn't register another punch.
+/// * `source` - Where the badge came from, a tap on a reader, a keyboard wedge or typed in by hand.
+/// * `reader` - The name of the reader the card was tapped on, or of the source.
+/// * `timestamp` - When the card was tapped, in RFC 3339.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct CardTapEvent {
+    pub(crate) uid: String,
+    pub(crate) user_id: Option<String>,
+    pub(crate) payload: Option<String>,
+    pub(crate) migrated: bool,
+    pub(crate) duplicate: bool,
+    pub(crate) source: SourceKind,
+    pub(crate) reader: String,
+    pub(crate) timestamp: String,
+}
+
+/// Payload of the `card:rejected` event, sent instead of `card:tapped` for forged, copied or revoked badges.
+///
+/// * `uid` - The card UID as an uppercase hex string.
+/// * `reason` - Why the card was rejected.
+/// * `revoked` - The card is in the revocation list, the kiosk should ask for it to be handed in.
+/// * `source` - Where the badge came from.
+/// * `reader` - The name of the reader the card was tapped on, or of the source.
+/// * `timestamp` - When the card was tapped, in RFC 3339.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct CardRejectedEvent {
+    pub(crate) uid: String,
+    pub(crate) reason: String,
+    pub(crate) revoked: bool,
+    pub(crate) source: SourceKind,
+    pub(crate) reader: String,
+    pub(crate) timestamp: String,
+}
+
+enum Tap {
+    Accepted(CardTapEvent),
+    /// Accepted, but held until the employee types the PIN, see `require_pin`.
+    PendingPin(CardTapEvent),
+    Rejected(CardRejectedEvent),
+}
+
+/// The last punch of every badge source, shared so a badge can't punch twice in a row through two sources.
+#[derive(Default)]
+pub(crate) struct TapState {
+    last_punch: Mutex<Option<(String, Instant)>>,
+}
+
+impl TapState {
+    /// Records the punch of a tap that waited for its PIN, once the PIN checks out.
+    pub(crate) fn punched(&self, uid: &str, now: Instant) {
+        *self.last_punch.lock().unwrap() = Some((uid.to_string(), now));
+    }
+}
+
+/// What the watcher needs to verify badges and give feedback, loaded again on every tap so changes apply right away.
+struct TapContext {
+    keys: KeyStore,
+    badge_key: Option<Vec<u8>>,
+    /// The local sled database, with the badge counters and the revoked cards.
+    local_db: Option<sled::Db>,
+    feedback: FeedbackSettings,
+    /// Whether the site asks for the PIN after the badge.
+    card_pin: bool,
+    /// The cached users, to find who a card is enrolled to.
+    users: Vec<UserExternal>,
+}
+
+impl TapContext {
+    fn load(local_db: Option<sled::Db>) -> TapContext {
+        TapContext {
+            keys: KeyStore::load().unwrap_or_default(),
+            badge_key: load_badge_key().ok(),
+            local_db,
+            feedback: load_settings().unwrap_or_default().feedback,
+            card_pin: card_pin_enabled(),
+            users: get_cache().into_values().collect(),
+        }
+    }
+
+    /// Finds the user the card is enrolled to.
+    fn enrolled(&self, uid_hex: &str) -> Option<&UserExternal> {
+        self.users
+            .iter()
+            .find(|user| user.card_uid.as_deref().map(normalize_uid).as_deref() == Some(uid_hex))
+    }
+
+    /// Checks whether the employee was ever issued a signed badge.
+    ///
+    /// From then on the card has to carry it, the enrolled UID alone doesn't identify them anymore.
+    fn has_badge(&self, user: &UserExternal) -> Result<bool, ReaderError> {
+        if user.card_serial.is_some() {
+            return Ok(true);
+        }
+
+        match &self.local_db {
+            Some(local_db) => Ok(current_counter(local_db, &user.id)? > 0),
+            None => Ok(false),
+        }
+    }
+}
+
+/// Checks whether the site asks for the PIN after the badge.
+///
+/// A policy that can't be read asks for it, so a broken policy file never turns the PIN off.
+fn card_pin_enabled() -> bool {
+    match load_policy() {
+        Ok(policy) => policy.card_pin.enabled,
+        Err(e) => {
+            println!(
+                "Card watcher: couldn't load the site policy, asking for the PIN: {}",
+                e
+            );
+            true
+        }
+    }
+}
+
+/// Decodes the data read from the card, dropping the NUL padding added when writing.
+fn decode_payload(data: &[u8]) -> Option<String> {
+    let data = String::from_utf8(data.to_vec()).ok()?;
+    let data = data.trim_end_matches('\0').to_string();
+
+    if data.is_empty() {
+        None
+    } else {
+        Some(data)
+    }
+}
+
+/// Gets the employee id out of the data of a card without a signed badge.
+///
+/// # Returns
+///
+/// * `Some((id, legacy))` - The id, and whether it's a bare id written before badge records.
+/// * `None` - If the card holds a signed badge, nothing, or a record that's corrupted.
+fn decode_unsigned(data: &[u8]) -> Option<(String, bool)> {
+    if Badge::is_badge(data) {
+        return None;
+    }
+
+    if is_record(data) {
+        return match BadgeRecord::decode(data) {
+            Ok(record) => Some((record.employee_id, false)),
+            Err(e) => {
+                println!("Card watcher: {}", e);
+                None
+            }
+        };
+    }
+
+    data.get(..16)
+        .and_then(decode_payload)
+        .map(|payload| (payload, true))
+}
+
+/// Finds the employee the bare id of a card written before badge records can be upgraded to.
+///
+/// The id has to name a cached employee, the card can't be enrolled to anyone else,
+/// and the employee can't have another card enrolled or a badge issued already,
+/// so a card with someone's id written on it can't take over an employee who already has a card.
+///
+/// # Returns
+///
+/// * `Ok(&UserExternal)` - The employee to write the badge for.
+/// * `Err(String)` - Why the card can't be upgraded, for the log.
+fn legacy_owner<'a>(
+    context: &'a TapContext,
+    payload: &str,
+    uid_hex: &str,
+) -> Result<&'a UserExternal, String> {
+    let user = context
+        .users
+        .iter()
+        .find(|user| user.id == payload)
+        .ok_or("no employee has the id on the card")?;
+
+    if context
+        .enrolled(uid_hex)
+        .is_some_and(|owner| owner.id != user.id)
+    {
+        return Err("the card is enrolled to another employee".to_string());
+    }
+    if user
+        .card_uid
+        .as_deref()
+        .is_some_and(|card_uid| normalize_uid(card_uid) != uid_hex)
+    {
+        return Err("the employee has another card enrolled".to_string());
+    }
+    if context.has_badge(user).map_err(|e| e.to_string())? {
+        return Err("the employee was already issued a badge".to_string());
+    }
+
+    Ok(user)
+}
+
+/// Rewrites the bare id of a card written before badge records as a signed badge, in place.
+///
+/// Only done for the employee found by `legacy_owner`,
+/// so a random card that happens to hold some text is never written to.
+/// The UID is enrolled to the employee afterwards, see `enroll_migrated`.
+/// The badge gets the last counter issued to the employee, so it doesn't turn down a badge they already have.
+fn migrate_legacy(
+    reader: &mut dyn CardReader,
+    card_type: CardType,
+    context: &TapContext,
+    employee_id: &str,
+    uid: &[u8],
+    cancel_flag: &AtomicBool,
+) -> Result<(), ReaderError> {
+    let badge_key = context
+        .badge_key
+        .as_deref()
+        .ok_or_else(|| ReaderError::BadgeError("Badge key unavailable".to_string()))?;
+    let local_db = context
+        .local_db
+        .as_ref()
+        .ok_or_else(|| ReaderError::BadgeError("Local database unavailable".to_string()))?;
+
+    let badge = Badge {
+        employee_id: employee_id.to_string(),
+        uid: uid.to_vec(),
+        counter: current_counter(local_db, employee_id)?,
+    };
+    let data = badge.sign(badge_key)?;
+    write_data(
+        reader,
+        &context.keys,
+        card_type,
+        BADGE_BLOCK,
+        &data,
+        cancel_flag,
+    )?;
+    Ok(())
+}
+
+/// Checks the badge on the card and finds out who it belongs to.
+///
+/// Revoked cards are turned down before anything else.
+/// Cards without a badge are identified by their enrolled UID only, as long as the employee never got a badge.
+/// Otherwise a card with its badge wiped, or a copy of the UID alone, would get past the signature.
+fn identify(context: &TapContext, data: &[u8], uid: &[u8]) -> Result<Option<String>, ReaderError> {
+    let uid_hex = hex::encode_upper(uid);
+    if let Some(local_db) = &context.local_db {
+        check_revoked(local_db, &uid_hex)?;
+    }
+
+    let enrolled = context.enrolled(&uid_hex);
+
+    if !Badge::is_badge(data) {
+        return match enrolled {
+            Some(user) if context.has_badge(user)? => Err(ReaderError::BadgeError(
+                "The card has no badge, but the employee was issued one".to_string(),
+            )),
+            _ => Ok(enrolled.map(|user| user.id.clone())),
+        };
+    }
+
+    let badge_key = context
+        .badge_key
+        .as_deref()
+        .ok_or_else(|| ReaderError::BadgeError("Badge key unavailable".to_string()))?;
+    let badge = Badge::verify(data, uid, badge_key)?;
+
+    if let Some(local_db) = &context.local_db {
+        check_counter(local_db, &badge)?;
+    }
+
+    match enrolled {
+        Some(enrolled) if enrolled.id != badge.employee_id => Err(ReaderError::BadgeError(
+            "The card is enrolled to another employee".to_string(),
+        )),
+        _ => Ok(Some(badge.employee_id)),
+    }
+}
+
+/// Waits for a card to be tapped, reads its UID and badge and checks who it belongs to.
+///
+/// Cards still holding a bare id are upgraded to a signed badge once they're identified,
+/// the ones that can't be are logged with the reason.
+/// The card is left connected, so the reader can still play the feedback of the tap.
+fn wait_for_tap(
+    reader: &mut dyn CardReader,
+    context: &TapContext,
+    cancel_flag: &AtomicBool,
+) -> Result<Tap, ReaderError> {
+    let atr = reader.connect(cancel_flag, None)?;
+
+    let uid = reader.get_uid()?;
+
+    // The badge is optional, cards that were never written to still have a UID
+    let card_type = CardType::detect(reader, &atr).ok();
+    let data = card_type
+        .map(|card_type| read_badge_data(reader, card_type, &context.keys, cancel_flag))
+        .and_then(Result::ok)
+        .unwrap_or_default();
+    let unsigned = decode_unsigned(&data);
+
+    let timestamp = chrono::Utc::now().to_rfc3339();
+
+    match identify(context, &data, &uid) {
+        Ok(mut user_id) => {
+            let mut migrated = false;
+            if let (Some(card_type), Some((payload, true))) = (card_type, &unsigned) {
+                let uid_hex = hex::encode_upper(&uid);
+                let upgraded = legacy_owner(context, payload, &uid_hex).and_then(|user| {
+                    migrate_legacy(reader, card_type, context, &user.id, &uid, cancel_flag)
+                        .map_err(|e| e.to_string())
+                });
+
+                match upgraded {
+                    Ok(()) => {
+                        // The signed badge on the card now vouches for the id
+                        user_id = Some(payload.clone());
+                        migrated = true;
+                    }
+                    Err(reason) => println!(
+                        "Card watcher: not upgrading the badge of card {}, {}",
+                        uid_hex, reason
+                    ),
+                }
+            }
+
+            Ok(Tap::Accepted(CardTapEvent {
+                uid: hex::encode_upper(uid),
+                user_id,
+                payload: unsigned.map(|(payload, _)| payload),
+                migrated,
+                duplicate: false,
+                source: SourceKind::Nfc,
+                reader: reader.name().to_string(),
+                timestamp,
+            }))
+        }
+        Err(e) => Ok(Tap::Rejected(CardRejectedEvent {
+            uid: hex::encode_upper(uid),
+            reason: e.to_string(),
+            revoked: matches!(e, ReaderError::CardRevoked(_)),
+            source: SourceKind::Nfc,
+            reader: reader.name().to_string(),
+            timestamp,
+        })),
+    }
+}
+
+/// Enrolls the UID of a card that was just upgraded from a bare id to the employee, like `enroll_card` does.
+///
+/// The signed badge already identifies the employee, so the card keeps working if this fails, e.g. while offline.
+fn enroll_migrated(app: &AppHandle, local_db: Option<&sled::Db>, event: &CardTapEvent) {
+    let Some(user_id) = &event.user_id else {
+        return;
+    };
+
+    let card = CardRecord {
+        uid: Some(event.uid.clone()),
+        issued_at: Some(event.timestamp.clone()),
+        serial: local_db.and_then(|local_db| current_counter(local_db, user_id).ok()),
+    };
+    if let Err(e) = tauri::async_runtime::block_on(set_user_card(app.clone(), user_id, card)) {
+        println!(
+            "Card watcher: failed to enroll the upgraded card {}: {}",
+            event.uid, e
+        );
+    }
+}
+
+/// Works out the feedback of the tap, and flags the card that just punched as a duplicate if it's tapped again.
+///
+/// Only accepted punches start the duplicate window, a duplicate doesn't extend it.
+/// When the site asks for the PIN after the badge, the tap is held for it and only starts the window
+/// once the PIN checks out, see `confirm_card_pin`.
+fn punch_outcome(
+    tap: &mut Tap,
+    last_punch: &mut Option<(String, Instant)>,
+    now: Instant,
+    card_pin: bool,
+) -> PunchOutcome {
+    let event = match tap {
+        Tap::Accepted(event) if event.user_id.is_some() => event,
+        // Unknown cards don't punch anyone in either
+        _ => return PunchOutcome::Rejected,
+    };
+
+    if let Some((uid, punched_at)) = last_punch {
+        if *uid == event.uid && now.duration_since(*punched_at) < DUPLICATE_WINDOW {
+            event.duplicate = true;
+            return PunchOutcome::Duplicate;
+        }
+    }
+
+    if card_pin {
+        *tap = Tap::PendingPin(event.clone());
+        return PunchOutcome::PendingPin;
+    }
+
+    *last_punch = Some((event.uid.clone(), now));
+    PunchOutcome::Accepted
+}
+
+/// Plays the pattern of the outcome on the reader, readers without LED and buzzer control stay quiet.
+fn play_feedback(reader: &mut dyn CardReader, settings: &FeedbackSettings, outcome: PunchOutcome) {
+    let Some(pattern) = settings.pattern(outcome) else {
+        return;
+    };
+
+    match reader.play_feedback(pattern) {
+        Ok(()) | Err(ReaderError::CardError(_, Error::UnsupportedFeature)) => {}
+        Err(e) => println!("Card watcher: feedback failed: {}", e),
+    }
+}
+
+/// Waits for the card left on the reader to be taken away, then for the next tap, and plays its feedback.
+///
+/// A card still on the reader was already handled, by the last tap or by the operation that ran before,
+/// so it only counts as a tap once it's taken away and tapped again.
+fn watch_tap(
+    operation: Operation<'_>,
+    context: &TapContext,
+    state: &TapState,
+) -> Result<Tap, ReaderError> {
+    operation.driver.wait_for_removal(operation.token.flag())?;
+
+    let mut tap = wait_for_tap(operation.driver, context, operation.token.flag());
+    let outcome = match &mut tap {
+        Ok(tap) => punch_outcome(
+            tap,
+            &mut state.last_punch.lock().unwrap(),
+            Instant::now(),
+            context.card_pin,
+        ),
+        Err(_) => PunchOutcome::Rejected,
+    };
+
+    // Played while the card is still connected, the ACR122U needs it to take the command
+    if !matches!(&tap, Err(e) if e.is_reader_lost() || matches!(e, ReaderError::OperationCancelled(_)))
+    {
+        play_feedback(operation.driver, &context.feedback, outcome);
+    }
+
+    tap
+}
+
+/// Emits the event of the tap, `card:tapped` or `card:rejected`.
+///
+/// A tap waiting for its PIN is held until it's typed instead, see `card_pin`.
+fn emit_tap(app: &AppHandle, tap: Tap) {
+    let emitted = match tap {
+        Tap::Accepted(event) => app.emit("card:tapped", event),
+        Tap::PendingPin(event) => {
+            require_pin(app, event);
+            Ok(())
+        }
+        Tap::Rejected(event) => app.emit("card:rejected", event),
+    };
+    if let Err(e) = emitted {
+        println!("Card watcher: failed to emit event: {}", e);
+    }
+}
+
+/// Identifies a badge from a source that only gives its number, and emits its event like a tap on the reader.
+///
+/// There's no card to read the badge from, so it's identified by the UID it's enrolled to,
+/// like a card without a badge. Revoked cards and duplicates are flagged the same way,
+/// and employees who were issued a badge are turned down since the number alone can't prove it.
+///
+/// # Arguments
+///
+/// * `app` - The app handle, to emit the event.
+/// * `source` - Where the badge number came from.
+/// * `uid` - The card UID, see `NumberFormat::parse`.
+pub(crate) async fn submit_badge(app: &AppHandle, source: SourceKind, uid: &[u8]) {
+    let local_db = match &app.state::<SharedDatabases>().sled_db {
+        Some(db) => Some(db.lock().await.clone()),
+        None => None,
+    };
+    let context = TapContext::load(local_db);
+
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    let mut tap = match identify(&context, &[], uid) {
+        Ok(user_id) => Tap::Accepted(CardTapEvent {
+            uid: hex::encode_upper(uid),
+            user_id,
+            payload: None,
+            migrated: false,
+            duplicate: false,
+            source,
+            reader: source.name().to_string(),
+            timestamp,
+        }),
+        Err(e) => Tap::Rejected(CardRejectedEvent {
+            uid: hex::encode_upper(uid),
+            reason: e.to_string(),
+            revoked: matches!(e, ReaderError::CardRevoked(_)),
+            source,
+            reader: source.name().to_string(),
+            timestamp,
+        }),
+    };
+
+    let state = app.state::<Arc<TapState>>();
+    punch_outcome(
+        &mut tap,
+        &mut state.last_punch.lock().unwrap(),
+        Instant::now(),
+        context.card_pin,
+    );
+    emit_tap(app, tap);
+}
+
+/// Watches the readers for card taps and emits a `card:tapped` event for each one,
+/// or `card:rejected` when the card is revoked or the badge on it doesn't check out.
+///
+/// The selected reader and the reader of every role each get a watcher of their own, on a thread of its own.
+/// A reader bound to several roles, or to a role and selected, is watched once, a second watcher would only
+/// cancel the wait of the first one on the queue of the reader.
+/// Roles bound later are picked up from the settings, the watcher of a role stops once it's unbound.
+/// This runs for the whole life of the app, so it should be spawned on a blocking thread.
+pub(crate) fn watch_cards(app: AppHandle) {
+    let queue = app.state::<Arc<ReaderQueue>>().inner().clone();
+    // The names of the readers with a watcher
+    let watching: Arc<Mutex<HashSet<String>>> = Arc::default();
+
+    loop {
+        let roles = load_settings().unwrap_or_default().roles.into_keys();
+
+        for role in iter::once(None).chain(roles.map(Some)) {
+            // A reader that can't be found yet is tried again on the next check
+            let Ok(reader) = queue.resolve(role.as_deref()) else {
+                continue;
+            };
+            if !watching.lock().unwrap().insert(reader.clone()) {
+                continue;
+            }
+
+            let app = app.clone();
+            let watching = watching.clone();
+            std::thread::Builder::new()
+                .name(format!(
+                    "card-watcher {}",
+                    role.as_deref().unwrap_or("selected")
+                ))
+                .spawn(move || {
+                    watch_reader(&app, role, &reader);
+                    watching.lock().unwrap().remove(&reader);
+                })
+                .expect("Couldn't start the card watcher");
+        }
+
+        std::thread::sleep(ROLES_CHECK_INTERVAL);
+    }
+}
+
+/// Watches the reader bound to the role, or the selected reader, until the role is unbound
+/// or bound to another reader than `reader`, the one it was started for.
+///
+/// The reader blinks and beeps with the pattern of each outcome, see `FeedbackSettings`.
+/// Each wait for a tap runs on the queue of the reader with the `Idle` priority, so any other card operation
+/// on it cancels the wait and runs first, the watcher then queues itself again.
+/// If the reader is unplugged, it waits for the reader monitor to see it come back and carries on.
+fn watch_reader(app: &AppHandle, role: Option<String>, reader: &str) {
+    let queue = app.state::<Arc<ReaderQueue>>().inner().clone();
+    let tap_state = app.state::<Arc<TapState>>().inner().clone();
+
+    // The sled handle is cheap to clone, so the lock is only held to get it
+    let local_db = app
+        .state::<SharedDatabases>()
+        .sled_db
+        .as_ref()
+        .map(|db| db.blocking_lock().clone());
+
+    loop {
+        // `watch_cards` starts a watcher for the reader the role points to now, if nobody watches it yet
+        if matches!(queue.resolve(role.as_deref()), Ok(current) if current != reader) {
+            println!(
+                "Card watcher: the reader of {} changed, stopping.",
+                role.as_deref().unwrap_or("the selection")
+            );
+            return;
+        }
+
+        // Reloaded on every tap so key changes apply without restarting the watcher
+        let context = TapContext::load(local_db.clone());
+
+        let state = tap_state.clone();
+        let tap = queue.run_blocking(
+            Priority::Idle,
+            role.clone(),
+            CancelToken::new(),
+            move |operation| watch_tap(operation, &context, &state),
+        );
+
+        match tap {
+            Ok(tap) => {
+                if let Tap::Accepted(event) | Tap::PendingPin(event) = &tap {
+                    if event.migrated {
+                        enroll_migrated(app, local_db.as_ref(), event);
+                    }
+                }
+                emit_tap(app, tap)
+            }
+            // Made way for another operation
+            Err(ReaderError::OperationCancelled(_)) => {}
+            Err(ReaderError::RoleNotBound(role)) => {
+                println!("Card watcher: the {} role was unbound, stopping.", role);
+                return;
+            }
+            Err(e) if e.is_reader_lost() || matches!(e, ReaderError::UnsupportedReader(_)) => {
+                println!("Card watcher: {}, waiting for the reader to come back.", e);
+                wait_for_reader(RECONNECT_DELAY);
+            }
+            Err(e) => {
+                println!("Card watcher: {}", e);
+                // Keeps a card that fails every read from spinning the watcher
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::card::badge::save_counter;
+
+    #[test]
+    fn test_decode_payload() {
+        let mut data = b"hOtB6pOx".to_vec();
+        data.resize(16, 0);
+
+        assert_eq!(decode_payload(&data), Some("hOtB6pOx".to_string()));
+        assert_eq!(decode_payload(&[0; 16]), None);
+        assert_eq!(decode_payload(&[0xff, 0xfe, 0x00]), None);
+    }
+
+    #[test]
+    fn test_decode_unsigned() {
+        let mut legacy = b"hOtB6pOxiL2IQPYs".to_vec();
+        legacy.resize(48, 0);
+        assert_eq!(
+            decode_unsigned(&legacy),
+            Some(("hOtB6pOxiL2IQPYs".to_string(), true))
+        );
+
+        let mut record = BadgeRecord::new("hOtB6pOxiL2IQPYs").encode().unwrap();
+        record.resize(48, 0);
+        assert_eq!(
+            decode_unsigned(&record),
+            Some(("hOtB6pOxiL2IQPYs".to_string(), false))
+        );
+
+        // Cut short by a card pulled away mid-write
+        record[20..].fill(0);
+        assert_eq!(decode_unsigned(&record), None);
+        assert_eq!(decode_unsigned(&[0; 48]), None);
+    }
+
+    fn test_context(users: Vec<UserExternal>) -> TapContext {
+        TapContext {
+            keys: KeyStore::default(),
+            badge_key: Some(vec![0x42; 32]),
+            local_db: Some(sled::Config::new().temporary(true).open().unwrap()),
+            feedback: FeedbackSettings::default(),
+            card_pin: false,
+            users,
+        }
+    }
+
+    fn test_user(id: &str, card_uid: Option<&str>, card_serial: Option<u32>) -> UserExternal {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": "Test User",
+            "role": "Employee",
+            "card_uid": card_uid,
+            "card_serial": card_serial,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_identify_without_badge() {
+        let context = test_context(vec![
+            test_user("hOtB6pOxiL2IQPYs", Some("04A23B1C"), Some(1)),
+            test_user("iL2IQPYshOtB6pOx", Some("04C0FFEE"), None),
+        ]);
+        let blank = [0u8; 64];
+
+        // Badge issued, but block 4 was wiped or only the UID was copied
+        assert!(matches!(
+            identify(&context, &blank, &[0x04, 0xa2, 0x3b, 0x1c]),
+            Err(ReaderError::BadgeError(_))
+        ));
+
+        // Never got a badge, the enrolled UID is enough
+        assert_eq!(
+            identify(&context, &blank, &[0x04, 0xc0, 0xff, 0xee]).unwrap(),
+            Some("iL2IQPYshOtB6pOx".to_string())
+        );
+
+        // Until a badge is issued to them
+        save_counter(context.local_db.as_ref().unwrap(), "iL2IQPYshOtB6pOx", 1).unwrap();
+        assert!(identify(&context, &blank, &[0x04, 0xc0, 0xff, 0xee]).is_err());
+
+        // Signed badges still go through
+        let badge = Badge {
+            employee_id: "hOtB6pOxiL2IQPYs".to_string(),
+            uid: vec![0x04, 0xa2, 0x3b, 0x1c],
+            counter: 1,
+        };
+        let data = badge.sign(&[0x42; 32]).unwrap();
+        assert_eq!(
+            identify(&context, &data, &[0x04, 0xa2, 0x3b, 0x1c]).unwrap(),
+            Some("hOtB6pOxiL2IQPYs".to_string())
+        );
+    }
+
+    #[test]
+    fn test_migrate_legacy() {
+        use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
+
+        let uid = [0x04, 0xa2, 0x3b, 0x1c];
+        let mut reader = SimulatedReader::new(VirtualCard::classic_1k(uid));
+        let cancel_flag = AtomicBool::new(false);
+        let context = test_context(Vec::new());
+        let local_db = context.local_db.clone().unwrap();
+        save_counter(&local_db, "hOtB6pOxiL2IQPYs", 2).unwrap();
+
+        let atr = reader.connect(&cancel_flag, None).unwrap();
+        let card_type = CardType::detect(&mut reader, &atr).unwrap();
+        write_data(
+            &mut reader,
+            &context.keys,
+            card_type,
+            BADGE_BLOCK,
+            b"hOtB6pOxiL2IQPYs",
+            &cancel_flag,
+        )
+        .unwrap();
+
+        migrate_legacy(
+            &mut reader,
+            card_type,
+            &context,
+            "hOtB6pOxiL2IQPYs",
+            &uid,
+            &cancel_flag,
+        )
+        .unwrap();
+
+        // Signed with the current counter, so it still checks out
+        let data = read_badge_data(&mut reader, card_type, &context.keys, &cancel_flag).unwrap();
+        assert_eq!(decode_unsigned(&data), None);
+        let badge = Badge::verify(&data, &uid, &[0x42; 32]).unwrap();
+        assert_eq!(badge.employee_id, "hOtB6pOxiL2IQPYs");
+        assert_eq!(badge.counter, 2);
+        assert!(check_counter(&local_db, &badge).is_ok());
+    }
+
+    #[test]
+    fn test_wait_for_legacy_tap() {
+        use crate::acr122u::driver::simulated::{SimulatedReader, VirtualCard};
+
+        let uid = [0x04, 0xa2, 0x3b, 0x1c];
+        let cancel_flag = AtomicBool::new(false);
+        let legacy_card = || {
+            let mut reader = SimulatedReader::new(VirtualCard::classic_1k(uid));
+            let atr = reader.connect(&cancel_flag, None).unwrap();
+            let card_type = CardType::detect(&mut reader, &atr).unwrap();
+            write_data(
+                &mut reader,
+                &KeyStore::default(),
+                card_type,
+                BADGE_BLOCK,
+                b"hOtB6pOxiL2IQPYs",
+                &cancel_flag,
+            )
+            .unwrap();
+            reader.disconnect();
+            reader
+        };
+
+        // Written before UIDs were enrolled, the id on the card is all there is
+        let context = test_context(vec![test_user("hOtB6pOxiL2IQPYs", None, None)]);
+        let mut reader = legacy_card();
+        let Ok(Tap::Accepted(event)) = wait_for_tap(&mut reader, &context, &cancel_flag) else {
+            panic!("The legacy card wasn't accepted");
+        };
+        assert!(event.migrated);
+        assert_eq!(event.user_id.as_deref(), Some("hOtB6pOxiL2IQPYs"));
+
+        let card_type = CardType::MifareClassic1K;
+        let data = read_badge_data(&mut reader, card_type, &context.keys, &cancel_flag).unwrap();
+        assert_eq!(
+            Badge::verify(&data, &uid, &[0x42; 32]).unwrap().employee_id,
+            "hOtB6pOxiL2IQPYs"
+        );
+
+        // The id doesn't name anyone, the card is left as it is
+        let context = test_context(vec![test_user("iL2IQPYshOtB6pOx", None, None)]);
+        let mut reader = legacy_card();
+        let Ok(Tap::Accepted(event)) = wait_for_tap(&mut reader, &context, &cancel_flag) else {
+            panic!("The legacy card wasn't accepted");
+        };
+        assert!(!event.migrated);
+        assert_eq!(event.user_id, None);
+        assert_eq!(event.payload.as_deref(), Some("hOtB6pOxiL2IQPYs"));
+
+        // The employee already has another card
+        let context = test_context(vec![test_user("hOtB6pOxiL2IQPYs", Some("04C0FFEE"), None)]);
+        let mut reader = legacy_card();
+        let Ok(Tap::Accepted(event)) = wait_for_tap(&mut reader, &context, &cancel_flag) else {
+            panic!("The legacy card wasn't accepted");
+        };
+        assert!(!event.migrated);
+    }
+
+    #[test]
+    fn test_punch_outcome() {
+        let tap = |uid: &str, user_id: Option<&str>| {
+            Tap::Accepted(CardTapEvent {
+                uid: uid.to_string(),
+                user_id: user_id.map(|id| id.to_string()),
+                payload: None,
+                migrated: false,
+                duplicate: false,
+                source: SourceKind::Nfc,
+                reader: "ACS ACR122U PICC Interface 00 00".to_string(),
+                timestamp: "2024-10-01T08:00:00Z".to_string(),
+            })
+        };
+        let start = Instant::now();
+        let mut last_punch = None;
+
+        let mut first = tap("04A23B1C", Some("hOtB6pOxiL2IQPYs"));
+        assert_eq!(
+            punch_outcome(&mut first, &mut last_punch, start, false),
+            PunchOutcome::Accepted
+        );
+
+        let mut again = tap("04A23B1C", Some("hOtB6pOxiL2IQPYs"));
+        assert_eq!(
+            punch_outcome(
+                &mut again,
+                &mut last_punch,
+                start + Duration::from_secs(5),
+                false
+            ),
+            PunchOutcome::Duplicate
+        );
+        assert!(matches!(again, Tap::Accepted(event) if event.duplicate));
+
+        let mut unknown = tap("04FFFFFF", None);
+        assert_eq!(
+            punch_outcome(
+                &mut unknown,
+                &mut last_punch,
+                start + Duration::from_secs(10),
+                false
+            ),
+            PunchOutcome::Rejected
+        );
+
+        // The window counts from the first punch
+        let mut later = tap("04A23B1C", Some("hOtB6pOxiL2IQPYs"));
+        assert_eq!(
+            punch_outcome(&mut later, &mut last_punch, start + DUPLICATE_WINDOW, false),
+            PunchOutcome::Accepted
+        );
+
+        // Held for the PIN, the punch isn't recorded until it's typed
+        let mut pin = tap("04C0FFEE", Some("iL2IQPYshOtB6pOx"));
+        assert_eq!(
+            punch_outcome(&mut pin, &mut last_punch, start + DUPLICATE_WINDOW, true),
+            PunchOutcome::PendingPin
+        );
+        assert!(matches!(pin, Tap::PendingPin(_)));
+        assert_eq!(last_punch.as_ref().unwrap().0, "04A23B1C");
+    }
+}
@@ -23,9 +23,44 @@ pub(crate) fn get_cache() -> HashMap<String, UserExternal> {
     }
 }
 
+/// Normalizes a card UID to uppercase hex without separators, e.g. "04:a2:1b:7c" becomes "04A21B7C".
+pub(crate) fn normalize_uid(uid: &str) -> String {
+    uid.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Finds the cached user whose badge has the given UID.
+pub(crate) fn find_cached_user_by_uid(uid: &str) -> Option<UserExternal> {
+    let uid = normalize_uid(uid);
+    if uid.is_empty() {
+        return None;
+    }
+
+    get_cache()
+        .into_values()
+        .find(|user| user.card_uid.as_deref().map(normalize_uid) == Some(uid.clone()))
+}
+
+/// Resolves a card UID to the employee it was enrolled to.
+///
+/// Uses the local cache, so it also works while the app is offline.
+#[tauri::command]
+pub(crate) fn find_user_by_uid(uid: String) -> Result<UserExternal, String> {
+    find_cached_user_by_uid(&uid).ok_or_else(|| "No user enrolled with this card".to_string())
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::cache::get::get_cache;
+    use crate::cache::get::{get_cache, normalize_uid};
+
+    #[test]
+    fn test_normalize_uid() {
+        assert_eq!(normalize_uid("04:a2:1b:7c"), "04A21B7C");
+        assert_eq!(normalize_uid("04 A2 1B 7C"), "04A21B7C");
+        assert_eq!(normalize_uid("04a21b7c"), "04A21B7C");
+    }
 
     #[test]
     fn test_get_cache() {
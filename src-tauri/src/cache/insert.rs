@@ -66,6 +66,9 @@ pub(crate) async fn insert_new_user(
                     image: None,
                     status: None,
                     hour_data: Option::from(HashMap::new()),
+                    card_uid: None,
+                    card_issued_at: None,
+                    card_serial: None,
                 };
 
                 collection.insert_one(user.clone()).await.unwrap();
@@ -80,6 +83,7 @@ pub(crate) async fn insert_new_user(
                     email: user.email,
                     phone,
                     permissions,
+                    card_uid: None,
                 };
 
                 let internal_user: InternalUserSchema = InternalUserSchema {
@@ -88,6 +92,7 @@ pub(crate) async fn insert_new_user(
                     password: id,
                     registered_at: chrono::Utc::now().to_string(),
                     worker_data,
+                    pin_hash: None,
                 };
 
                 let collection: Collection<InternalUserSchema> = db.collection("users_internal");
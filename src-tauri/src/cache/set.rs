@@ -122,6 +122,7 @@ mod tests {
             lunch_break_return: random_time(&mut rng_clone),
             clocked_out: random_time(&mut rng_clone),
             total_hours: random_total_hours(&mut rng_clone),
+            methods: HashMap::new(),
         }
     }
 
@@ -1,6 +1,11 @@
+use crate::cache::get::normalize_uid;
 use crate::database::connect::SharedDatabases;
 use crate::database::helpers::set_app_connection::set_offline;
-use crate::database::schemas::user_schema::{HourData, UserExternal};
+use crate::database::schemas::permission_verify::{PermissionAction, PermissionChecker};
+use crate::database::schemas::user_schema::{
+    HourData, InternalUserSchema, PunchMethod, UserExternal,
+};
+use crate::misc::token::verify;
 use mongodb::bson::doc;
 use mongodb::error::ErrorKind;
 use mongodb::Collection;
@@ -19,6 +24,18 @@ pub(crate) enum UpdateKey {
     ClockOut,
 }
 
+impl UpdateKey {
+    /// The key of the punch in `HourData::methods`, the same one it's parsed from.
+    fn key(&self) -> &'static str {
+        match self {
+            UpdateKey::ClockIn => "clock_in",
+            UpdateKey::ClockLunchOut => "clock_lunch_out",
+            UpdateKey::ClockLunchReturn => "clock_lunch_return",
+            UpdateKey::ClockOut => "clock_out",
+        }
+    }
+}
+
 impl FromStr for UpdateKey {
     type Err = ();
 
@@ -33,6 +50,9 @@ impl FromStr for UpdateKey {
     }
 }
 
+/// Saves a punch of the user, or an edit of their hours.
+///
+/// `method` is how the punch was made, edits by hand leave it out.
 #[tauri::command]
 pub(crate) async fn update_cache_hour_data(
     app: AppHandle,
@@ -40,6 +60,7 @@ pub(crate) async fn update_cache_hour_data(
     day: String,
     key_to_update: UpdateKey,
     value: String,
+    method: Option<PunchMethod>,
 ) -> Result<bool, String> {
     let cache_path = get_cache_path()?;
     let mut users_map = load_users_cache(&cache_path)?;
@@ -56,9 +77,14 @@ pub(crate) async fn update_cache_hour_data(
             lunch_break_return: "N/A".to_string(),
             clocked_out: "N/A".to_string(),
             total_hours: "N/A".to_string(),
+            methods: HashMap::new(),
         }
     });
 
+    match method {
+        Some(method) => day_data.methods.insert(key_to_update.key().to_string(), method),
+        None => day_data.methods.remove(key_to_update.key()),
+    };
     update_hour_data(day_data, key_to_update, value.clone());
 
     if db.is_online.load(Ordering::SeqCst) {
@@ -72,6 +98,85 @@ pub(crate) async fn update_cache_hour_data(
     Ok(true)
 }
 
+/// The badge fields of a user, always saved together so they describe the same card.
+///
+/// * `uid` - Hardware UID of the badge, as uppercase hex.
+/// * `issued_at` - When the badge was enrolled, in RFC 3339.
+/// * `serial` - Issue counter of the badge.
+#[derive(Serialize, Debug, Clone, Default)]
+pub(crate) struct CardRecord {
+    pub(crate) uid: Option<String>,
+    pub(crate) issued_at: Option<String>,
+    pub(crate) serial: Option<u32>,
+}
+
+/// Assigns a badge to a user by its hardware UID, or removes it when `uid` is `None`.
+///
+/// The UID is saved on both the external and internal user records, and on the local cache.
+/// A UID can only belong to one user, so this fails if the card is already enrolled to someone else.
+/// A card enrolled to an employee clocks them in, so only admins with the `WriteOthers` permission can do this.
+#[tauri::command]
+pub(crate) async fn update_card_uid(
+    app: AppHandle,
+    token: String,
+    id: String,
+    uid: Option<String>,
+) -> Result<bool, String> {
+    let admin = verify(token, app.clone()).await?;
+    if !PermissionChecker::check_permission(
+        admin.worker_data.permissions,
+        PermissionAction::WriteOthers,
+    ) {
+        return Err("Permission denied, WriteOthers is required".to_string());
+    }
+
+    // Assigned by hand, so there's no badge written to the card to date or number
+    set_user_card(app, &id, CardRecord { uid, ..CardRecord::default() }).await?;
+
+    Ok(true)
+}
+
+/// Saves the badge of a user, replacing the card enrolled before it.
+///
+/// Used by `update_card_uid` and by the card enrollment, which writes the badge to the card first.
+pub(crate) async fn set_user_card(app: AppHandle, id: &str, card: CardRecord) -> Result<(), String> {
+    let cache_path = get_cache_path()?;
+    let mut users_map = load_users_cache(&cache_path)?;
+    let db_connection = app.state::<SharedDatabases>();
+    let db = db_connection.deref();
+
+    let card = CardRecord {
+        uid: card.uid.map(|uid| normalize_uid(&uid)),
+        ..card
+    };
+    if card.uid.as_deref() == Some("") {
+        return Err("Invalid card UID".to_string());
+    }
+
+    if let Some(ref uid) = card.uid {
+        let owner = users_map
+            .values()
+            .find(|user| user.id != id && user.card_uid.as_ref() == Some(uid));
+        if owner.is_some() {
+            return Err("Card is already enrolled to another user".to_string());
+        }
+    }
+
+    if !db.is_online.load(Ordering::SeqCst) {
+        return Err("App is offline and cannot enroll cards for now.".to_string());
+    }
+
+    let user = get_user(&mut users_map, id)?;
+    user.card_uid = card.uid.clone();
+    user.card_issued_at = card.issued_at.clone();
+    user.card_serial = card.serial;
+
+    update_mongo_card(app.clone(), db, id, card).await?;
+    save_users_cache(&cache_path, &users_map)?;
+
+    Ok(())
+}
+
 fn get_cache_path() -> Result<std::path::PathBuf, String> {
     let mut cache_path = dirs::cache_dir().ok_or("Failed to get cache path")?;
     cache_path.push("PontuAll/cache/users/users.json");
@@ -138,6 +243,47 @@ async fn update_mongo_db(
 }
 
 
+async fn update_mongo_card(
+    app: AppHandle,
+    db: &SharedDatabases,
+    id: &str,
+    card: CardRecord,
+) -> Result<(), String> {
+    let filter = doc! { "id": id };
+
+    let get_db = db.mongo_db.as_ref().ok_or("MongoDB connection unavailable")?;
+    let get_db = get_db.lock().await;
+    let mongo_db = get_db.clone().expect("Could not get a MongoDb instance");
+    let mongo_db = mongo_db.read().await;
+
+    let external: Collection<UserExternal> = mongo_db.collection("users_external");
+    let internal: Collection<InternalUserSchema> = mongo_db.collection("users_internal");
+
+    let result = async {
+        external
+            .update_one(
+                filter.clone(),
+                doc! { "$set": {
+                    "card_uid": card.uid.clone(),
+                    "card_issued_at": card.issued_at,
+                    "card_serial": card.serial,
+                } },
+            )
+            .await?;
+        internal
+            .update_one(filter, doc! { "$set": { "worker_data.card_uid": card.uid } })
+            .await
+    }
+        .await;
+
+    if let Err(e) = result {
+        set_offline(app.clone()).await;
+        return Err(format!("Database error: {}", e));
+    }
+
+    Ok(())
+}
+
 async fn update_sled_db(db: &SharedDatabases, id: &str, hour_data: &HashMap<String, HourData>) -> Result<(), String> {
     let sled_db = db.sled_db.as_ref().ok_or("Sled database unavailable")?;
     let serialized_user_data = bincode::serialize(hour_data).map_err(|e| format!("Failed to serialize user data: {}", e))?;
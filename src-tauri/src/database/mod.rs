@@ -3,5 +3,6 @@ pub(crate) mod schemas;
 #[macro_use]
 pub(crate) mod tauri_commands;
 pub(crate) mod sync;
+pub(crate) mod revoked_cards;
 
 pub(crate) mod helpers;
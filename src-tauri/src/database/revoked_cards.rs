@@ -0,0 +1,84 @@
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::Collection;
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+use tauri::{AppHandle, Manager};
+
+use crate::acr122u::card::revocation::{replace_revoked, RevokedCard};
+use crate::database::connect::SharedDatabases;
+use crate::database::helpers::set_app_connection::set_offline;
+
+/// Gets the `revoked_cards` collection, failing if the app is offline.
+async fn revoked_collection(app: &AppHandle) -> Result<Collection<RevokedCard>, String> {
+    let db_connection = app.state::<SharedDatabases>();
+    let db = db_connection.deref();
+
+    if !db.is_online.load(Ordering::SeqCst) {
+        return Err("App is offline and cannot change revoked cards for now.".to_string());
+    }
+
+    let get_db = db
+        .mongo_db
+        .as_ref()
+        .ok_or("MongoDB connection unavailable")?;
+    let get_db = get_db.lock().await;
+    let mongo_db = get_db.clone().ok_or("MongoDB connection unavailable")?;
+    let mongo_db = mongo_db.read().await;
+
+    Ok(mongo_db.collection("revoked_cards"))
+}
+
+/// Adds the card to the `revoked_cards` collection, replacing the reason if it was already revoked.
+pub(crate) async fn insert_revoked_card(app: AppHandle, card: &RevokedCard) -> Result<(), String> {
+    let collection = revoked_collection(&app).await?;
+
+    let result = collection
+        .replace_one(doc! { "uid": &card.uid }, card)
+        .upsert(true)
+        .await;
+
+    if let Err(e) = result {
+        set_offline(app.clone()).await;
+        return Err(format!("Database error: {}", e));
+    }
+
+    Ok(())
+}
+
+/// Takes the card out of the `revoked_cards` collection.
+pub(crate) async fn delete_revoked_card(app: AppHandle, uid: &str) -> Result<(), String> {
+    let collection = revoked_collection(&app).await?;
+
+    if let Err(e) = collection.delete_one(doc! { "uid": uid }).await {
+        set_offline(app.clone()).await;
+        return Err(format!("Database error: {}", e));
+    }
+
+    Ok(())
+}
+
+/// Copies the `revoked_cards` collection to the local sled store, so taps are checked against it offline.
+pub(crate) async fn sync_revoked_cards(app: AppHandle) -> Result<(), String> {
+    let collection = revoked_collection(&app).await?;
+
+    let cards: Vec<RevokedCard> = match collection.find(doc! {}).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(|e| e.to_string())?,
+        Err(e) => {
+            set_offline(app.clone()).await;
+            return Err(format!("Database error: {}", e));
+        }
+    };
+
+    let db_connection = app.state::<SharedDatabases>();
+    let sled_db = db_connection
+        .sled_db
+        .as_ref()
+        .ok_or("Sled database unavailable")?;
+    let sled_db = sled_db.lock().await;
+
+    replace_revoked(&sled_db, &cards).map_err(|e| e.to_string())?;
+    println!("Synchronized {} revoked cards", cards.len());
+
+    Ok(())
+}
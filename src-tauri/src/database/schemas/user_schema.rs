@@ -37,6 +37,9 @@ pub(crate) struct WorkerData {
     pub(crate) email: Option<String>,
     pub(crate) phone: Option<String>,
     pub(crate) permissions: PermissionsBitField,
+    /// Hardware UID of the employee's badge, as uppercase hex.
+    #[serde(default)]
+    pub(crate) card_uid: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -46,6 +49,22 @@ pub(crate) struct InternalUserSchema {
     pub password: String,
     pub registered_at: String,
     pub worker_data: WorkerData,
+    /// Argon2 hash of the PIN the employee clocks in with when the badge is forgotten, see `misc::pin`.
+    #[serde(default)]
+    pub pin_hash: Option<String>,
+}
+
+/// How a punch was made.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PunchMethod {
+    /// A card tapped on an NFC reader.
+    Nfc,
+    /// A badge number typed by a keyboard wedge reader.
+    Wedge,
+    /// A badge number typed in by hand.
+    Manual,
+    /// The employee id and PIN, for a forgotten badge.
+    Pin,
 }
 
 /** External User Schema */
@@ -57,6 +76,33 @@ pub(crate) struct HourData {
     pub(crate) lunch_break_return: String,
     pub(crate) clocked_out: String,
     pub(crate) total_hours: String,
+    /// How each punch of the day was made, by the key it was saved with, e.g. "clock_in".
+    /// Punches made before the method was recorded aren't in it.
+    #[serde(default)]
+    pub(crate) methods: HashMap<String, PunchMethod>,
+}
+
+/// `HourData` as it was saved to sled before the punch methods, bincode can't leave a field out.
+#[derive(Deserialize)]
+pub(crate) struct LegacyHourData {
+    clock_in: String,
+    lunch_break_out: String,
+    lunch_break_return: String,
+    clocked_out: String,
+    total_hours: String,
+}
+
+impl From<LegacyHourData> for HourData {
+    fn from(legacy: LegacyHourData) -> Self {
+        HourData {
+            clock_in: legacy.clock_in,
+            lunch_break_out: legacy.lunch_break_out,
+            lunch_break_return: legacy.lunch_break_return,
+            clocked_out: legacy.clocked_out,
+            total_hours: legacy.total_hours,
+            methods: HashMap::new(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -69,4 +115,13 @@ pub(crate) struct UserExternal {
     pub(crate) hour_data: Option<HashMap<String, HourData>>,
     pub(crate) lunch_time: Option<String>,
     pub(crate) status: Option<String>,
+    /// Hardware UID of the employee's badge, as uppercase hex.
+    #[serde(default)]
+    pub(crate) card_uid: Option<String>,
+    /// When the badge was enrolled, in RFC 3339.
+    #[serde(default)]
+    pub(crate) card_issued_at: Option<String>,
+    /// Issue counter of the badge, a badge with a lower one was replaced.
+    #[serde(default)]
+    pub(crate) card_serial: Option<u32>,
 }
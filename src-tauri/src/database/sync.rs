@@ -1,7 +1,10 @@
 use crate::cache::get::get_cache;
 use crate::database::connect::{mongo_db_connection, SharedDatabases};
-use crate::database::schemas::user_schema::{HourData, UserExternal};
+use crate::database::revoked_cards::sync_revoked_cards;
+use crate::database::schemas::user_schema::{HourData, LegacyHourData, UserExternal};
+use crate::misc::pin::sync_pin_hashes;
 use bincode;
+use bincode::Options;
 use mongodb::bson::doc;
 use mongodb::error::ErrorKind;
 use mongodb::Collection;
@@ -11,6 +14,21 @@ use std::ops::Deref;
 use std::sync::atomic::Ordering;
 use tauri::{AppHandle, Emitter, Manager};
 
+/// Decodes the hour data saved to sled while offline, the one saved before the punch methods included.
+fn decode_hour_data(value: &[u8]) -> Result<HashMap<String, HourData>, String> {
+    // Strict, so old data can't pass for the new format by leaving bytes behind
+    let current = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize::<HashMap<String, HourData>>(value);
+
+    current.or_else(|e| {
+        bincode::deserialize::<HashMap<String, LegacyHourData>>(value)
+            .map(|legacy| legacy.into_iter().map(|(day, data)| (day, data.into())).collect())
+            .map_err(|_| format!("Failed to deserialize hour_data: {}", e))
+    })
+}
+
 // Then refactor your sync_database function
 pub(crate) async fn sync_database(app: AppHandle) -> Result<(), Box<dyn Error>> {
     // Access the shared databases
@@ -42,8 +60,7 @@ pub(crate) async fn sync_database(app: AppHandle) -> Result<(), Box<dyn Error>>
 
 
                         // Deserialize the data as HashMap<String, HourData> since it was serialized that way
-                        let hour_data: HashMap<String, HourData> = bincode::deserialize(&value)
-                            .map_err(|e| format!("Failed to deserialize hour_data: {}", e))?;
+                        let hour_data = decode_hour_data(&value)?;
 
                         let filter = doc! {"id": &id};
 
@@ -117,6 +134,16 @@ pub(crate) async fn sync_database(app: AppHandle) -> Result<(), Box<dyn Error>>
         eprintln!("Still offline, skipping synchronization.");
     }
 
+    // Pulled after the punches are pushed, the MongoDB lock above is released by now
+    if db.is_online.load(Ordering::SeqCst) {
+        if let Err(e) = sync_revoked_cards(app.clone()).await {
+            eprintln!("Failed to synchronize revoked cards: {}", e);
+        }
+        if let Err(e) = sync_pin_hashes(app.clone()).await {
+            eprintln!("Failed to synchronize PIN hashes: {}", e);
+        }
+    }
+
     // Send event to indicate that synchronization has finished
     app.emit("sync_event", "Synchronization completed successfully.")?;
 
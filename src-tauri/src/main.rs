@@ -1,20 +1,29 @@
 // Prevents an additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 use tauri::Manager;
 
+use crate::acr122u::reader::queue::ReaderQueue;
 use crate::acr122u::tauri_commands::{
-    cancel_read, cancel_write, connect_reader, get_connection, read_card, write_card, ReadState,
-    WriteState,
+    cancel_read, cancel_write, connect_reader, dump_card, enroll_card, get_connection,
+    get_key_mapping, get_reader_settings, issue_badge, list_readers, list_revoked_cards, read_card,
+    read_card_raw, read_card_uid, read_ndef, reader_diagnostics, reader_status, reinstate_card,
+    remove_sector_key, restore_card, revoke_card, select_reader, set_card_timeout,
+    set_feedback_settings, set_reader_role, set_sector_key, set_source_settings, simulate_card,
+    submit_badge_number, verify_badge, wedge_input, write_card, write_card_raw, write_card_trailer,
+    write_ndef, ReadState, WedgeState, WriteState,
 };
-use crate::cache::get::get_cache;
+use crate::acr122u::watcher::TapState;
+use crate::cache::get::{find_user_by_uid, get_cache};
 use crate::cache::insert::{gen_id, insert_new_user};
 use crate::cache::set::get_users_and_cache;
-use crate::cache::update::update_cache_hour_data;
+use crate::cache::update::{update_cache_hour_data, update_card_uid};
 use crate::database::tauri_commands::{check_permission, user_login};
 use crate::excel::create::create_excel_relatory;
+use crate::misc::card_pin::{cancel_card_pin, confirm_card_pin, CardPinState};
 use crate::misc::get::version_name;
+use crate::misc::pin::{pin_clock_in, set_user_pin, PinState};
+use crate::misc::policy::{get_site_policy, set_site_policy};
 use crate::misc::set_db_uri::insert_uri;
 use crate::misc::setup::{complete_setup, SetupState};
 use crate::misc::token::verify;
@@ -26,18 +35,15 @@ mod excel;
 mod misc;
 
 fn main() {
-    let cancel_flag = Arc::new(AtomicBool::new(false));
-    let read_in_progress = Arc::new(Mutex::new(false));
+    let read_state = Arc::new(ReadState::default());
+    let write_state = Arc::new(WriteState::default());
+    let tap_state = Arc::new(TapState::default());
+    let wedge_state = Arc::new(WedgeState::default());
+    let pin_state = Arc::new(PinState::load());
+    let card_pin_state = Arc::new(CardPinState::default());
 
-    let read_state = Arc::new(ReadState {
-        cancel_flag,
-        read_in_progress,
-    });
-
-    let write_state_cancel_flag = Arc::new(AtomicBool::new(false));
-    let write_state = Arc::new(WriteState {
-        cancel_flag: write_state_cancel_flag,
-    });
+    // Every card operation, the card watcher's included, runs on this queue
+    let reader_queue = ReaderQueue::start();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
@@ -52,8 +58,13 @@ fn main() {
         }))
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_fs::init())
+        .manage(reader_queue)
         .manage(read_state)
         .manage(write_state)
+        .manage(tap_state)
+        .manage(wedge_state)
+        .manage(pin_state)
+        .manage(card_pin_state)
         .manage(Mutex::new(SetupState {
             frontend_task: false,
             backend_task: false,
@@ -62,16 +73,54 @@ fn main() {
             // NFC READER
             connect_reader,
             read_card,
+            read_card_uid,
+            read_card_raw,
             write_card,
+            write_card_raw,
+            write_card_trailer,
+            write_ndef,
+            read_ndef,
+            dump_card,
+            restore_card,
+            issue_badge,
+            enroll_card,
+            revoke_card,
+            reinstate_card,
+            list_revoked_cards,
+            verify_badge,
             cancel_write,
             cancel_read,
             get_connection,
+            list_readers,
+            reader_status,
+            reader_diagnostics,
+            get_reader_settings,
+            select_reader,
+            set_reader_role,
+            set_feedback_settings,
+            set_source_settings,
+            set_card_timeout,
+            set_sector_key,
+            remove_sector_key,
+            get_key_mapping,
+            simulate_card,
+            wedge_input,
+            submit_badge_number,
             // Local Cache
             gen_id,
             get_cache,
             insert_new_user,
             update_cache_hour_data,
+            find_user_by_uid,
+            update_card_uid,
             get_users_and_cache,
+            // PIN clock-in
+            set_user_pin,
+            pin_clock_in,
+            confirm_card_pin,
+            cancel_card_pin,
+            get_site_policy,
+            set_site_policy,
             // Setup / System related
             complete_setup,
             insert_uri,
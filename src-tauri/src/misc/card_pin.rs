@@ -0,0 +1,254 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::acr122u::watcher::{CardRejectedEvent, CardTapEvent, TapState};
+use crate::misc::pin::{find_pin_hash, lockout_message, verify_pin, PinState};
+use crate::misc::policy::{load_policy, CardPinPolicy};
+
+/// Payload of the `card:pin_required` event, sent instead of `card:tapped` when the site asks for the PIN after the badge.
+///
+/// * `challenge` - The id to confirm the PIN with, see `confirm_card_pin`.
+/// * `user_id` - The id of the employee the badge belongs to.
+/// * `timeout_secs` - How long the employee has to type the PIN.
+/// * `tap` - The tap waiting for the PIN, emitted as `card:tapped` once it's confirmed.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct PinRequiredEvent {
+    pub(crate) challenge: String,
+    pub(crate) user_id: String,
+    pub(crate) timeout_secs: u64,
+    pub(crate) tap: CardTapEvent,
+}
+
+/// A tap waiting for the PIN of the employee.
+struct Challenge {
+    id: String,
+    tap: CardTapEvent,
+    expires_at: Instant,
+}
+
+/// The tap waiting for its PIN, one at a time since the kiosk has a single PIN pad.
+///
+/// The wrong PINs are counted in `PinState`, along with the ones typed to clock in without the badge.
+#[derive(Default)]
+pub(crate) struct CardPinState {
+    pending: Mutex<Option<Challenge>>,
+}
+
+impl CardPinState {
+    /// Takes the challenge out if it's still the one waiting.
+    fn take(&self, challenge: &str) -> Option<Challenge> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending
+            .as_ref()
+            .is_some_and(|pending| pending.id == challenge)
+        {
+            pending.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns down a tap whose PIN never came, so the frontend closes the PIN pad.
+///
+/// The tap didn't punch anyone in, so the badge can be tapped again right away.
+fn reject(app: &AppHandle, tap: CardTapEvent, reason: &str) {
+    let event = CardRejectedEvent {
+        uid: tap.uid,
+        reason: reason.to_string(),
+        revoked: false,
+        source: tap.source,
+        reader: tap.reader,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    };
+    if let Err(e) = app.emit("card:rejected", event) {
+        println!("Card PIN: failed to emit event: {}", e);
+    }
+}
+
+/// Holds the tap until the employee types the PIN, and emits `card:pin_required` for the frontend to ask for it.
+///
+/// A tap already waiting is turned down, the badge tapped last is the one at the kiosk.
+/// If the PIN isn't confirmed within the timeout of the site policy, the tap is turned down as well.
+pub(crate) fn require_pin(app: &AppHandle, tap: CardTapEvent) {
+    // The tap was already held for the PIN, a policy that can't be read only loses its timeout
+    let policy = match load_policy() {
+        Ok(policy) => policy.card_pin,
+        Err(e) => {
+            println!(
+                "Card PIN: couldn't load the site policy, using the default timeout: {}",
+                e
+            );
+            CardPinPolicy::default()
+        }
+    };
+    let timeout = Duration::from_secs(policy.timeout_secs);
+    let challenge = uuid::Uuid::new_v4().to_string();
+
+    let event = PinRequiredEvent {
+        challenge: challenge.clone(),
+        user_id: tap.user_id.clone().unwrap_or_default(),
+        timeout_secs: policy.timeout_secs,
+        tap: tap.clone(),
+    };
+
+    let state = app.state::<Arc<CardPinState>>();
+    let replaced = state.pending.lock().unwrap().replace(Challenge {
+        id: challenge.clone(),
+        tap,
+        expires_at: Instant::now() + timeout,
+    });
+    if let Some(replaced) = replaced {
+        reject(app, replaced.tap, "Another badge was tapped");
+    }
+
+    if let Err(e) = app.emit("card:pin_required", event) {
+        println!("Card PIN: failed to emit event: {}", e);
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(timeout).await;
+
+        let expired = app.state::<Arc<CardPinState>>().take(&challenge);
+        if let Some(expired) = expired {
+            reject(&app, expired.tap, "The PIN wasn't typed in time");
+        }
+    });
+}
+
+/// Confirms the tap waiting for the PIN of the employee, see `require_pin`.
+///
+/// The PIN is checked against the database, or against the local copy of the hashes when the app is offline.
+/// Once confirmed, the tap is emitted as `card:tapped` and the frontend saves the punch like any other,
+/// it's only then that the badge counts as punched for the duplicate window.
+/// After the wrong PINs allowed by the site policy, the employee is locked out and the tap is turned down.
+///
+/// # Arguments
+///
+/// * `challenge` - The id of the challenge, from the `card:pin_required` event.
+/// * `pin` - The PIN typed at the kiosk.
+/// * `app` - The app handle, used to get the database and emit the events.
+/// * `state` - The tap waiting for its PIN.
+/// * `pins` - The wrong PINs tried so far.
+///
+/// # Returns
+///
+/// * `Ok(CardTapEvent)` - The confirmed tap.
+/// * `Err(String)` - If the PIN is wrong, the employee is locked out or the tap isn't waiting anymore.
+#[tauri::command]
+pub(crate) async fn confirm_card_pin(
+    challenge: String,
+    pin: String,
+    app: AppHandle,
+    state: State<'_, Arc<CardPinState>>,
+    pins: State<'_, Arc<PinState>>,
+) -> Result<CardTapEvent, String> {
+    let tap = match state.pending.lock().unwrap().as_ref() {
+        Some(pending) if pending.id == challenge && pending.expires_at > Instant::now() => {
+            pending.tap.clone()
+        }
+        _ => return Err("No badge is waiting for this PIN".to_string()),
+    };
+    let user_id = tap.user_id.clone().unwrap_or_default();
+
+    let reserved = pins
+        .attempts
+        .lock()
+        .unwrap()
+        .reserve(&user_id, Instant::now());
+    if let Err(remaining) = reserved {
+        if let Some(pending) = state.take(&challenge) {
+            reject(&app, pending.tap, "Too many wrong PINs");
+        }
+        return Err(lockout_message(remaining));
+    }
+
+    let Some(pin_hash) = find_pin_hash(&app, &user_id).await? else {
+        if let Some(pending) = state.take(&challenge) {
+            reject(&app, pending.tap, "The employee has no PIN");
+        }
+        return Err("No PIN is set for this employee, ask a manager to set one".to_string());
+    };
+
+    if !verify_pin(&pin, &pin_hash) {
+        // Already counted by `reserve`, the lockout may have started with it
+        let locked = pins
+            .attempts
+            .lock()
+            .unwrap()
+            .check(&user_id, Instant::now());
+        if let Err(remaining) = locked {
+            if let Some(pending) = state.take(&challenge) {
+                reject(&app, pending.tap, "Too many wrong PINs");
+            }
+            return Err(lockout_message(remaining));
+        }
+        return Err("Wrong PIN".to_string());
+    }
+
+    pins.attempts.lock().unwrap().succeeded(&user_id);
+
+    // It may have timed out while the PIN was checked
+    let confirmed = state
+        .take(&challenge)
+        .ok_or("No badge is waiting for this PIN")?;
+    app.state::<Arc<TapState>>()
+        .punched(&confirmed.tap.uid, Instant::now());
+    app.emit("card:tapped", confirmed.tap.clone())
+        .map_err(|e| e.to_string())?;
+
+    Ok(confirmed.tap)
+}
+
+/// Drops the tap waiting for its PIN, when the employee walks away from the PIN pad.
+///
+/// # Arguments
+///
+/// * `challenge` - The id of the challenge, from the `card:pin_required` event.
+/// * `app` - The app handle, to emit the event.
+/// * `state` - The tap waiting for its PIN.
+#[tauri::command]
+pub(crate) fn cancel_card_pin(
+    challenge: String,
+    app: AppHandle,
+    state: State<'_, Arc<CardPinState>>,
+) -> Result<(), String> {
+    if let Some(pending) = state.take(&challenge) {
+        reject(&app, pending.tap, "The PIN was cancelled");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acr122u::source::SourceKind;
+
+    #[test]
+    fn test_take_challenge() {
+        let state = CardPinState::default();
+        *state.pending.lock().unwrap() = Some(Challenge {
+            id: "first".to_string(),
+            tap: CardTapEvent {
+                uid: "04A23B1C".to_string(),
+                user_id: Some("hOtB6pOxiL2IQPYs".to_string()),
+                payload: None,
+                migrated: false,
+                duplicate: false,
+                source: SourceKind::Nfc,
+                reader: "ACS ACR122U PICC Interface 00 00".to_string(),
+                timestamp: "2024-10-01T08:00:00Z".to_string(),
+            },
+            expires_at: Instant::now(),
+        });
+
+        assert!(state.take("second").is_none());
+        assert_eq!(state.take("first").unwrap().tap.uid, "04A23B1C");
+        assert!(state.take("first").is_none());
+    }
+}
@@ -7,4 +7,7 @@ pub(crate) mod token;
 #[macro_use]
 pub(crate) mod set_db_uri;
 
+pub(crate) mod card_pin;
+pub(crate) mod pin;
 pub(crate) mod ping;
+pub(crate) mod policy;
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::Collection;
+use rand::rngs::OsRng;
+use tauri::{AppHandle, Manager, State};
+
+use crate::database::connect::SharedDatabases;
+use crate::database::helpers::set_app_connection::set_offline;
+use crate::database::schemas::permission_verify::{PermissionAction, PermissionChecker};
+use crate::database::schemas::user_schema::InternalUserSchema;
+use crate::misc::policy::{load_policy, CardPinPolicy};
+use crate::misc::token::verify;
+
+/// PINs are 4 to 8 digits, short enough to type on the kiosk keypad.
+const PIN_LENGTH: std::ops::RangeInclusive<usize> = 4..=8;
+
+/// The sled tree keeping a copy of the PIN hashes, so PINs are checked offline too.
+const PIN_TREE: &str = "pin_hashes";
+
+/// The same answer for an unknown id, an employee without a PIN and a wrong PIN,
+/// so the kiosk can't be used to find out which ids exist.
+const INVALID_PIN: &str = "Invalid employee id or PIN";
+
+/// Counts the wrong PINs of each employee id, to lock the id out after `max_failures` of them.
+///
+/// The limits are the ones of the site policy, shared by the PIN clock-in and the PIN after a badge.
+/// `lockout` is how long the id stays locked out, and how long a wrong PIN counts towards it.
+pub(crate) struct PinAttempts {
+    failures: HashMap<String, (u32, Instant)>,
+    pub(crate) max_failures: u32,
+    pub(crate) lockout: Duration,
+}
+
+impl Default for PinAttempts {
+    fn default() -> Self {
+        let policy = CardPinPolicy::default();
+        PinAttempts {
+            failures: HashMap::new(),
+            max_failures: policy.max_failures,
+            lockout: Duration::from_secs(policy.lockout_secs),
+        }
+    }
+}
+
+impl PinAttempts {
+    /// Checks that the id isn't locked out.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If a PIN can be tried.
+    /// * `Err(Duration)` - How long the id stays locked out.
+    pub(crate) fn check(&mut self, id: &str, now: Instant) -> Result<(), Duration> {
+        let Some((count, last_failure)) = self.failures.get(id) else {
+            return Ok(());
+        };
+
+        let elapsed = now.duration_since(*last_failure);
+        if elapsed >= self.lockout {
+            self.failures.remove(id);
+        } else if *count >= self.max_failures {
+            return Err(self.lockout - elapsed);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the id isn't locked out and counts the attempt as a wrong PIN right away.
+    ///
+    /// Done under the same lock as the check, so PINs tried at the same time can't all get past it
+    /// while they're being verified. A right PIN clears it again with `succeeded`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If a PIN can be tried.
+    /// * `Err(Duration)` - How long the id stays locked out.
+    pub(crate) fn reserve(&mut self, id: &str, now: Instant) -> Result<(), Duration> {
+        self.check(id, now)?;
+        self.failed(id, now);
+        Ok(())
+    }
+
+    pub(crate) fn failed(&mut self, id: &str, now: Instant) {
+        let (count, last_failure) = self.failures.entry(id.to_string()).or_insert((0, now));
+        *count += 1;
+        *last_failure = now;
+    }
+
+    pub(crate) fn succeeded(&mut self, id: &str) {
+        self.failures.remove(id);
+    }
+}
+
+/// What the kiosk says to an employee id that's locked out.
+pub(crate) fn lockout_message(remaining: Duration) -> String {
+    format!(
+        "Too many wrong PINs, try again in {} minutes",
+        remaining.as_secs().div_ceil(60)
+    )
+}
+
+/// The wrong PINs of the kiosk, kept for as long as the app runs.
+///
+/// The PINs typed to clock in without the badge and the ones typed after a badge count together.
+#[derive(Default)]
+pub(crate) struct PinState {
+    pub(crate) attempts: Mutex<PinAttempts>,
+}
+
+impl PinState {
+    /// A new state with the lockout of the site policy.
+    pub(crate) fn load() -> PinState {
+        let state = PinState::default();
+        state.apply_policy(&load_policy().unwrap_or_default().card_pin);
+        state
+    }
+
+    /// Applies the wrong PINs allowed and the lockout of the policy, when it's loaded or changed.
+    pub(crate) fn apply_policy(&self, policy: &CardPinPolicy) {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.max_failures = policy.max_failures;
+        attempts.lockout = Duration::from_secs(policy.lockout_secs);
+    }
+}
+
+fn validate_pin(pin: &str) -> Result<(), String> {
+    if !PIN_LENGTH.contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "The PIN must have {} to {} digits",
+            PIN_LENGTH.start(),
+            PIN_LENGTH.end()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Hashes the PIN with Argon2 and a random salt, in the PHC string format.
+pub(crate) fn hash_pin(pin: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Checks the PIN against its hash, a hash that can't be parsed never matches.
+pub(crate) fn verify_pin(pin: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(pin.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// A hash to check PINs against when the employee has none, so an unknown id takes as long to answer as a wrong PIN.
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+
+    HASH.get_or_init(|| hash_pin("00000000").expect("Couldn't hash the dummy PIN"))
+}
+
+/// Checks the PIN against the hash of the employee, or against `dummy_hash` when there's none,
+/// so the time it takes doesn't tell whether the id exists.
+fn verify_pin_or_dummy(pin: &str, hash: Option<&str>) -> bool {
+    match hash {
+        Some(hash) => verify_pin(pin, hash),
+        None => {
+            verify_pin(pin, dummy_hash());
+            false
+        }
+    }
+}
+
+fn pin_tree(db: &sled::Db) -> Result<sled::Tree, String> {
+    db.open_tree(PIN_TREE).map_err(|e| e.to_string())
+}
+
+/// Finds the local copy of the PIN hash of the employee.
+pub(crate) fn cached_pin_hash(db: &sled::Db, id: &str) -> Result<Option<String>, String> {
+    match pin_tree(db)?.get(id.as_bytes()) {
+        Ok(Some(hash)) => String::from_utf8(hash.to_vec())
+            .map(Some)
+            .map_err(|e| e.to_string()),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Keeps a local copy of the PIN hash of the employee, `None` removes it.
+pub(crate) fn cache_pin_hash(db: &sled::Db, id: &str, hash: Option<&str>) -> Result<(), String> {
+    let tree = pin_tree(db)?;
+
+    match hash {
+        Some(hash) => tree.insert(id.as_bytes(), hash.as_bytes()),
+        None => tree.remove(id.as_bytes()),
+    }
+    .map_err(|e| e.to_string())?;
+    tree.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Replaces the local copy of the PIN hashes with the ones from the database, as (id, hash).
+pub(crate) fn replace_cached_pins(
+    db: &sled::Db,
+    hashes: &[(String, String)],
+) -> Result<(), String> {
+    pin_tree(db)?.clear().map_err(|e| e.to_string())?;
+
+    for (id, hash) in hashes {
+        cache_pin_hash(db, id, Some(hash))?;
+    }
+
+    Ok(())
+}
+
+async fn local_db(app: &AppHandle) -> Result<sled::Db, String> {
+    let db_connection = app.state::<SharedDatabases>();
+    let sled_db = db_connection
+        .sled_db
+        .as_ref()
+        .ok_or("Sled database unavailable")?;
+
+    let sled_db = sled_db.lock().await.clone();
+    Ok(sled_db)
+}
+
+/// Gets the `users_internal` collection, failing if the app is offline.
+async fn internal_collection(app: &AppHandle) -> Result<Collection<InternalUserSchema>, String> {
+    let db_connection = app.state::<SharedDatabases>();
+    let db = db_connection.deref();
+
+    if !db.is_online.load(Ordering::SeqCst) {
+        return Err("App is offline and cannot check PINs for now.".to_string());
+    }
+
+    let get_db = db
+        .mongo_db
+        .as_ref()
+        .ok_or("MongoDB connection unavailable")?;
+    let get_db = get_db.lock().await;
+    let mongo_db = get_db.clone().ok_or("MongoDB connection unavailable")?;
+    let mongo_db = mongo_db.read().await;
+
+    Ok(mongo_db.collection("users_internal"))
+}
+
+/// Finds the PIN hash of the employee in the database, or in its local copy when the app is offline.
+///
+/// The local copy is refreshed on the way.
+pub(crate) async fn find_pin_hash(app: &AppHandle, id: &str) -> Result<Option<String>, String> {
+    let local_db = local_db(app).await?;
+
+    let db_connection = app.state::<SharedDatabases>();
+    if !db_connection.is_online.load(Ordering::SeqCst) {
+        return cached_pin_hash(&local_db, id);
+    }
+
+    let collection = internal_collection(app).await?;
+    match collection.find_one(doc! { "id": id }).await {
+        Ok(user) => {
+            let pin_hash = user.and_then(|user| user.pin_hash);
+            cache_pin_hash(&local_db, id, pin_hash.as_deref())?;
+            Ok(pin_hash)
+        }
+        Err(e) => {
+            println!("Database error: {}", e);
+            set_offline(app.clone()).await;
+            cached_pin_hash(&local_db, id)
+        }
+    }
+}
+
+/// Copies the PIN hashes from the `users_internal` collection to the local sled store, so PINs are checked offline too.
+pub(crate) async fn sync_pin_hashes(app: AppHandle) -> Result<(), String> {
+    let collection = internal_collection(&app).await?;
+
+    let users: Vec<InternalUserSchema> = match collection
+        .find(doc! { "pin_hash": { "$type": "string" } })
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await.map_err(|e| e.to_string())?,
+        Err(e) => {
+            set_offline(app.clone()).await;
+            return Err(format!("Database error: {}", e));
+        }
+    };
+
+    let hashes: Vec<(String, String)> = users
+        .into_iter()
+        .filter_map(|user| Some((user.id, user.pin_hash?)))
+        .collect();
+    replace_cached_pins(&local_db(&app).await?, &hashes)?;
+    println!("Synchronized {} PIN hashes", hashes.len());
+
+    Ok(())
+}
+
+/// Sets the PIN an employee clocks in with when the badge is forgotten.
+///
+/// Employees can set their own PIN, setting someone else's needs the WriteOthers permission.
+///
+/// # Arguments
+///
+/// * `token` - The login token of whoever sets the PIN.
+/// * `id` - The id of the employee.
+/// * `pin` - The new PIN, 4 to 8 digits.
+/// * `app` - The app handle, used to get the database.
+#[tauri::command]
+pub(crate) async fn set_user_pin(
+    token: String,
+    id: String,
+    pin: String,
+    app: AppHandle,
+) -> Result<bool, String> {
+    let caller = verify(token, app.clone()).await?;
+    let permission = if caller.id == id {
+        PermissionAction::WriteSelf
+    } else {
+        PermissionAction::WriteOthers
+    };
+    if !PermissionChecker::check_permission(caller.worker_data.permissions, permission) {
+        return Err("Permission denied".to_string());
+    }
+
+    validate_pin(&pin)?;
+    let pin_hash = hash_pin(&pin)?;
+
+    let collection = internal_collection(&app).await?;
+    let result = collection
+        .update_one(
+            doc! { "id": &id },
+            doc! { "$set": { "pin_hash": &pin_hash } },
+        )
+        .await;
+
+    match result {
+        Ok(result) if result.matched_count == 0 => Err("User not found".to_string()),
+        Ok(_) => {
+            cache_pin_hash(&local_db(&app).await?, &id, Some(&pin_hash))?;
+            Ok(true)
+        }
+        Err(e) => {
+            set_offline(app.clone()).await;
+            Err(format!("Database error: {}", e))
+        }
+    }
+}
+
+/// Checks the id and PIN of an employee who forgot the badge, so they can clock in at the kiosk.
+///
+/// The punch itself is then saved by `update_cache_hour_data` with the `Pin` method.
+/// The PIN is checked against the local copy of the hashes when the app is offline.
+/// After the wrong PINs allowed by the site policy the id is locked out for a while, and the policy can turn it off.
+/// Every PIN counts as wrong until it's verified, see `PinAttempts::reserve`.
+///
+/// # Arguments
+///
+/// * `id` - The id of the employee.
+/// * `pin` - The PIN typed at the kiosk.
+/// * `app` - The app handle, used to get the database.
+/// * `state` - The wrong PINs tried so far.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The id of the employee, to save the punch with.
+/// * `Err(String)` - If the PIN is wrong, the id is locked out or PIN clock-in is turned off.
+#[tauri::command]
+pub(crate) async fn pin_clock_in(
+    id: String,
+    pin: String,
+    app: AppHandle,
+    state: State<'_, Arc<PinState>>,
+) -> Result<String, String> {
+    if !load_policy()?.pin_clock_in {
+        return Err("PIN clock-in is turned off at this site".to_string());
+    }
+
+    if let Err(remaining) = state.attempts.lock().unwrap().reserve(&id, Instant::now()) {
+        return Err(lockout_message(remaining));
+    }
+
+    let pin_hash = find_pin_hash(&app, &id).await?;
+    if !verify_pin_or_dummy(&pin, pin_hash.as_deref()) {
+        return Err(INVALID_PIN.to_string());
+    }
+
+    state.attempts.lock().unwrap().succeeded(&id);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_hash() {
+        assert!(validate_pin("4821").is_ok());
+        assert!(validate_pin("482").is_err());
+        assert!(validate_pin("48a1").is_err());
+
+        let hash = hash_pin("4821").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_pin("4821", &hash));
+        assert!(!verify_pin("4822", &hash));
+        assert!(!verify_pin("4821", "not a hash"));
+
+        // An employee without a PIN never matches, not even the dummy PIN
+        assert!(verify_pin_or_dummy("4821", Some(&hash)));
+        assert!(!verify_pin_or_dummy("00000000", None));
+    }
+
+    #[test]
+    fn test_pin_cache() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let hash = hash_pin("4821").unwrap();
+
+        cache_pin_hash(&db, "hOtB6pOxiL2IQPYs", Some(&hash)).unwrap();
+        assert_eq!(
+            cached_pin_hash(&db, "hOtB6pOxiL2IQPYs").unwrap(),
+            Some(hash.clone())
+        );
+
+        replace_cached_pins(&db, &[("iL2IQPYshOtB6pOx".to_string(), hash)]).unwrap();
+        assert_eq!(cached_pin_hash(&db, "hOtB6pOxiL2IQPYs").unwrap(), None);
+        assert!(cached_pin_hash(&db, "iL2IQPYshOtB6pOx").unwrap().is_some());
+
+        cache_pin_hash(&db, "iL2IQPYshOtB6pOx", None).unwrap();
+        assert_eq!(cached_pin_hash(&db, "iL2IQPYshOtB6pOx").unwrap(), None);
+    }
+
+    #[test]
+    fn test_pin_attempts() {
+        let mut attempts = PinAttempts::default();
+        let start = Instant::now();
+        let lockout = attempts.lockout;
+
+        for _ in 0..attempts.max_failures {
+            assert!(attempts.check("hOtB6pOxiL2IQPYs", start).is_ok());
+            attempts.failed("hOtB6pOxiL2IQPYs", start);
+        }
+        assert!(attempts.check("hOtB6pOxiL2IQPYs", start).is_err());
+        assert!(attempts.check("iL2IQPYshOtB6pOx", start).is_ok());
+
+        assert!(attempts.check("hOtB6pOxiL2IQPYs", start + lockout).is_ok());
+
+        attempts.failed("hOtB6pOxiL2IQPYs", start + lockout);
+        attempts.succeeded("hOtB6pOxiL2IQPYs");
+        assert!(attempts.failures.is_empty());
+    }
+
+    #[test]
+    fn test_pin_reserve() {
+        let mut attempts = PinAttempts::default();
+        let start = Instant::now();
+
+        // PINs tried at once are counted before any of them is verified
+        for _ in 0..attempts.max_failures {
+            assert!(attempts.reserve("hOtB6pOxiL2IQPYs", start).is_ok());
+        }
+        assert!(attempts.reserve("hOtB6pOxiL2IQPYs", start).is_err());
+
+        attempts.succeeded("hOtB6pOxiL2IQPYs");
+        assert!(attempts.reserve("hOtB6pOxiL2IQPYs", start).is_ok());
+    }
+
+    #[test]
+    fn test_pin_policy() {
+        let state = PinState::default();
+        state.apply_policy(&CardPinPolicy {
+            max_failures: 1,
+            lockout_secs: 60,
+            ..CardPinPolicy::default()
+        });
+
+        let mut attempts = state.attempts.lock().unwrap();
+        let start = Instant::now();
+        assert!(attempts.reserve("hOtB6pOxiL2IQPYs", start).is_ok());
+        assert_eq!(
+            attempts.reserve("hOtB6pOxiL2IQPYs", start),
+            Err(Duration::from_secs(60))
+        );
+    }
+}
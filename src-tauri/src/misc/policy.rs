@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::database::schemas::permission_verify::{PermissionAction, PermissionChecker};
+use crate::misc::pin::PinState;
+use crate::misc::token::verify;
+
+/// The clock-in rules of the site, every install of the app is one site.
+///
+/// * `pin_clock_in` - Whether employees who forgot their badge can clock in with their id and PIN.
+/// * `card_pin` - Whether the PIN is also asked after the badge, see `CardPinPolicy`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct SitePolicy {
+    #[serde(default = "enabled")]
+    pub(crate) pin_clock_in: bool,
+    #[serde(default)]
+    pub(crate) card_pin: CardPinPolicy,
+}
+
+/// Asks for the PIN of the employee after the badge, before the punch is saved,
+/// for sites where the badge alone isn't proof enough that the employee is there.
+///
+/// * `enabled` - Whether the PIN is asked.
+/// * `timeout_secs` - How long the employee has to type the PIN after the tap.
+/// * `max_failures` - Wrong PINs in a row before the employee is locked out, after a badge or to clock in with the PIN.
+/// * `lockout_secs` - How long the lockout lasts.
+///
+/// Fields missing from the policy file get their default, so files saved by older versions still load.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CardPinPolicy {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    #[serde(default = "default_timeout")]
+    pub(crate) timeout_secs: u64,
+    #[serde(default = "default_max_failures")]
+    pub(crate) max_failures: u32,
+    #[serde(default = "default_lockout")]
+    pub(crate) lockout_secs: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+fn default_max_failures() -> u32 {
+    3
+}
+
+fn default_lockout() -> u64 {
+    5 * 60
+}
+
+impl Default for CardPinPolicy {
+    fn default() -> Self {
+        CardPinPolicy {
+            enabled: false,
+            timeout_secs: default_timeout(),
+            max_failures: default_max_failures(),
+            lockout_secs: default_lockout(),
+        }
+    }
+}
+
+impl CardPinPolicy {
+    /// Checks the limits, a timeout of 0 expires every PIN right away and 0 failures locks everyone out.
+    fn validate(&self) -> Result<(), String> {
+        if self.timeout_secs == 0 {
+            return Err("The PIN timeout must be at least 1 second".to_string());
+        }
+        if self.max_failures == 0 {
+            return Err("At least 1 wrong PIN must be allowed".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for SitePolicy {
+    fn default() -> Self {
+        SitePolicy {
+            pin_clock_in: enabled(),
+            card_pin: CardPinPolicy::default(),
+        }
+    }
+}
+
+fn get_policy_path() -> Result<PathBuf, String> {
+    let mut policy_path = dirs::config_dir().ok_or("Failed to get config path")?;
+    policy_path.push("PontuAll");
+    policy_path.push("site_policy.json");
+    Ok(policy_path)
+}
+
+/// Loads the policy of the site, the default one if it was never set.
+pub(crate) fn load_policy() -> Result<SitePolicy, String> {
+    let policy_path = get_policy_path()?;
+
+    if !policy_path.exists() {
+        return Ok(SitePolicy::default());
+    }
+
+    let policy_json = std::fs::read_to_string(&policy_path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&policy_json).map_err(|e| e.to_string())
+}
+
+fn save_policy(policy: &SitePolicy) -> Result<(), String> {
+    let policy_path = get_policy_path()?;
+
+    if let Some(parent) = policy_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let policy_json = serde_json::to_string(policy).map_err(|e| e.to_string())?;
+    std::fs::write(policy_path, policy_json).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub(crate) fn get_site_policy() -> Result<SitePolicy, String> {
+    load_policy()
+}
+
+/// Changes the clock-in rules of the site, only administrators can.
+///
+/// # Arguments
+///
+/// * `token` - The login token of the administrator.
+/// * `policy` - The new policy.
+/// * `app` - The app handle, used to check the token and apply the new lockout.
+#[tauri::command]
+pub(crate) async fn set_site_policy(
+    token: String,
+    policy: SitePolicy,
+    app: AppHandle,
+) -> Result<SitePolicy, String> {
+    let admin = verify(token, app.clone()).await?;
+    if !PermissionChecker::check_permission(
+        admin.worker_data.permissions,
+        PermissionAction::Administrator,
+    ) {
+        return Err("Permission denied, Administrator is required".to_string());
+    }
+
+    policy.card_pin.validate()?;
+    save_policy(&policy)?;
+    app.state::<Arc<PinState>>().apply_policy(&policy.card_pin);
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_pin_policy() {
+        // Saved before the lockout could be set
+        let policy: SitePolicy =
+            serde_json::from_str(r#"{"pin_clock_in":true,"card_pin":{"enabled":true}}"#).unwrap();
+        assert!(policy.card_pin.enabled);
+        assert_eq!(policy.card_pin.timeout_secs, default_timeout());
+        assert_eq!(policy.card_pin.max_failures, default_max_failures());
+        assert!(policy.card_pin.validate().is_ok());
+
+        let no_time = CardPinPolicy {
+            timeout_secs: 0,
+            ..CardPinPolicy::default()
+        };
+        assert!(no_time.validate().is_err());
+
+        let no_failures = CardPinPolicy {
+            max_failures: 0,
+            ..CardPinPolicy::default()
+        };
+        assert!(no_failures.validate().is_err());
+    }
+}
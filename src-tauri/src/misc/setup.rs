@@ -3,6 +3,8 @@ use tauri::{AppHandle, Emitter, Manager, State, WebviewUrl, WebviewWindowBuilder
 use tokio::{spawn, task};
 
 
+use crate::acr122u::reader::monitor::monitor_readers;
+use crate::acr122u::watcher::watch_cards;
 use crate::cache::set::get_users_and_cache;
 use crate::database::connect::{create_db_connections, SharedDatabases};
 use crate::database::sync::sync_database;
@@ -20,6 +22,14 @@ async fn setup(app: AppHandle) -> Result<(), ()> {
 
     spawn(check_connection_loop(app.clone()));
 
+    // The card watcher blocks while waiting for taps, so it gets its own thread
+    let watcher_app = app.clone();
+    task::spawn_blocking(move || watch_cards(watcher_app));
+
+    // Same for the reader monitor, which waits on PC/SC for readers to be plugged in or out
+    let monitor_app = app.clone();
+    task::spawn_blocking(move || monitor_readers(monitor_app));
+
     let splash_window = app.get_webview_window("splashscreen").unwrap();
     splash_window.emit("splashscreen:progress", ("database", true))
         .unwrap();
@@ -8,13 +8,36 @@ export default class TauriApi {
 
     public static async Connect() {
         const {reader} = await this.command("connect_reader", {}) as {
-            reader: string
+            reader: string,
+            model: ReaderModel
         }
         return reader;
     }
 
-    public static async ReadCard(blockNumber: number): Promise<string> {
-        return this.command<string>("read_card", {blockNumber});
+    public static async ReadCard(blockNumber: number, role?: string, timeoutMs?: number): Promise<string> {
+        return this.command<string>("read_card", {blockNumber, role, timeoutMs});
+    }
+
+    public static async ReadCardRaw(
+        blockNumber: number,
+        length?: number,
+        encoding?: DataEncoding,
+        role?: string,
+        timeoutMs?: number,
+    ): Promise<string> {
+        return this.command<string>("read_card_raw", {blockNumber, length, encoding, role, timeoutMs});
+    }
+
+    public static async ReadCardUid(role?: string, timeoutMs?: number): Promise<string> {
+        return this.command<string>("read_card_uid", {role, timeoutMs});
+    }
+
+    public static async FindUserByUid(uid: string): Promise<IUsers> {
+        return this.command<IUsers>("find_user_by_uid", {uid});
+    }
+
+    public static async UpdateCardUid(token: string, id: string, uid: string | null): Promise<boolean> {
+        return this.command<boolean>("update_card_uid", {token, id, uid});
     }
 
     public static async CloseReader(): Promise<void> {
@@ -29,8 +52,129 @@ export default class TauriApi {
         return this.command<string>("gen_id", {});
     }
 
-    public static async WriteCard(blockNumber: number, data: string): Promise<boolean> {
-        return this.command<boolean>("write_card", {blockNumber, data});
+    public static async WriteCard(blockNumber: number, data: string, role?: string, timeoutMs?: number): Promise<boolean> {
+        return this.command<boolean>("write_card", {blockNumber, data, role, timeoutMs});
+    }
+
+    public static async WriteCardRaw(
+        blockNumber: number,
+        data: string,
+        encoding?: DataEncoding,
+        role?: string,
+        timeoutMs?: number,
+    ): Promise<boolean> {
+        return this.command<boolean>("write_card_raw", {blockNumber, data, encoding, role, timeoutMs});
+    }
+
+    public static async WriteCardTrailer(
+        sector: number,
+        access: AccessConditions,
+        keyA: string,
+        keyB: string,
+        generalPurpose?: number,
+        role?: string,
+    ): Promise<KeyMapping> {
+        return this.command<KeyMapping>("write_card_trailer", {sector, access, keyA, keyB, generalPurpose, role});
+    }
+
+    public static async WriteNdef(records: NdefRecord[], role?: string): Promise<boolean> {
+        return this.command<boolean>("write_ndef", {records, role});
+    }
+
+    public static async DumpCard(path: string, format?: DumpFormat, role?: string): Promise<CardDump> {
+        return this.command<CardDump>("dump_card", {path, format, role});
+    }
+
+    public static async RestoreCard(path: string, writeTrailers?: boolean, role?: string): Promise<number[]> {
+        return this.command<number[]>("restore_card", {path, writeTrailers, role});
+    }
+
+    public static async IssueBadge(userId: string, token: string, role?: string): Promise<number> {
+        return this.command<number>("issue_badge", {userId, token, role});
+    }
+
+    public static async EnrollCard(userId: string, token: string, role?: string): Promise<CardRecord> {
+        return this.command<CardRecord>("enroll_card", {userId, token, role});
+    }
+
+    public static async RevokeCard(uid: string, reason: string, token: string): Promise<RevokedCard> {
+        return this.command<RevokedCard>("revoke_card", {uid, reason, token});
+    }
+
+    public static async ReinstateCard(uid: string, token: string): Promise<boolean> {
+        return this.command<boolean>("reinstate_card", {uid, token});
+    }
+
+    public static async ListRevokedCards(): Promise<RevokedCard[]> {
+        return this.command<RevokedCard[]>("list_revoked_cards", {});
+    }
+
+    public static async VerifyBadge(role?: string): Promise<string> {
+        return this.command<string>("verify_badge", {role});
+    }
+
+    public static async ReadNdef(role?: string): Promise<NdefRecord[]> {
+        return this.command<NdefRecord[]>("read_ndef", {role});
+    }
+
+    public static async ListReaders(): Promise<ReaderInfo[]> {
+        return this.command<ReaderInfo[]>("list_readers", {});
+    }
+
+    public static async ReaderStatus(): Promise<ReaderStatus> {
+        return this.command<ReaderStatus>("reader_status", {});
+    }
+
+    public static async ReaderDiagnostics(sector?: number, role?: string): Promise<ReaderDiagnostics> {
+        return this.command<ReaderDiagnostics>("reader_diagnostics", {sector, role});
+    }
+
+    public static async GetReaderSettings(): Promise<ReaderSettings> {
+        return this.command<ReaderSettings>("get_reader_settings", {});
+    }
+
+    public static async SelectReader(reader: string | null): Promise<ReaderSettings> {
+        return this.command<ReaderSettings>("select_reader", {reader});
+    }
+
+    public static async SetReaderRole(role: string, reader: string | null): Promise<ReaderSettings> {
+        return this.command<ReaderSettings>("set_reader_role", {role, reader});
+    }
+
+    public static async SetFeedbackSettings(feedback: FeedbackSettings): Promise<ReaderSettings> {
+        return this.command<ReaderSettings>("set_feedback_settings", {feedback});
+    }
+
+    public static async SetSourceSettings(sources: SourceSettings): Promise<ReaderSettings> {
+        return this.command<ReaderSettings>("set_source_settings", {sources});
+    }
+
+    public static async SetCardTimeout(timeoutSecs: number): Promise<ReaderSettings> {
+        return this.command<ReaderSettings>("set_card_timeout", {timeoutSecs});
+    }
+
+    public static async SetSectorKey(sector: number | null, keyType: MifareKeyType, key: string): Promise<KeyMapping> {
+        return this.command<KeyMapping>("set_sector_key", {sector, keyType, key});
+    }
+
+    public static async RemoveSectorKey(sector: number | null): Promise<KeyMapping> {
+        return this.command<KeyMapping>("remove_sector_key", {sector});
+    }
+
+    public static async GetKeyMapping(): Promise<KeyMapping> {
+        return this.command<KeyMapping>("get_key_mapping", {});
+    }
+
+    public static async SimulateCard(present: boolean): Promise<void> {
+        return this.command<void>("simulate_card", {present});
+    }
+
+    public static async WedgeInput(keys: string): Promise<void> {
+        return this.command<void>("wedge_input", {keys});
+    }
+
+    public static async SubmitBadgeNumber(number: string): Promise<void> {
+        return this.command<void>("submit_badge_number", {number});
     }
 
     public static async InsertNewUser(
@@ -58,15 +202,41 @@ export default class TauriApi {
         day: string,
         keyToUpdate: "ClockIn" | "ClockLunchOut" | "ClockLunchReturn" | "ClockOut",
         value: string,
+        method?: PunchMethod,
     ) {
         return this.command<boolean>("update_cache_hour_data", {
             id,
             day,
             keyToUpdate,
-            value
+            value,
+            method
         });
     }
 
+    public static async SetUserPin(token: string, id: string, pin: string): Promise<boolean> {
+        return this.command<boolean>("set_user_pin", {token, id, pin});
+    }
+
+    public static async PinClockIn(id: string, pin: string): Promise<string> {
+        return this.command<string>("pin_clock_in", {id, pin});
+    }
+
+    public static async ConfirmCardPin(challenge: string, pin: string): Promise<CardTapEvent> {
+        return this.command<CardTapEvent>("confirm_card_pin", {challenge, pin});
+    }
+
+    public static async CancelCardPin(challenge: string): Promise<void> {
+        return this.command<void>("cancel_card_pin", {challenge});
+    }
+
+    public static async GetSitePolicy(): Promise<SitePolicy> {
+        return this.command<SitePolicy>("get_site_policy", {});
+    }
+
+    public static async SetSitePolicy(token: string, policy: SitePolicy): Promise<SitePolicy> {
+        return this.command<SitePolicy>("set_site_policy", {token, policy});
+    }
+
     public static async UpdateCache() {
         return this.command<void>("get_users_and_cache", {});
     }
@@ -1,10 +1,15 @@
 declare global {
+    type PunchMethod = "Nfc" | "Wedge" | "Manual" | "Pin"
+
     type HourData = {
         clock_in: string,
         lunch_break_out: string,
         lunch_break_return: string,
         clocked_out: string,
-        total_hours: string
+        total_hours: string,
+        methods?: {
+            [key: string]: PunchMethod
+        }
     }
 
     interface IUsers {
@@ -17,7 +22,10 @@ declare global {
             [key: string]: HourData
         }
         status?: string,
-        lunch_time?: string
+        lunch_time?: string,
+        card_uid?: string,
+        card_issued_at?: string,
+        card_serial?: number
     }
 
     interface CachedUsers {
@@ -66,7 +74,20 @@ declare global {
             permissions: {
                 flags: string
             },
-        }
+        },
+        pin_hash?: string
+    }
+
+    type SitePolicy = {
+        pin_clock_in: boolean,
+        card_pin: CardPinPolicy
+    }
+
+    type CardPinPolicy = {
+        enabled: boolean,
+        timeout_secs: number,
+        max_failures: number,
+        lockout_secs: number
     }
 
     type Pages = "home" | "configuration" | "profile" | "about" | "help" | "admin"
@@ -88,6 +109,206 @@ declare global {
         [key: string]: boolean
     }
 
+    type ReaderModel = "Acr122u" | "Acr1252u" | "GenericPcsc" | "Simulated"
+
+    type ReaderInfo = {
+        name: string,
+        supported: boolean,
+        model?: ReaderModel,
+        card_present: boolean,
+        atr?: string,
+        selected: boolean,
+        roles: string[]
+    }
+
+    type ReaderSettings = {
+        selected_reader?: string,
+        roles: {
+            [role: string]: string
+        },
+        feedback: FeedbackSettings,
+        sources: SourceSettings,
+        card_timeout_secs: number
+    }
+
+    type LedColor = "Green" | "Red" | "Orange"
+
+    type FeedbackPattern = {
+        led: LedColor,
+        on_ms: number,
+        off_ms: number,
+        repetitions: number,
+        beep: boolean
+    }
+
+    type FeedbackSettings = {
+        enabled: boolean,
+        accepted: FeedbackPattern,
+        rejected: FeedbackPattern,
+        duplicate: FeedbackPattern,
+        pending_pin: FeedbackPattern
+    }
+
+    type SourceKind = "Nfc" | "Wedge" | "Manual"
+
+    type NumberFormat = "Hex" | "Decimal" | "DecimalReversed"
+
+    type SourceSettings = {
+        wedge: boolean,
+        manual: boolean,
+        format: NumberFormat,
+        max_key_interval_ms: number
+    }
+
+    type MifareKeyType = "A" | "B"
+
+    type KeyMapping = {
+        default: MifareKeyType,
+        custom_default: boolean,
+        sectors: {
+            [sector: number]: MifareKeyType
+        }
+    }
+
+    type DataBlockAccess =
+        "ReadWriteAB"
+        | "ReadOnlyAB"
+        | "ReadABWriteB"
+        | "ValueWriteB"
+        | "ValueDecrementOnly"
+        | "ReadWriteB"
+        | "ReadOnlyB"
+        | "Never"
+
+    type TrailerAccess =
+        "KeyAWritesKeys"
+        | "KeyAReadOnly"
+        | "KeyBWritesKeys"
+        | "Frozen"
+        | "Transport"
+        | "KeyBManaged"
+        | "KeyBWritesAccessBits"
+        | "Locked"
+
+    type AccessConditions = {
+        blocks: [DataBlockAccess, DataBlockAccess, DataBlockAccess],
+        trailer: TrailerAccess
+    }
+
+    type SectorKey = {
+        key_type: MifareKeyType,
+        key: number[]
+    }
+
+    type CardType =
+        "MifareClassic1K"
+        | "MifareClassic4K"
+        | "MifareUltralight"
+        | "Ntag213"
+        | "Ntag215"
+        | "Ntag216"
+
+    type DumpFormat = "Json" | "Binary"
+
+    type DataEncoding = "Hex" | "Base64"
+
+    type SectorDump = {
+        sector: number,
+        key?: SectorKey,
+        blocks: (string | null)[]
+    }
+
+    type CardDump = {
+        uid: string,
+        atr: string,
+        card_type: CardType,
+        dumped_at: string,
+        sectors: SectorDump[]
+    }
+
+    type NdefRecord =
+        { type: "Text", language: string, text: string }
+        | { type: "Uri", uri: string }
+
+    type CardTapEvent = {
+        uid: string,
+        user_id?: string,
+        payload?: string,
+        migrated: boolean,
+        duplicate: boolean,
+        source: SourceKind,
+        reader: string,
+        timestamp: string
+    }
+
+    type CardRejectedEvent = {
+        uid: string,
+        reason: string,
+        revoked: boolean,
+        source: SourceKind,
+        reader: string,
+        timestamp: string
+    }
+
+    type PinRequiredEvent = {
+        challenge: string,
+        user_id: string,
+        timeout_secs: number,
+        tap: CardTapEvent
+    }
+
+    type RevokedCard = {
+        uid: string,
+        reason: string,
+        revoked_by: string,
+        revoked_at: string
+    }
+
+    type CardRecord = {
+        uid?: string,
+        issued_at?: string,
+        serial?: number
+    }
+
+    type ReaderStatus = {
+        connected: boolean,
+        reader?: string,
+        model?: ReaderModel,
+        readers: string[]
+    }
+
+    type ReaderEvent = {
+        reader: string,
+        model?: ReaderModel,
+        active: boolean,
+        timestamp: string
+    }
+
+    type AuthenticationTest = {
+        sector: number,
+        key_type: MifareKeyType,
+        success: boolean,
+        error?: string
+    }
+
+    type CardDiagnostics = {
+        atr: string,
+        protocol?: string,
+        card_type?: CardType,
+        uid?: string,
+        authentication?: AuthenticationTest
+    }
+
+    type ReaderDiagnostics = {
+        reader: string,
+        model: ReaderModel,
+        firmware?: string,
+        state: string[],
+        card?: CardDiagnostics,
+        errors: string[],
+        collected_at: string
+    }
+
     type IDialogMessage = {
         message: string,
         type: string,